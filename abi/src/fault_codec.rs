@@ -0,0 +1,966 @@
+//! The messages of the fault protocol.
+//!
+//! A thread that faults is blocked and its fault is sent to its fault handler as a message
+//! labelled with the `seL4_Fault_tag` of the fault. Every fault has a typed message here, with
+//! its message register layout as associated constants and symmetric `encode`/`decode` over a
+//! slice of message registers. The reply to a fault is a `FaultReply` the same way. The kernel
+//! encodes the messages in `Transfer::do_fault_transfer` and decodes the replies in
+//! `Transfer::do_fault_reply_transfer`, a user-level pager or supervisor decodes and encodes
+//! them with the same definitions, which only need `core`. The fault tags and register counts
+//! are spelled out here and checked against `sel4_common` by the kernel.
+//!
+//! The registers of `UnknownSyscall` and `UserException` are listed per architecture, the
+//! kernel copies them with `copy_syscall_fault_mrs` and `copy_exeception_fault_mrs` and
+//! encodes the rest of the message here. Likewise the lookup failure of a `CapFault` is
+//! written from the kernel's `lookup_fault` by `set_lookup_fault_mrs`, in the layout of
+//! `LookupFailure`.
+use crate::MessageInfo;
+
+/// The `seL4_Fault_tag`s, the architecture faults follow the optional ones
+const FAULT_CAP_FAULT: u64 = 1;
+const FAULT_UNKNOWN_SYSCALL: u64 = 2;
+const FAULT_USER_EXCEPTION: u64 = 3;
+#[cfg(feature = "hardware_debug_api")]
+const FAULT_DEBUG_EXCEPTION: u64 = 4;
+#[cfg(feature = "kernel_mcs")]
+const FAULT_TIMEOUT: u64 = 5;
+#[cfg(not(feature = "kernel_mcs"))]
+const FAULT_VM_FAULT: u64 = 5;
+#[cfg(feature = "kernel_mcs")]
+const FAULT_VM_FAULT: u64 = 6;
+#[cfg(feature = "arm_hypervisor_support")]
+const FAULT_VGIC_MAINTENANCE: u64 = FAULT_VM_FAULT + 1;
+#[cfg(feature = "arm_hypervisor_support")]
+const FAULT_VCPU_FAULT: u64 = FAULT_VM_FAULT + 2;
+#[cfg(feature = "arm_hypervisor_support")]
+const FAULT_VPPI_EVENT: u64 = FAULT_VM_FAULT + 3;
+
+/// The register sets a fault reply is copied into, `MESSAGE_ID_*` of the kernel
+pub const MESSAGE_ID_SYSCALL: usize = 0;
+pub const MESSAGE_ID_EXCEPTION: usize = 1;
+#[cfg(feature = "kernel_mcs")]
+pub const MESSAGE_ID_TIMEOUT_REPLY: usize = 2;
+
+/// Registers of the `UnknownSyscall` message, `n_syscallMessage`
+#[cfg(target_arch = "aarch64")]
+pub const N_SYSCALL_MESSAGE: usize = 12;
+/// Registers of the `UserException` message, `n_exceptionMessage`
+#[cfg(target_arch = "aarch64")]
+pub const N_EXCEPTON_MESSAGE: usize = 3;
+/// Registers of the `Timeout` reply, `n_timeoutMessage`
+#[cfg(all(feature = "kernel_mcs", target_arch = "aarch64"))]
+pub const N_TIMEOUT_MESSAGE: usize = 34;
+// Other targets, the host among them, use the layout of riscv64.
+/// Registers of the `UnknownSyscall` message, `n_syscallMessage`
+#[cfg(not(target_arch = "aarch64"))]
+pub const N_SYSCALL_MESSAGE: usize = 10;
+/// Registers of the `UserException` message, `n_exceptionMessage`
+#[cfg(not(target_arch = "aarch64"))]
+pub const N_EXCEPTON_MESSAGE: usize = 2;
+/// Registers of the `Timeout` reply, `n_timeoutMessage`
+#[cfg(all(feature = "kernel_mcs", not(target_arch = "aarch64")))]
+pub const N_TIMEOUT_MESSAGE: usize = 32;
+
+/// Longest fault message
+pub const MAX_FAULT_MESSAGE: usize = max(
+    CapFault::MAX_LENGTH,
+    max(
+        UnknownSyscall::MAX_LENGTH,
+        max(UserException::MAX_LENGTH, VmFault::MAX_LENGTH),
+    ),
+);
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// A fault message with a fixed label
+pub trait FaultCodec: Sized {
+    /// The `seL4_Fault_tag` of the fault, which is the label of the message
+    const LABEL: u64;
+    /// Longest message of the fault
+    const MAX_LENGTH: usize;
+
+    /// Write the message to `mrs`, which must hold `MAX_LENGTH` registers. Returns the length
+    /// of the message.
+    fn encode(&self, mrs: &mut [usize]) -> usize;
+
+    /// Read a message of exactly `mrs.len()` registers, `None` if it is not a well-formed
+    /// message of the fault
+    fn decode(mrs: &[usize]) -> Option<Self>;
+}
+
+#[inline]
+fn decode_bool(word: usize) -> Option<bool> {
+    match word {
+        0 => Some(false),
+        1 => Some(true),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// Why a capability lookup failed, in the order of `lookup_fault_tag`
+pub enum LookupFailure {
+    InvalidRoot,
+    MissingCapability {
+        bits_left: usize,
+    },
+    DepthMismatch {
+        bits_left: usize,
+        bits_found: usize,
+    },
+    GuardMismatch {
+        bits_left: usize,
+        guard_found: usize,
+        bits_found: usize,
+    },
+}
+
+impl LookupFailure {
+    /// The type of the failure, `lookup_fault_tag` plus one
+    pub const TYPE: usize = 0;
+    pub const BITS_LEFT: usize = 1;
+    /// `bits_found` of a depth mismatch, `guard_found` of a guard mismatch
+    pub const DEPTH_MISMATCH_BITS_FOUND: usize = 2;
+    pub const GUARD_MISMATCH_GUARD_FOUND: usize = 2;
+    pub const GUARD_MISMATCH_BITS_FOUND: usize = 3;
+    pub const MAX_LENGTH: usize = 4;
+
+    /// Write the failure to `mrs`, which must hold `MAX_LENGTH` registers. Returns the length.
+    pub fn encode(&self, mrs: &mut [usize]) -> usize {
+        match *self {
+            LookupFailure::InvalidRoot => {
+                mrs[Self::TYPE] = 1;
+                1
+            }
+            LookupFailure::MissingCapability { bits_left } => {
+                mrs[Self::TYPE] = 2;
+                mrs[Self::BITS_LEFT] = bits_left;
+                2
+            }
+            LookupFailure::DepthMismatch {
+                bits_left,
+                bits_found,
+            } => {
+                mrs[Self::TYPE] = 3;
+                mrs[Self::BITS_LEFT] = bits_left;
+                mrs[Self::DEPTH_MISMATCH_BITS_FOUND] = bits_found;
+                3
+            }
+            LookupFailure::GuardMismatch {
+                bits_left,
+                guard_found,
+                bits_found,
+            } => {
+                mrs[Self::TYPE] = 4;
+                mrs[Self::BITS_LEFT] = bits_left;
+                mrs[Self::GUARD_MISMATCH_GUARD_FOUND] = guard_found;
+                mrs[Self::GUARD_MISMATCH_BITS_FOUND] = bits_found;
+                4
+            }
+        }
+    }
+
+    /// Read a failure of exactly `mrs.len()` registers
+    pub fn decode(mrs: &[usize]) -> Option<Self> {
+        match (mrs.first()?, mrs.len()) {
+            (1, 1) => Some(LookupFailure::InvalidRoot),
+            (2, 2) => Some(LookupFailure::MissingCapability {
+                bits_left: mrs[Self::BITS_LEFT],
+            }),
+            (3, 3) => Some(LookupFailure::DepthMismatch {
+                bits_left: mrs[Self::BITS_LEFT],
+                bits_found: mrs[Self::DEPTH_MISMATCH_BITS_FOUND],
+            }),
+            (4, 4) => Some(LookupFailure::GuardMismatch {
+                bits_left: mrs[Self::BITS_LEFT],
+                guard_found: mrs[Self::GUARD_MISMATCH_GUARD_FOUND],
+                bits_found: mrs[Self::GUARD_MISMATCH_BITS_FOUND],
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A capability could not be looked up
+pub struct CapFault {
+    pub ip: usize,
+    /// The cap address that failed
+    pub addr: usize,
+    /// The lookup was for the receive phase of a call
+    pub in_recv_phase: bool,
+    pub lookup_failure: LookupFailure,
+}
+
+impl CapFault {
+    pub const IP: usize = 0;
+    pub const ADDR: usize = 1;
+    pub const IN_RECV_PHASE: usize = 2;
+    /// Start of the `LookupFailure`
+    pub const LOOKUP_FAILURE_TYPE: usize = 3;
+}
+
+impl FaultCodec for CapFault {
+    const LABEL: u64 = FAULT_CAP_FAULT;
+    const MAX_LENGTH: usize = Self::LOOKUP_FAILURE_TYPE + LookupFailure::MAX_LENGTH;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::IP] = self.ip;
+        mrs[Self::ADDR] = self.addr;
+        mrs[Self::IN_RECV_PHASE] = self.in_recv_phase as usize;
+        Self::LOOKUP_FAILURE_TYPE
+            + self
+                .lookup_failure
+                .encode(&mut mrs[Self::LOOKUP_FAILURE_TYPE..])
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() <= Self::LOOKUP_FAILURE_TYPE {
+            return None;
+        }
+        Some(CapFault {
+            ip: mrs[Self::IP],
+            addr: mrs[Self::ADDR],
+            in_recv_phase: decode_bool(mrs[Self::IN_RECV_PHASE])?,
+            lookup_failure: LookupFailure::decode(&mrs[Self::LOOKUP_FAILURE_TYPE..])?,
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A syscall number the kernel does not know
+pub struct UnknownSyscall {
+    /// The registers of the thread, in the order of the architecture
+    pub regs: [usize; N_SYSCALL_MESSAGE],
+    pub syscall: usize,
+}
+
+impl UnknownSyscall {
+    pub const SYSCALL: usize = N_SYSCALL_MESSAGE;
+}
+
+impl FaultCodec for UnknownSyscall {
+    const LABEL: u64 = FAULT_UNKNOWN_SYSCALL;
+    const MAX_LENGTH: usize = Self::SYSCALL + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[..N_SYSCALL_MESSAGE].copy_from_slice(&self.regs);
+        mrs[Self::SYSCALL] = self.syscall;
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() != Self::MAX_LENGTH {
+            return None;
+        }
+        let mut regs = [0; N_SYSCALL_MESSAGE];
+        regs.copy_from_slice(&mrs[..N_SYSCALL_MESSAGE]);
+        Some(UnknownSyscall {
+            regs,
+            syscall: mrs[Self::SYSCALL],
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// An exception raised by the processor that is not a memory fault
+pub struct UserException {
+    /// The registers of the thread, in the order of the architecture
+    pub regs: [usize; N_EXCEPTON_MESSAGE],
+    pub number: usize,
+    pub code: usize,
+}
+
+impl UserException {
+    pub const NUMBER: usize = N_EXCEPTON_MESSAGE;
+    pub const CODE: usize = N_EXCEPTON_MESSAGE + 1;
+}
+
+impl FaultCodec for UserException {
+    const LABEL: u64 = FAULT_USER_EXCEPTION;
+    const MAX_LENGTH: usize = Self::CODE + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[..N_EXCEPTON_MESSAGE].copy_from_slice(&self.regs);
+        mrs[Self::NUMBER] = self.number;
+        mrs[Self::CODE] = self.code;
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() != Self::MAX_LENGTH {
+            return None;
+        }
+        let mut regs = [0; N_EXCEPTON_MESSAGE];
+        regs.copy_from_slice(&mrs[..N_EXCEPTON_MESSAGE]);
+        Some(UserException {
+            regs,
+            number: mrs[Self::NUMBER],
+            code: mrs[Self::CODE],
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A memory access the address space does not allow
+pub struct VmFault {
+    pub ip: usize,
+    pub addr: usize,
+    /// The access was an instruction fetch
+    pub prefetch_fault: bool,
+    /// The fault status register of the architecture
+    pub fsr: usize,
+}
+
+impl VmFault {
+    pub const IP: usize = 0;
+    pub const ADDR: usize = 1;
+    pub const PREFETCH_FAULT: usize = 2;
+    pub const FSR: usize = 3;
+}
+
+impl FaultCodec for VmFault {
+    const LABEL: u64 = FAULT_VM_FAULT;
+    const MAX_LENGTH: usize = Self::FSR + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::IP] = self.ip;
+        mrs[Self::ADDR] = self.addr;
+        mrs[Self::PREFETCH_FAULT] = self.prefetch_fault as usize;
+        mrs[Self::FSR] = self.fsr;
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() != Self::MAX_LENGTH {
+            return None;
+        }
+        Some(VmFault {
+            ip: mrs[Self::IP],
+            addr: mrs[Self::ADDR],
+            prefetch_fault: decode_bool(mrs[Self::PREFETCH_FAULT])?,
+            fsr: mrs[Self::FSR],
+        })
+    }
+}
+
+#[cfg(feature = "hardware_debug_api")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// Why a debug exception was raised, in the order of `seL4_BreakpointType`
+pub enum DebugExceptionReason {
+    /// A hardware instruction breakpoint was hit
+    InstructionBreakpoint { breakpoint: usize, address: usize },
+    /// A watchpoint was hit, `address` is the data address that triggered it
+    DataBreakpoint { breakpoint: usize, address: usize },
+    /// The thread finished the instructions it was told to step
+    SingleStep,
+    /// The thread executed a software breakpoint instruction
+    SoftwareBreakRequest,
+}
+
+#[cfg(feature = "hardware_debug_api")]
+impl DebugExceptionReason {
+    pub const INSTRUCTION_BREAKPOINT: usize = 0;
+    pub const DATA_BREAKPOINT: usize = 1;
+    pub const SINGLE_STEP: usize = 2;
+    pub const SOFTWARE_BREAK_REQUEST: usize = 3;
+
+    /// Build the reason from the fields of `seL4_Fault_DebugException`, `None` for an unknown
+    /// reason
+    pub fn new(reason: usize, breakpoint: usize, address: usize) -> Option<Self> {
+        match reason {
+            Self::INSTRUCTION_BREAKPOINT => Some(DebugExceptionReason::InstructionBreakpoint {
+                breakpoint,
+                address,
+            }),
+            Self::DATA_BREAKPOINT => Some(DebugExceptionReason::DataBreakpoint {
+                breakpoint,
+                address,
+            }),
+            Self::SINGLE_STEP => Some(DebugExceptionReason::SingleStep),
+            Self::SOFTWARE_BREAK_REQUEST => Some(DebugExceptionReason::SoftwareBreakRequest),
+            _ => None,
+        }
+    }
+
+    /// The reason as the `seL4_BreakpointType` of the message
+    pub fn raw(&self) -> usize {
+        match self {
+            DebugExceptionReason::InstructionBreakpoint { .. } => Self::INSTRUCTION_BREAKPOINT,
+            DebugExceptionReason::DataBreakpoint { .. } => Self::DATA_BREAKPOINT,
+            DebugExceptionReason::SingleStep => Self::SINGLE_STEP,
+            DebugExceptionReason::SoftwareBreakRequest => Self::SOFTWARE_BREAK_REQUEST,
+        }
+    }
+
+    /// The hardware breakpoint that was hit, if any
+    pub fn breakpoint(&self) -> Option<usize> {
+        match *self {
+            DebugExceptionReason::InstructionBreakpoint { breakpoint, .. }
+            | DebugExceptionReason::DataBreakpoint { breakpoint, .. } => Some(breakpoint),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "hardware_debug_api")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A breakpoint, watchpoint or single step of the hardware debug API. Only hardware
+/// breakpoints carry the trigger address and breakpoint number.
+pub struct DebugException {
+    pub ip: usize,
+    pub reason: DebugExceptionReason,
+}
+
+#[cfg(feature = "hardware_debug_api")]
+impl DebugException {
+    pub const FAULT_IP: usize = 0;
+    pub const EXCEPTION_REASON: usize = 1;
+    pub const TRIGGER_ADDRESS: usize = 2;
+    pub const BREAKPOINT_NUMBER: usize = 3;
+}
+
+#[cfg(feature = "hardware_debug_api")]
+impl FaultCodec for DebugException {
+    const LABEL: u64 = FAULT_DEBUG_EXCEPTION;
+    const MAX_LENGTH: usize = Self::BREAKPOINT_NUMBER + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::FAULT_IP] = self.ip;
+        mrs[Self::EXCEPTION_REASON] = self.reason.raw();
+        match self.reason {
+            DebugExceptionReason::InstructionBreakpoint {
+                breakpoint,
+                address,
+            }
+            | DebugExceptionReason::DataBreakpoint {
+                breakpoint,
+                address,
+            } => {
+                mrs[Self::TRIGGER_ADDRESS] = address;
+                mrs[Self::BREAKPOINT_NUMBER] = breakpoint;
+                Self::MAX_LENGTH
+            }
+            _ => Self::TRIGGER_ADDRESS,
+        }
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() <= Self::EXCEPTION_REASON {
+            return None;
+        }
+        let reason = DebugExceptionReason::new(
+            mrs[Self::EXCEPTION_REASON],
+            mrs.get(Self::BREAKPOINT_NUMBER).copied().unwrap_or(0),
+            mrs.get(Self::TRIGGER_ADDRESS).copied().unwrap_or(0),
+        )?;
+        let length = match reason.breakpoint() {
+            Some(_) => Self::MAX_LENGTH,
+            None => Self::TRIGGER_ADDRESS,
+        };
+        (mrs.len() == length).then_some(DebugException {
+            ip: mrs[Self::FAULT_IP],
+            reason,
+        })
+    }
+}
+
+#[cfg(feature = "hardware_debug_api")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What a debugger asks for in the reply to a `DebugException`. The thread is always
+/// resumed. `Step` after anything but a single step and `ClearBreakpoint` are extensions of
+/// this kernel, a debugger written for seL4 only sends `Resume`.
+pub enum DebugReply {
+    /// Resume the thread, and stop stepping it after a single step
+    Resume,
+    /// Stop the thread again after `instructions` instructions, which must not be 0
+    Step { instructions: usize },
+    /// Disable the hardware breakpoint that was hit and resume the thread
+    ClearBreakpoint,
+}
+
+#[cfg(feature = "hardware_debug_api")]
+impl DebugReply {
+    /// The label seL4 expects, it reads no other
+    pub const RESUME: usize = 0;
+    /// Not in seL4, which steps again only when replying to a single step
+    pub const STEP: usize = 1;
+    /// Not in seL4, which leaves clearing a breakpoint to `TCB_UnsetBreakpoint`
+    pub const CLEAR_BREAKPOINT: usize = 2;
+    /// Message register of the number of instructions to step
+    pub const INSTRUCTIONS: usize = 0;
+
+    /// Write the reply to `mrs`, which must hold one register. Returns the label and the
+    /// length of the reply.
+    pub fn encode(&self, mrs: &mut [usize]) -> (usize, usize) {
+        let (label, instructions) = match *self {
+            DebugReply::Step { instructions } if instructions != 0 => (Self::STEP, instructions),
+            DebugReply::ClearBreakpoint => (Self::CLEAR_BREAKPOINT, 0),
+            _ => (Self::RESUME, 0),
+        };
+        mrs[Self::INSTRUCTIONS] = instructions;
+        (label, 1)
+    }
+
+    /// Read the reply to `fault`, with the `label` and message registers `mrs` of the reply.
+    /// As in seL4, the first message register of any reply to a single step is the number
+    /// of instructions to step next, 0 to stop stepping, whatever the length of the reply.
+    /// Clearing is only possible after a hardware breakpoint.
+    pub fn decode(fault: &DebugException, label: usize, mrs: &[usize]) -> Self {
+        let instructions = mrs.get(Self::INSTRUCTIONS).copied().unwrap_or(0);
+        match label {
+            Self::CLEAR_BREAKPOINT if fault.reason.breakpoint().is_some() => {
+                DebugReply::ClearBreakpoint
+            }
+            _ if instructions != 0
+                && (label == Self::STEP || fault.reason == DebugExceptionReason::SingleStep) =>
+            {
+                DebugReply::Step { instructions }
+            }
+            _ => DebugReply::Resume,
+        }
+    }
+}
+
+#[cfg(feature = "kernel_mcs")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A scheduling context ran out of budget
+pub struct Timeout {
+    /// The badge of the timeout fault handler cap
+    pub data: usize,
+    /// Time consumed since the last report, if the thread has a scheduling context
+    pub consumed: Option<usize>,
+}
+
+#[cfg(feature = "kernel_mcs")]
+impl Timeout {
+    pub const DATA: usize = 0;
+    pub const CONSUMED: usize = 1;
+}
+
+#[cfg(feature = "kernel_mcs")]
+impl FaultCodec for Timeout {
+    const LABEL: u64 = FAULT_TIMEOUT;
+    const MAX_LENGTH: usize = Self::CONSUMED + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::DATA] = self.data;
+        match self.consumed {
+            Some(consumed) => {
+                mrs[Self::CONSUMED] = consumed;
+                Self::MAX_LENGTH
+            }
+            None => Self::CONSUMED,
+        }
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        match mrs.len() {
+            1 | 2 => Some(Timeout {
+                data: mrs[Self::DATA],
+                consumed: mrs.get(Self::CONSUMED).copied(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A maintenance interrupt of the virtual GIC of a VCPU, e.g. an EOI of a level-triggered
+/// virtual interrupt
+pub struct VgicMaintenance {
+    /// The list register that raised it, `None` if the hardware did not say
+    pub idx: Option<usize>,
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl VgicMaintenance {
+    pub const IDX: usize = 0;
+    /// `IDX` of a maintenance interrupt without a valid list register
+    pub const NO_IDX: usize = usize::MAX;
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl FaultCodec for VgicMaintenance {
+    const LABEL: u64 = FAULT_VGIC_MAINTENANCE;
+    const MAX_LENGTH: usize = Self::IDX + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::IDX] = self.idx.unwrap_or(Self::NO_IDX);
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        match *mrs {
+            [idx] => Some(VgicMaintenance {
+                idx: (idx != Self::NO_IDX).then_some(idx),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A trap of the guest running on a VCPU that the kernel does not handle itself
+pub struct VcpuFault {
+    /// The hypervisor syndrome register of the trap
+    pub hsr: usize,
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl VcpuFault {
+    pub const HSR: usize = 0;
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl FaultCodec for VcpuFault {
+    const LABEL: u64 = FAULT_VCPU_FAULT;
+    const MAX_LENGTH: usize = Self::HSR + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::HSR] = self.hsr;
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        match *mrs {
+            [hsr] => Some(VcpuFault { hsr }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A private peripheral interrupt of a VCPU, e.g. its virtual timer, that the VMM must
+/// inject. The interrupt stays masked until the VMM acknowledges it on the VCPU.
+pub struct VppiEvent {
+    pub irq: usize,
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl VppiEvent {
+    pub const IRQ: usize = 0;
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+impl FaultCodec for VppiEvent {
+    const LABEL: u64 = FAULT_VPPI_EVENT;
+    const MAX_LENGTH: usize = Self::IRQ + 1;
+
+    fn encode(&self, mrs: &mut [usize]) -> usize {
+        mrs[Self::IRQ] = self.irq;
+        Self::MAX_LENGTH
+    }
+
+    fn decode(mrs: &[usize]) -> Option<Self> {
+        match *mrs {
+            [irq] => Some(VppiEvent { irq }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A fault message of any fault
+pub enum FaultMessage {
+    CapFault(CapFault),
+    UnknownSyscall(UnknownSyscall),
+    UserException(UserException),
+    VmFault(VmFault),
+    #[cfg(feature = "kernel_mcs")]
+    Timeout(Timeout),
+    #[cfg(feature = "hardware_debug_api")]
+    DebugException(DebugException),
+    #[cfg(feature = "arm_hypervisor_support")]
+    VgicMaintenance(VgicMaintenance),
+    #[cfg(feature = "arm_hypervisor_support")]
+    VcpuFault(VcpuFault),
+    #[cfg(feature = "arm_hypervisor_support")]
+    VppiEvent(VppiEvent),
+}
+
+impl FaultMessage {
+    /// The label of the message
+    pub fn label(&self) -> u64 {
+        match self {
+            FaultMessage::CapFault(_) => CapFault::LABEL,
+            FaultMessage::UnknownSyscall(_) => UnknownSyscall::LABEL,
+            FaultMessage::UserException(_) => UserException::LABEL,
+            FaultMessage::VmFault(_) => VmFault::LABEL,
+            #[cfg(feature = "kernel_mcs")]
+            FaultMessage::Timeout(_) => Timeout::LABEL,
+            #[cfg(feature = "hardware_debug_api")]
+            FaultMessage::DebugException(_) => DebugException::LABEL,
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VgicMaintenance(_) => VgicMaintenance::LABEL,
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VcpuFault(_) => VcpuFault::LABEL,
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VppiEvent(_) => VppiEvent::LABEL,
+        }
+    }
+
+    /// Write the message to `mrs`, which must hold `MAX_FAULT_MESSAGE` registers. Returns the
+    /// length of the message.
+    pub fn encode(&self, mrs: &mut [usize]) -> usize {
+        match self {
+            FaultMessage::CapFault(fault) => fault.encode(mrs),
+            FaultMessage::UnknownSyscall(fault) => fault.encode(mrs),
+            FaultMessage::UserException(fault) => fault.encode(mrs),
+            FaultMessage::VmFault(fault) => fault.encode(mrs),
+            #[cfg(feature = "kernel_mcs")]
+            FaultMessage::Timeout(fault) => fault.encode(mrs),
+            #[cfg(feature = "hardware_debug_api")]
+            FaultMessage::DebugException(fault) => fault.encode(mrs),
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VgicMaintenance(fault) => fault.encode(mrs),
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VcpuFault(fault) => fault.encode(mrs),
+            #[cfg(feature = "arm_hypervisor_support")]
+            FaultMessage::VppiEvent(fault) => fault.encode(mrs),
+        }
+    }
+
+    /// Read the message with `label` and the message registers `mrs`, `None` if it is not a
+    /// well-formed fault message
+    pub fn decode(label: u64, mrs: &[usize]) -> Option<Self> {
+        match label {
+            CapFault::LABEL => CapFault::decode(mrs).map(FaultMessage::CapFault),
+            UnknownSyscall::LABEL => UnknownSyscall::decode(mrs).map(FaultMessage::UnknownSyscall),
+            UserException::LABEL => UserException::decode(mrs).map(FaultMessage::UserException),
+            VmFault::LABEL => VmFault::decode(mrs).map(FaultMessage::VmFault),
+            #[cfg(feature = "kernel_mcs")]
+            Timeout::LABEL => Timeout::decode(mrs).map(FaultMessage::Timeout),
+            #[cfg(feature = "hardware_debug_api")]
+            DebugException::LABEL => DebugException::decode(mrs).map(FaultMessage::DebugException),
+            #[cfg(feature = "arm_hypervisor_support")]
+            VgicMaintenance::LABEL => {
+                VgicMaintenance::decode(mrs).map(FaultMessage::VgicMaintenance)
+            }
+            #[cfg(feature = "arm_hypervisor_support")]
+            VcpuFault::LABEL => VcpuFault::decode(mrs).map(FaultMessage::VcpuFault),
+            #[cfg(feature = "arm_hypervisor_support")]
+            VppiEvent::LABEL => VppiEvent::decode(mrs).map(FaultMessage::VppiEvent),
+            _ => None,
+        }
+    }
+}
+
+/// Check if the fault has a message, i.e. `do_fault_transfer` knows how to encode it.
+pub fn is_transferable_fault(fault_tag: u64) -> bool {
+    #[cfg(feature = "kernel_mcs")]
+    if fault_tag == Timeout::LABEL {
+        return true;
+    }
+    #[cfg(feature = "hardware_debug_api")]
+    if fault_tag == DebugException::LABEL {
+        return true;
+    }
+    #[cfg(feature = "arm_hypervisor_support")]
+    if fault_tag == VgicMaintenance::LABEL
+        || fault_tag == VcpuFault::LABEL
+        || fault_tag == VppiEvent::LABEL
+    {
+        return true;
+    }
+    fault_tag == CapFault::LABEL
+        || fault_tag == UnknownSyscall::LABEL
+        || fault_tag == UserException::LABEL
+        || fault_tag == VmFault::LABEL
+}
+
+/// Longest fault reply
+#[cfg(not(feature = "kernel_mcs"))]
+pub const MAX_FAULT_REPLY: usize = max(N_SYSCALL_MESSAGE, N_EXCEPTON_MESSAGE);
+/// Longest fault reply, the reply to a timeout carries the most registers
+#[cfg(feature = "kernel_mcs")]
+pub const MAX_FAULT_REPLY: usize = max(
+    N_TIMEOUT_MESSAGE,
+    max(N_SYSCALL_MESSAGE, N_EXCEPTON_MESSAGE),
+);
+
+/// Get the layout of the reply to a fault, i.e. the `MESSAGE_ID_*` of the register set the
+/// reply is copied into and the maximum number of message registers it may carry.
+/// Faults whose reply carries no registers return `None`.
+pub fn fault_reply_layout(fault_tag: u64) -> Option<(usize, usize)> {
+    if fault_tag == UnknownSyscall::LABEL {
+        Some((MESSAGE_ID_SYSCALL, N_SYSCALL_MESSAGE))
+    } else if fault_tag == UserException::LABEL {
+        Some((MESSAGE_ID_EXCEPTION, N_EXCEPTON_MESSAGE))
+    } else {
+        #[cfg(feature = "kernel_mcs")]
+        if fault_tag == Timeout::LABEL {
+            return Some((MESSAGE_ID_TIMEOUT_REPLY, N_TIMEOUT_MESSAGE));
+        }
+        None
+    }
+}
+
+/// Decide whether the faulting thread is restarted after a fault reply. Faults whose reply
+/// carries registers restart only if the reply label is 0, all others always restart,
+/// including the debug and hypervisor faults.
+pub fn fault_reply_restart(fault_tag: u64, label: usize) -> bool {
+    fault_reply_layout(fault_tag).is_none() || label == 0
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The reply of a fault handler. The registers are written back to the faulting thread in the
+/// order of its fault message, and only restart it if the reply asks for it.
+pub struct FaultReply {
+    pub restart: bool,
+    /// The new values of the first `length` registers
+    pub regs: [usize; MAX_FAULT_REPLY],
+    pub length: usize,
+}
+
+impl FaultReply {
+    /// Write the reply to a fault with `fault_tag` to `mrs`, which must hold `MAX_FAULT_REPLY`
+    /// registers. Returns the label and the length of the reply.
+    pub fn encode(&self, fault_tag: u64, mrs: &mut [usize]) -> (usize, usize) {
+        let max_length = fault_reply_layout(fault_tag).map_or(0, |(_, max_length)| max_length);
+        let length = core::cmp::min(self.length, max_length);
+        mrs[..length].copy_from_slice(&self.regs[..length]);
+        (!self.restart as usize, length)
+    }
+
+    /// Read the reply to a fault with `fault_tag`, with the `label` and message registers
+    /// `mrs` of the reply. Registers past the layout of the fault are ignored.
+    pub fn decode(fault_tag: u64, label: usize, mrs: &[usize]) -> Self {
+        let max_length = fault_reply_layout(fault_tag).map_or(0, |(_, max_length)| max_length);
+        let length = core::cmp::min(mrs.len(), max_length);
+        let mut regs = [0; MAX_FAULT_REPLY];
+        regs[..length].copy_from_slice(&mrs[..length]);
+        FaultReply {
+            restart: fault_reply_restart(fault_tag, label),
+            regs,
+            length,
+        }
+    }
+}
+
+#[cfg(all(feature = "register_replies", target_arch = "aarch64"))]
+/// Registers a `RegisterReply` may write, numbered as in `seL4_UserContext`: the PC, the
+/// stack pointer and the general purpose registers, but not the status or thread-local
+/// registers.
+pub const WRITABLE_REPLY_REGISTERS: &[usize] = &[
+    0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30, 31, 32, 33,
+];
+#[cfg(all(feature = "register_replies", target_arch = "riscv64"))]
+/// Registers a `RegisterReply` may write, numbered as in `seL4_UserContext`: the PC and the
+/// general purpose registers, but not `gp` and `tp`.
+pub const WRITABLE_REPLY_REGISTERS: &[usize] = &[
+    0, 1, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+    27, 28, 29, 30,
+];
+#[cfg(all(
+    feature = "register_replies",
+    not(any(target_arch = "aarch64", target_arch = "riscv64"))
+))]
+/// No registers are writable by a `RegisterReply` on other architectures
+pub const WRITABLE_REPLY_REGISTERS: &[usize] = &[];
+
+#[cfg(feature = "register_replies")]
+/// Most registers a `RegisterReply` writes
+pub const MAX_REGISTER_WRITES: usize = 8;
+
+#[cfg(feature = "register_replies")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+/// A register of the faulting thread and its new value
+pub struct RegisterWrite {
+    /// The register, numbered as in `seL4_UserContext`
+    pub register: usize,
+    pub value: usize,
+}
+
+#[cfg(feature = "register_replies")]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The opt-in reply to a `VmFault` or `CapFault` that sets registers of the faulting thread
+/// before restarting it, e.g. to step the PC over an instruction the pager emulated. The
+/// message registers hold (register, value) pairs, every register must be in
+/// `WRITABLE_REPLY_REGISTERS`.
+pub struct RegisterReply {
+    pub writes: [RegisterWrite; MAX_REGISTER_WRITES],
+    pub count: usize,
+}
+
+#[cfg(feature = "register_replies")]
+impl RegisterReply {
+    /// The label of a register reply, any other label restarts the thread unchanged
+    pub const LABEL: usize = 0x5247;
+    pub const REGISTER: usize = 0;
+    pub const VALUE: usize = 1;
+    pub const MAX_LENGTH: usize = 2 * MAX_REGISTER_WRITES;
+
+    /// Check if the reply to a fault with `fault_tag` may be a register reply
+    pub fn accepts(fault_tag: u64) -> bool {
+        fault_tag == VmFault::LABEL || fault_tag == CapFault::LABEL
+    }
+
+    /// The registers to write
+    pub fn writes(&self) -> &[RegisterWrite] {
+        &self.writes[..self.count]
+    }
+
+    /// Write the reply to `mrs`, which must hold `MAX_LENGTH` registers. Returns the label and
+    /// the length of the reply.
+    pub fn encode(&self, mrs: &mut [usize]) -> (usize, usize) {
+        for (pair, write) in mrs.chunks_exact_mut(2).zip(self.writes()) {
+            pair[Self::REGISTER] = write.register;
+            pair[Self::VALUE] = write.value;
+        }
+        (Self::LABEL, 2 * self.count)
+    }
+
+    /// Read a reply of exactly `mrs.len()` registers, `None` if it is malformed or writes a
+    /// register that is not writable
+    pub fn decode(mrs: &[usize]) -> Option<Self> {
+        if mrs.len() % 2 != 0 || mrs.len() > Self::MAX_LENGTH {
+            return None;
+        }
+        let mut reply = RegisterReply {
+            writes: [RegisterWrite::default(); MAX_REGISTER_WRITES],
+            count: mrs.len() / 2,
+        };
+        for (write, pair) in reply.writes.iter_mut().zip(mrs.chunks_exact(2)) {
+            if !WRITABLE_REPLY_REGISTERS.contains(&pair[Self::REGISTER]) {
+                return None;
+            }
+            *write = RegisterWrite {
+                register: pair[Self::REGISTER],
+                value: pair[Self::VALUE],
+            };
+        }
+        Some(reply)
+    }
+}
+
+/// Decode which registers a fault reply copies back to the faulting thread, clamping the
+/// user-controlled message length to the layout of the fault.
+/// # Arguments
+/// * `fault_tag` - The fault of the thread being replied to
+/// * `info` - The message info of the reply
+pub fn fault_reply_copy(fault_tag: u64, info: MessageInfo) -> Option<(usize, usize)> {
+    fault_reply_layout(fault_tag)
+        .map(|(message_id, max_length)| (message_id, core::cmp::min(info.length(), max_length)))
+}
+
+/// Parse the fault message with `info` and the message registers `mrs`, `None` if it is not a
+/// well-formed fault message
+pub fn fault_message(info: MessageInfo, mrs: &[usize]) -> Option<FaultMessage> {
+    FaultMessage::decode(info.label() as u64, mrs.get(..info.length())?)
+}
+
+/// The message info of `message` sent in `length` message registers
+pub fn fault_message_info(message: &FaultMessage, length: usize) -> MessageInfo {
+    MessageInfo::new(message.label() as usize, length)
+}
@@ -0,0 +1,14 @@
+//! The IPC ABI of `sel4_ipc`, shared by the kernel and user space.
+//!
+//! What a message looks like to the threads exchanging it, the messages of the fault protocol,
+//! and the shared-memory rings two address spaces exchange messages through. The crate only
+//! needs `core`, so user libraries build it for their own targets, and the kernel re-exports
+//! it from `sel4_ipc::abi`, `sel4_ipc::fault_codec` and `sel4_ipc::ipc_ring`.
+#![no_std]
+
+pub mod fault_codec;
+mod message;
+pub mod ring;
+
+pub use fault_codec::{fault_message, fault_message_info, FaultCodec, FaultMessage, FaultReply};
+pub use message::*;
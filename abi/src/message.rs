@@ -0,0 +1,205 @@
+//! What a message looks like to the threads exchanging it: the `seL4_MessageInfo` word with
+//! its label, length, extra caps and unwrapped-cap bitmap, the `caps_or_badges` words of the
+//! IPC buffer, and the badge of a non-blocking receive that found nothing. The kernel builds
+//! messages with these types in `do_normal_transfer` and `set_transfer_caps`, user libraries
+//! parse them with the same ones.
+
+/// Longest message, in message registers, `seL4_MsgMaxLength`
+pub const MSG_MAX_LENGTH: usize = 120;
+/// Most caps a message carries, `seL4_MsgMaxExtraCaps`
+pub const MSG_MAX_EXTRA_CAPS: usize = 3;
+/// The badge of a non-blocking receive that found no message and no signal. A message sent on
+/// an unbadged cap has it too, so it does not prove that nothing was received.
+pub const NO_MESSAGE_BADGE: usize = 0;
+
+/// The fields of the `seL4_MessageInfo` word, from the lowest bit up
+const LENGTH_BITS: usize = 7;
+const EXTRA_CAPS_SHIFT: usize = 7;
+const EXTRA_CAPS_BITS: usize = 2;
+const CAPS_UNWRAPPED_SHIFT: usize = 9;
+const CAPS_UNWRAPPED_BITS: usize = 3;
+const LABEL_SHIFT: usize = 12;
+
+#[inline]
+const fn mask(bits: usize) -> usize {
+    (1 << bits) - 1
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+/// The message info word of a message, in the `seL4_MessageInfo` layout
+pub struct MessageInfo(usize);
+
+impl MessageInfo {
+    /// The info of a message with `label` and `length` message registers, without caps
+    pub fn new(label: usize, length: usize) -> Self {
+        MessageInfo(label << LABEL_SHIFT).with_length(length)
+    }
+
+    /// Read the info word of a message
+    pub fn from_word(word: usize) -> Self {
+        MessageInfo(word)
+    }
+
+    /// Read the info word a user thread sent, with the length and extra caps cut to what a
+    /// message can hold
+    pub fn from_word_security(word: usize) -> Self {
+        let info = MessageInfo(word);
+        info.with_length(info.length().min(MSG_MAX_LENGTH))
+    }
+
+    /// The info word of the message
+    pub fn word(self) -> usize {
+        self.0
+    }
+
+    #[inline]
+    fn field(self, shift: usize, bits: usize) -> usize {
+        (self.0 >> shift) & mask(bits)
+    }
+
+    #[inline]
+    fn with_field(self, shift: usize, bits: usize, value: usize) -> Self {
+        MessageInfo((self.0 & !(mask(bits) << shift)) | ((value & mask(bits)) << shift))
+    }
+
+    pub fn label(self) -> usize {
+        self.0 >> LABEL_SHIFT
+    }
+
+    /// Number of message registers
+    pub fn length(self) -> usize {
+        self.field(0, LENGTH_BITS)
+    }
+
+    /// Number of caps, sent or received
+    pub fn extra_caps(self) -> usize {
+        self.field(EXTRA_CAPS_SHIFT, EXTRA_CAPS_BITS)
+    }
+
+    /// Which of the received caps were unwrapped to their badges
+    pub fn caps_unwrapped(self) -> UnwrappedCaps {
+        UnwrappedCaps(self.field(CAPS_UNWRAPPED_SHIFT, CAPS_UNWRAPPED_BITS) as u8)
+    }
+
+    /// The same info with `length` message registers
+    pub fn with_length(self, length: usize) -> Self {
+        self.with_field(0, LENGTH_BITS, length)
+    }
+
+    /// The same info with the caps of `caps`, as the receiver sees them
+    pub fn with_received_caps(self, caps: ReceivedCaps) -> Self {
+        self.with_field(EXTRA_CAPS_SHIFT, EXTRA_CAPS_BITS, caps.count)
+            .with_field(
+                CAPS_UNWRAPPED_SHIFT,
+                CAPS_UNWRAPPED_BITS,
+                caps.unwrapped.0 as usize,
+            )
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+/// The `capsUnwrapped` bitmap, bit `i` is set if received cap `i` was unwrapped
+pub struct UnwrappedCaps(u8);
+
+impl UnwrappedCaps {
+    pub const NONE: Self = UnwrappedCaps(0);
+
+    /// Check whether received cap `index` was unwrapped
+    pub fn contains(self, index: usize) -> bool {
+        index < MSG_MAX_EXTRA_CAPS && self.0 & (1 << index) != 0
+    }
+
+    /// The same bitmap with received cap `index` unwrapped
+    pub fn with(self, index: usize) -> Self {
+        debug_assert!(index < MSG_MAX_EXTRA_CAPS);
+        UnwrappedCaps(self.0 | (1 << index))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A cap of a received message
+pub enum ReceivedCap {
+    /// An endpoint cap to the endpoint the message came through, its badge is in
+    /// `caps_or_badges`
+    Unwrapped { badge: usize },
+    /// A cap in the receive slot of the receiver
+    Transferred,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+/// The caps of a received message, built by the kernel as it transfers them and parsed by
+/// the receiver from the message info
+pub struct ReceivedCaps {
+    count: usize,
+    unwrapped: UnwrappedCaps,
+}
+
+impl ReceivedCaps {
+    pub const NONE: Self = ReceivedCaps {
+        count: 0,
+        unwrapped: UnwrappedCaps::NONE,
+    };
+
+    /// The caps of a received message with `info`
+    pub fn of(info: MessageInfo) -> Self {
+        ReceivedCaps {
+            count: info.extra_caps().min(MSG_MAX_EXTRA_CAPS),
+            unwrapped: info.caps_unwrapped(),
+        }
+    }
+
+    /// Number of caps received
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Add an unwrapped cap with `badge`, written to `caps_or_badges` of the receiver, false
+    /// if the message already has `MSG_MAX_EXTRA_CAPS` caps
+    pub fn unwrap(&mut self, caps_or_badges: &mut [usize], badge: usize) -> bool {
+        if self.count >= MSG_MAX_EXTRA_CAPS {
+            return false;
+        }
+        match caps_or_badges.get_mut(self.count) {
+            Some(slot) => {
+                *slot = badge;
+                self.unwrapped = self.unwrapped.with(self.count);
+                self.count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add a cap put in the receive slot. There is one receive slot, so the caller transfers
+    /// at most one cap.
+    pub fn transfer(&mut self) {
+        debug_assert!(self.count < MSG_MAX_EXTRA_CAPS);
+        self.count += 1;
+    }
+
+    /// Get received cap `index`, reading the badges from `caps_or_badges` of the receiver
+    pub fn get(&self, index: usize, caps_or_badges: &[usize]) -> Option<ReceivedCap> {
+        if index >= self.count {
+            return None;
+        }
+        Some(if self.unwrapped.contains(index) {
+            ReceivedCap::Unwrapped {
+                badge: *caps_or_badges.get(index)?,
+            }
+        } else {
+            ReceivedCap::Transferred
+        })
+    }
+
+    /// The received caps in order, reading the badges from `caps_or_badges` of the receiver
+    pub fn iter<'a>(
+        &'a self,
+        caps_or_badges: &'a [usize],
+    ) -> impl Iterator<Item = ReceivedCap> + 'a {
+        (0..self.count).map_while(|index| self.get(index, caps_or_badges))
+    }
+}
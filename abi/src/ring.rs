@@ -0,0 +1,222 @@
+//! Shared-memory IPC rings with notification doorbells.
+//!
+//! A ring channel is a submission ring and a completion ring in frames shared by two address
+//! spaces, each with a notification as its doorbell. The producer of a ring enqueues without a
+//! syscall while the consumer is busy, and signals the doorbell only when `Ring::enqueue` says
+//! the consumer sleeps. A completion ring without a doorbell is polled.
+//!
+//! Each ring is a `RingHeader` followed by a power of two of `RingSlot`s, a slot is a message
+//! info word in the `seL4_MessageInfo` layout and its message words. Rings carry no caps. Both
+//! sides work on the ring with `Ring`, single producer, single consumer. Before the consumer
+//! sleeps on the doorbell it calls `Ring::prepare_wait`, which sets `RING_NEED_DOORBELL` and
+//! checks the ring again, so that a message enqueued meanwhile is never slept through. The
+//! kernel does this for it in `wait_ipc_ring`.
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use crate::MessageInfo;
+
+/// Most slots in a ring
+pub const MAX_RING_ENTRIES: usize = 4096;
+/// Message words in a slot, after the message info
+pub const RING_SLOT_WORDS: usize = 7;
+/// Set in `RingHeader::flags` while the consumer sleeps on the doorbell
+pub const RING_NEED_DOORBELL: usize = 1;
+
+#[repr(C)]
+/// The indices of a ring, at the start of its region. It takes the room of one slot.
+pub struct RingHeader {
+    /// Next slot the consumer takes, only the consumer writes it
+    pub head: AtomicUsize,
+    /// Next slot the producer fills, only the producer writes it
+    pub tail: AtomicUsize,
+    pub flags: AtomicUsize,
+    /// Number of slots, set up by the kernel
+    pub entries: usize,
+    _reserved: [usize; RING_SLOT_WORDS - 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+/// A message in a ring
+pub struct RingSlot {
+    /// The `seL4_MessageInfo` of the message, without caps
+    pub info: usize,
+    pub mrs: [usize; RING_SLOT_WORDS],
+}
+
+const _: () = assert!(size_of::<RingHeader>() == size_of::<RingSlot>());
+
+/// Bytes of the region of a ring with `entries` slots
+pub const fn ring_bytes(entries: usize) -> usize {
+    (entries + 1) * size_of::<RingSlot>()
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// Why a message could not be enqueued
+pub enum RingError {
+    /// The consumer has not taken enough messages yet
+    Full,
+    /// The message is longer than `RING_SLOT_WORDS` or than the words given
+    TooLong,
+    /// The message has extra caps, which rings cannot carry
+    HasCaps,
+}
+
+#[derive(Clone, Copy)]
+/// A message taken from a ring
+pub struct RingEntry {
+    pub info: MessageInfo,
+    mrs: [usize; RING_SLOT_WORDS],
+    length: usize,
+}
+
+impl RingEntry {
+    /// The message words of the entry
+    pub fn words(&self) -> &[usize] {
+        &self.mrs[..self.length]
+    }
+}
+
+/// A view of a ring in shared memory
+pub struct Ring<'a> {
+    header: *const RingHeader,
+    slots: *mut RingSlot,
+    entries: usize,
+    _region: PhantomData<&'a mut [RingSlot]>,
+}
+
+impl<'a> Ring<'a> {
+    /// Set up the ring in `region`, one slot for the header followed by the slots. Returns
+    /// `None` if the number of slots is not a power of two of at most `MAX_RING_ENTRIES`.
+    pub fn init(region: &'a mut [RingSlot]) -> Option<Self> {
+        let entries = region.len().checked_sub(1)?;
+        if !entries.is_power_of_two() || entries > MAX_RING_ENTRIES {
+            return None;
+        }
+        let header = region.as_mut_ptr() as *mut RingHeader;
+        unsafe {
+            header.write(RingHeader {
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+                flags: AtomicUsize::new(0),
+                entries,
+                _reserved: [0; RING_SLOT_WORDS - 3],
+            });
+        }
+        Some(Ring {
+            header,
+            slots: unsafe { region.as_mut_ptr().add(1) },
+            entries,
+            _region: PhantomData,
+        })
+    }
+
+    /// View the ring set up at `base` with `entries` slots.
+    ///
+    /// # Safety
+    /// `base` must be the mapped, slot-aligned region of a ring of `entries` slots, which
+    /// must be a power of two, and stay mapped while the view is used.
+    pub unsafe fn from_raw(base: usize, entries: usize) -> Ring<'static> {
+        Ring {
+            header: base as *const RingHeader,
+            slots: (base as *mut RingSlot).add(1),
+            entries,
+            _region: PhantomData,
+        }
+    }
+
+    #[inline]
+    fn header(&self) -> &RingHeader {
+        unsafe { &*self.header }
+    }
+
+    #[inline]
+    fn slot(&self, index: usize) -> *mut RingSlot {
+        unsafe { self.slots.add(index & (self.entries - 1)) }
+    }
+
+    /// Number of messages in the ring
+    pub fn len(&self) -> usize {
+        let header = self.header();
+        let tail = header.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(header.head.load(Ordering::Acquire))
+            .min(self.entries)
+    }
+
+    /// Check whether the ring has no messages
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Enqueue a message of `info` with the first words of `words`, as the producer. Returns
+    /// whether the consumer sleeps and the doorbell must be signalled.
+    pub fn enqueue(&self, info: MessageInfo, words: &[usize]) -> Result<bool, RingError> {
+        let length = info.length();
+        if info.extra_caps() != 0 {
+            return Err(RingError::HasCaps);
+        }
+        if length > RING_SLOT_WORDS || length > words.len() {
+            return Err(RingError::TooLong);
+        }
+        let header = self.header();
+        let tail = header.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(header.head.load(Ordering::Acquire)) >= self.entries {
+            return Err(RingError::Full);
+        }
+        let mut slot = RingSlot {
+            info: info.word(),
+            mrs: [0; RING_SLOT_WORDS],
+        };
+        slot.mrs[..length].copy_from_slice(&words[..length]);
+        unsafe { self.slot(tail).write_volatile(slot) };
+        header.tail.store(tail.wrapping_add(1), Ordering::Release);
+        // Pairs with the fence in `prepare_wait`, either the consumer sees the message or we
+        // see that it sleeps.
+        fence(Ordering::SeqCst);
+        Ok(header.flags.load(Ordering::Relaxed) & RING_NEED_DOORBELL != 0)
+    }
+
+    /// Take the oldest message, as the consumer
+    pub fn dequeue(&self) -> Option<RingEntry> {
+        let header = self.header();
+        let head = header.head.load(Ordering::Relaxed);
+        if head == header.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // The consumer runs, the producer need not ring until it sleeps again.
+        if header.flags.load(Ordering::Relaxed) & RING_NEED_DOORBELL != 0 {
+            self.cancel_wait();
+        }
+        let slot = unsafe { self.slot(head).read_volatile() };
+        header.head.store(head.wrapping_add(1), Ordering::Release);
+        // The producer may have written anything, sanitise the info like a message from a
+        // user thread.
+        let info = MessageInfo::from_word_security(slot.info);
+        Some(RingEntry {
+            info,
+            mrs: slot.mrs,
+            length: info.length().min(RING_SLOT_WORDS),
+        })
+    }
+
+    /// Ask the producer to ring the doorbell, as the consumer about to sleep on it. Returns
+    /// whether the ring is still empty, so that the consumer may sleep. Otherwise the
+    /// consumer takes the messages, or calls `cancel_wait` if it does not sleep after all.
+    pub fn prepare_wait(&self) -> bool {
+        self.header()
+            .flags
+            .fetch_or(RING_NEED_DOORBELL, Ordering::Relaxed);
+        // Pairs with the fence in `enqueue`.
+        fence(Ordering::SeqCst);
+        self.is_empty()
+    }
+
+    /// Tell the producer that the consumer does not sleep on the doorbell
+    pub fn cancel_wait(&self) {
+        self.header()
+            .flags
+            .fetch_and(!RING_NEED_DOORBELL, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,258 @@
+//! Round trips of the fault messages and replies. Each feature adds the faults it enables, run
+//! with `--all-features` to cover every variant.
+use sel4_ipc_abi::fault_codec::*;
+use sel4_ipc_abi::{fault_message, fault_message_info, FaultCodec, FaultMessage, FaultReply};
+
+/// Encode `message`, check that it decodes to itself and that the message without its last
+/// register does not.
+fn round_trip(message: FaultMessage) {
+    let mut mrs = [0; MAX_FAULT_MESSAGE];
+    let length = message.encode(&mut mrs);
+    let info = fault_message_info(&message, length);
+    assert_eq!(info.label() as u64, message.label());
+    assert_eq!(fault_message(info, &mrs), Some(message));
+    assert_ne!(
+        FaultMessage::decode(message.label(), &mrs[..length - 1]),
+        Some(message)
+    );
+}
+
+/// Registers counting up from 1, so that a shifted copy is noticed
+fn regs<const N: usize>() -> [usize; N] {
+    core::array::from_fn(|i| i + 1)
+}
+
+#[test]
+fn cap_fault() {
+    let lookup_failures = [
+        LookupFailure::InvalidRoot,
+        LookupFailure::MissingCapability { bits_left: 12 },
+        LookupFailure::DepthMismatch {
+            bits_left: 12,
+            bits_found: 8,
+        },
+        LookupFailure::GuardMismatch {
+            bits_left: 12,
+            guard_found: 0x5a,
+            bits_found: 8,
+        },
+    ];
+    for lookup_failure in lookup_failures {
+        for in_recv_phase in [false, true] {
+            round_trip(FaultMessage::CapFault(CapFault {
+                ip: 0x1000,
+                addr: 0x2a,
+                in_recv_phase,
+                lookup_failure,
+            }));
+        }
+    }
+}
+
+#[test]
+fn unknown_syscall() {
+    round_trip(FaultMessage::UnknownSyscall(UnknownSyscall {
+        regs: regs(),
+        syscall: usize::MAX,
+    }));
+}
+
+#[test]
+fn user_exception() {
+    round_trip(FaultMessage::UserException(UserException {
+        regs: regs(),
+        number: 2,
+        code: 7,
+    }));
+}
+
+#[test]
+fn vm_fault() {
+    for prefetch_fault in [false, true] {
+        round_trip(FaultMessage::VmFault(VmFault {
+            ip: 0x1000,
+            addr: 0xdead_b000,
+            prefetch_fault,
+            fsr: 0xf,
+        }));
+    }
+}
+
+/// A boolean register that is neither 0 nor 1 is not a message of the fault.
+#[test]
+fn malformed_bool() {
+    let mut mrs = [0; MAX_FAULT_MESSAGE];
+    let message = FaultMessage::VmFault(VmFault {
+        ip: 0x1000,
+        addr: 0x2000,
+        prefetch_fault: true,
+        fsr: 0,
+    });
+    let length = message.encode(&mut mrs);
+    mrs[VmFault::PREFETCH_FAULT] = 2;
+    assert_eq!(FaultMessage::decode(message.label(), &mrs[..length]), None);
+}
+
+#[cfg(feature = "kernel_mcs")]
+#[test]
+fn timeout() {
+    for consumed in [None, Some(1_000)] {
+        round_trip(FaultMessage::Timeout(Timeout { data: 3, consumed }));
+    }
+}
+
+#[cfg(feature = "hardware_debug_api")]
+#[test]
+fn debug_exception() {
+    let reasons = [
+        DebugExceptionReason::InstructionBreakpoint {
+            breakpoint: 1,
+            address: 0x1000,
+        },
+        DebugExceptionReason::DataBreakpoint {
+            breakpoint: 2,
+            address: 0x2000,
+        },
+        DebugExceptionReason::SingleStep,
+        DebugExceptionReason::SoftwareBreakRequest,
+    ];
+    for reason in reasons {
+        round_trip(FaultMessage::DebugException(DebugException {
+            ip: 0x1000,
+            reason,
+        }));
+    }
+    // An unknown reason is not a debug exception.
+    let mrs = [0x1000, DebugExceptionReason::SOFTWARE_BREAK_REQUEST + 1];
+    assert_eq!(DebugException::decode(&mrs), None);
+}
+
+#[cfg(feature = "hardware_debug_api")]
+#[test]
+fn debug_reply() {
+    let fault = DebugException {
+        ip: 0x1000,
+        reason: DebugExceptionReason::InstructionBreakpoint {
+            breakpoint: 1,
+            address: 0x1000,
+        },
+    };
+    let replies = [
+        DebugReply::Resume,
+        DebugReply::Step { instructions: 4 },
+        DebugReply::ClearBreakpoint,
+    ];
+    for reply in replies {
+        let mut mrs = [0; 1];
+        let (label, length) = reply.encode(&mut mrs);
+        assert_eq!(DebugReply::decode(&fault, label, &mrs[..length]), reply);
+    }
+}
+
+#[cfg(feature = "arm_hypervisor_support")]
+#[test]
+fn hypervisor_faults() {
+    for idx in [None, Some(3)] {
+        round_trip(FaultMessage::VgicMaintenance(VgicMaintenance { idx }));
+    }
+    round_trip(FaultMessage::VcpuFault(VcpuFault { hsr: 0x8000_0000 }));
+    round_trip(FaultMessage::VppiEvent(VppiEvent { irq: 27 }));
+}
+
+/// Faults whose reply carries registers, with the longest reply of each
+fn register_faults() -> Vec<u64> {
+    #[allow(unused_mut)]
+    let mut faults = vec![UnknownSyscall::LABEL, UserException::LABEL];
+    #[cfg(feature = "kernel_mcs")]
+    faults.push(Timeout::LABEL);
+    faults
+}
+
+#[test]
+fn fault_reply_with_registers() {
+    for fault_tag in register_faults() {
+        let (_, max_length) = fault_reply_layout(fault_tag).unwrap();
+        assert!(max_length <= MAX_FAULT_REPLY);
+        for restart in [false, true] {
+            for length in [0, max_length] {
+                let reply = FaultReply {
+                    restart,
+                    regs: regs(),
+                    length,
+                };
+                let mut mrs = [0; MAX_FAULT_REPLY];
+                let (label, reply_length) = reply.encode(fault_tag, &mut mrs);
+                assert_eq!(reply_length, length);
+                let decoded = FaultReply::decode(fault_tag, label, &mrs[..reply_length]);
+                assert_eq!(decoded.restart, restart);
+                assert_eq!(decoded.regs[..length], reply.regs[..length]);
+                assert_eq!(decoded.length, length);
+            }
+        }
+    }
+}
+
+/// Registers past the layout of the fault are dropped on both sides.
+#[test]
+fn fault_reply_is_clamped() {
+    let mrs: [usize; MAX_FAULT_REPLY + 1] = regs();
+    for fault_tag in register_faults() {
+        let (_, max_length) = fault_reply_layout(fault_tag).unwrap();
+        let decoded = FaultReply::decode(fault_tag, 0, &mrs);
+        assert_eq!(decoded.length, max_length);
+        assert_eq!(decoded.regs[..max_length], mrs[..max_length]);
+        let mut out = [0; MAX_FAULT_REPLY];
+        let reply = FaultReply {
+            length: MAX_FAULT_REPLY,
+            ..decoded
+        };
+        assert_eq!(reply.encode(fault_tag, &mut out).1, max_length);
+    }
+}
+
+/// The reply to any other fault carries no registers and always restarts the thread.
+#[test]
+fn fault_reply_without_registers() {
+    #[allow(unused_mut)]
+    let mut faults = vec![CapFault::LABEL, VmFault::LABEL];
+    #[cfg(feature = "hardware_debug_api")]
+    faults.push(DebugException::LABEL);
+    #[cfg(feature = "arm_hypervisor_support")]
+    faults.extend([VgicMaintenance::LABEL, VcpuFault::LABEL, VppiEvent::LABEL]);
+    for fault_tag in faults {
+        assert_eq!(fault_reply_layout(fault_tag), None);
+        let reply = FaultReply {
+            restart: true,
+            regs: regs(),
+            length: MAX_FAULT_REPLY,
+        };
+        let mut mrs = [0; MAX_FAULT_REPLY];
+        let (label, length) = reply.encode(fault_tag, &mut mrs);
+        assert_eq!((label, length), (0, 0));
+        let decoded = FaultReply::decode(fault_tag, 1, &mrs);
+        assert!(decoded.restart);
+        assert_eq!(decoded.length, 0);
+    }
+}
+
+#[cfg(feature = "register_replies")]
+#[test]
+fn register_reply() {
+    let mut reply = RegisterReply {
+        writes: [RegisterWrite::default(); MAX_REGISTER_WRITES],
+        count: 0,
+    };
+    for (write, &register) in reply.writes.iter_mut().zip(WRITABLE_REPLY_REGISTERS) {
+        *write = RegisterWrite {
+            register,
+            value: register + 0x100,
+        };
+        reply.count += 1;
+    }
+    let mut mrs = [0; RegisterReply::MAX_LENGTH];
+    let (label, length) = reply.encode(&mut mrs);
+    assert_eq!(label, RegisterReply::LABEL);
+    assert_eq!(RegisterReply::decode(&mrs[..length]), Some(reply));
+    // A register outside the writable set refuses the whole reply.
+    assert_eq!(RegisterReply::decode(&[usize::MAX, 0]), None);
+}
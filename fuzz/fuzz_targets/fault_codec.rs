@@ -0,0 +1,67 @@
+//! Fuzz the fault codec: every fault message and fault reply that decodes must encode back to
+//! the same message registers, so the kernel and a user-level handler agree on the layout.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+#[cfg(feature = "hardware_debug_api")]
+use sel4_ipc::fault_codec::DebugReply;
+#[cfg(feature = "register_replies")]
+use sel4_ipc::fault_codec::{RegisterReply, WRITABLE_REPLY_REGISTERS};
+use sel4_ipc::fault_codec::{
+    fault_reply_layout, FaultMessage, FaultReply, MAX_FAULT_MESSAGE, MAX_FAULT_REPLY,
+};
+
+#[derive(Arbitrary, Debug)]
+struct CodecInput {
+    label: u8,
+    reply_label: usize,
+    mrs: Vec<usize>,
+}
+
+fuzz_target!(|input: CodecInput| {
+    let label = input.label as u64;
+    let mrs = &input.mrs[..input.mrs.len().min(MAX_FAULT_MESSAGE)];
+
+    if let Some(message) = FaultMessage::decode(label, mrs) {
+        assert_eq!(message.label(), label);
+        let mut encoded = [0; MAX_FAULT_MESSAGE];
+        let length = message.encode(&mut encoded);
+        assert_eq!(&encoded[..length], mrs);
+
+        #[cfg(feature = "hardware_debug_api")]
+        if let FaultMessage::DebugException(fault) = message {
+            let reply = DebugReply::decode(&fault, input.reply_label, &input.mrs);
+            if let DebugReply::ClearBreakpoint = reply {
+                assert!(fault.reason.breakpoint().is_some());
+            }
+            let mut encoded = [0; 1];
+            let (reply_label, _) = reply.encode(&mut encoded);
+            assert_eq!(DebugReply::decode(&fault, reply_label, &encoded), reply);
+        }
+    }
+
+    #[cfg(feature = "register_replies")]
+    if let Some(reply) = RegisterReply::decode(&input.mrs) {
+        assert!(reply
+            .writes()
+            .iter()
+            .all(|write| WRITABLE_REPLY_REGISTERS.contains(&write.register)));
+        let mut encoded = [0; RegisterReply::MAX_LENGTH];
+        let (reply_label, length) = reply.encode(&mut encoded);
+        assert_eq!(reply_label, RegisterReply::LABEL);
+        assert_eq!(&encoded[..length], &input.mrs[..]);
+    }
+
+    let reply = FaultReply::decode(label, input.reply_label, &input.mrs);
+    let max_length = fault_reply_layout(label).map_or(0, |(_, max_length)| max_length);
+    assert!(reply.length <= max_length);
+    let mut encoded = [0; MAX_FAULT_REPLY];
+    let (reply_label, length) = reply.encode(label, &mut encoded);
+    assert_eq!(length, reply.length);
+    assert_eq!(&encoded[..length], &input.mrs[..length]);
+    assert_eq!(
+        FaultReply::decode(label, reply_label, &encoded[..length]),
+        reply
+    );
+});
@@ -0,0 +1,132 @@
+//! Fuzz the decoding side of the fault protocol along the path of `do_fault_reply_transfer`:
+//! `fault_reply_copy` picks the register list and length of an arbitrary reply, which is then
+//! copied into a simulated faulting thread the way `copy_fault_mrs_for_reply` does. The copy
+//! must stay within the register list of the architecture and the message registers the
+//! replier has, and leave every other register alone.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+#[cfg(feature = "kernel_mcs")]
+use sel4_common::arch::N_TIMEOUT_MESSAGE;
+use sel4_common::arch::{n_msgRegisters, N_EXCEPTON_MESSAGE, N_SYSCALL_MESSAGE};
+use sel4_common::message_info::seL4_MessageInfo_func;
+#[cfg(feature = "kernel_mcs")]
+use sel4_common::sel4_config::MESSAGE_ID_TIMEOUT_REPLY;
+use sel4_common::sel4_config::{seL4_MsgMaxLength, MESSAGE_ID_EXCEPTION, MESSAGE_ID_SYSCALL};
+use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
+use sel4_ipc::abi::MessageInfo;
+use sel4_ipc::fault_codec::FaultReply;
+use sel4_ipc::{fault_reply_copy, fault_reply_restart};
+
+#[derive(Arbitrary, Debug)]
+struct ReplyInput {
+    fault_tag: u8,
+    msg_info: usize,
+    reply_mrs: Vec<usize>,
+    has_ipc_buffer: bool,
+}
+
+/// Marks a register of the faulting thread the reply did not write
+const UNTOUCHED: usize = 0xdead_beef;
+
+/// The registers of a faulting thread, grouped by the `MESSAGE_ID_*` list of the architecture
+/// they belong to, each list exactly as long as on the architecture
+struct SimTcb {
+    lists: Vec<(usize, Vec<usize>)>,
+}
+
+impl SimTcb {
+    fn new() -> Self {
+        #[cfg_attr(not(feature = "kernel_mcs"), allow(unused_mut))]
+        let mut lists = vec![
+            (MESSAGE_ID_SYSCALL, vec![UNTOUCHED; N_SYSCALL_MESSAGE]),
+            (MESSAGE_ID_EXCEPTION, vec![UNTOUCHED; N_EXCEPTON_MESSAGE]),
+        ];
+        #[cfg(feature = "kernel_mcs")]
+        lists.push((MESSAGE_ID_TIMEOUT_REPLY, vec![UNTOUCHED; N_TIMEOUT_MESSAGE]));
+        SimTcb { lists }
+    }
+
+    /// The register list `message_id`, which must exist
+    fn list(&mut self, message_id: usize) -> &mut Vec<usize> {
+        &mut self
+            .lists
+            .iter_mut()
+            .find(|(id, _)| *id == message_id)
+            .unwrap_or_else(|| panic!("no register list {message_id}"))
+            .1
+    }
+}
+
+/// A replier, whose message registers past the physical ones live in its IPC buffer
+struct SimReplier<'a> {
+    mrs: &'a [usize],
+    has_ipc_buffer: bool,
+}
+
+impl SimReplier<'_> {
+    /// Mirror `get_mr`, checking that every register read exists
+    fn get_mr(&self, index: usize) -> Option<usize> {
+        assert!(
+            index < seL4_MsgMaxLength,
+            "read past the IPC buffer: {index}"
+        );
+        if index >= n_msgRegisters && !self.has_ipc_buffer {
+            return None;
+        }
+        Some(self.mrs.get(index).copied().unwrap_or(0))
+    }
+}
+
+fuzz_target!(|input: ReplyInput| {
+    let fault_tag = input.fault_tag as u64;
+    let tag = seL4_MessageInfo::from_word_security(input.msg_info);
+    let label = tag.get_label() as usize;
+    let replier = SimReplier {
+        mrs: &input.reply_mrs,
+        has_ipc_buffer: input.has_ipc_buffer,
+    };
+    let mut thread = SimTcb::new();
+
+    // The path of `do_fault_reply_transfer`.
+    let copied = fault_reply_copy(fault_tag, MessageInfo::from_word(tag.to_word()));
+    if let Some((message_id, length)) = copied {
+        assert!(length <= tag.get_length() as usize);
+        let list = thread.list(message_id);
+        assert!(
+            length <= list.len(),
+            "copied past register list {message_id}"
+        );
+        for (index, register) in list.iter_mut().enumerate().take(length) {
+            match replier.get_mr(index) {
+                Some(value) => *register = value,
+                None => break,
+            }
+        }
+    }
+
+    // Only the first registers of the list of the fault changed, to the words of the reply.
+    let reply_length = (tag.get_length() as usize).min(seL4_MsgMaxLength);
+    let reply_mrs: Vec<usize> = (0..reply_length)
+        .map_while(|index| replier.get_mr(index))
+        .collect();
+    let reply = FaultReply::decode(fault_tag, label, &reply_mrs);
+    for (message_id, list) in &thread.lists {
+        let written = match copied {
+            Some((id, length)) if id == *message_id => length.min(reply_mrs.len()),
+            _ => 0,
+        };
+        assert_eq!(&list[..written], &reply_mrs[..written]);
+        assert!(list[written..]
+            .iter()
+            .all(|register| *register == UNTOUCHED));
+        if written > 0 {
+            assert_eq!(&reply.regs[..reply.length], &list[..written]);
+        }
+    }
+    assert_eq!(reply.restart, fault_reply_restart(fault_tag, label));
+    if copied.is_none() {
+        assert_eq!(reply.length, 0);
+    }
+});
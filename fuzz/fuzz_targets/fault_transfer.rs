@@ -0,0 +1,227 @@
+//! Fuzz the encoding side of the fault protocol along the path of `do_fault_transfer`: a fault
+//! is encoded with `FaultMessage::encode` into a buffer of exactly `MAX_FAULT_MESSAGE`
+//! registers and written to a simulated receiver with `write_fault_mrs`. No message register
+//! past the physical ones may be written without an IPC buffer, none past the IPC buffer with
+//! one, and what the receiver got must decode to the fault that was sent.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sel4_common::arch::{n_msgRegisters, N_EXCEPTON_MESSAGE, N_SYSCALL_MESSAGE};
+use sel4_common::sel4_config::seL4_MsgMaxLength;
+#[cfg(feature = "kernel_mcs")]
+use sel4_ipc::fault_codec::Timeout;
+use sel4_ipc::fault_codec::{
+    CapFault, FaultMessage, LookupFailure, UnknownSyscall, UserException, VmFault,
+    MAX_FAULT_MESSAGE,
+};
+#[cfg(feature = "hardware_debug_api")]
+use sel4_ipc::fault_codec::{DebugException, DebugExceptionReason};
+#[cfg(feature = "arm_hypervisor_support")]
+use sel4_ipc::fault_codec::{VcpuFault, VgicMaintenance, VppiEvent};
+use sel4_ipc::{is_transferable_fault, write_fault_mrs};
+
+#[derive(Arbitrary, Debug)]
+enum Lookup {
+    InvalidRoot,
+    MissingCapability(usize),
+    DepthMismatch(usize, usize),
+    GuardMismatch(usize, usize, usize),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Fault {
+    Cap {
+        ip: usize,
+        addr: usize,
+        in_recv_phase: bool,
+        lookup: Lookup,
+    },
+    UnknownSyscall {
+        regs: [usize; N_SYSCALL_MESSAGE],
+        syscall: usize,
+    },
+    UserException {
+        regs: [usize; N_EXCEPTON_MESSAGE],
+        number: usize,
+        code: usize,
+    },
+    Vm {
+        ip: usize,
+        addr: usize,
+        prefetch_fault: bool,
+        fsr: usize,
+    },
+    #[cfg(feature = "kernel_mcs")]
+    Timeout {
+        data: usize,
+        consumed: Option<usize>,
+    },
+    #[cfg(feature = "hardware_debug_api")]
+    Debug {
+        ip: usize,
+        reason: usize,
+        breakpoint: usize,
+        address: usize,
+    },
+    #[cfg(feature = "arm_hypervisor_support")]
+    Hypervisor { kind: u8, word: usize },
+}
+
+#[derive(Arbitrary, Debug)]
+struct FaultInput {
+    fault: Fault,
+    has_ipc_buffer: bool,
+    other_tag: u8,
+    other_mrs: Vec<usize>,
+}
+
+impl Fault {
+    /// The message the kernel sends for the fault, `None` if the build has no such fault
+    fn message(&self) -> Option<FaultMessage> {
+        Some(match *self {
+            Fault::Cap {
+                ip,
+                addr,
+                in_recv_phase,
+                ref lookup,
+            } => FaultMessage::CapFault(CapFault {
+                ip,
+                addr,
+                in_recv_phase,
+                lookup_failure: match *lookup {
+                    Lookup::InvalidRoot => LookupFailure::InvalidRoot,
+                    Lookup::MissingCapability(bits_left) => {
+                        LookupFailure::MissingCapability { bits_left }
+                    }
+                    Lookup::DepthMismatch(bits_left, bits_found) => LookupFailure::DepthMismatch {
+                        bits_left,
+                        bits_found,
+                    },
+                    Lookup::GuardMismatch(bits_left, guard_found, bits_found) => {
+                        LookupFailure::GuardMismatch {
+                            bits_left,
+                            guard_found,
+                            bits_found,
+                        }
+                    }
+                },
+            }),
+            Fault::UnknownSyscall { regs, syscall } => {
+                FaultMessage::UnknownSyscall(UnknownSyscall { regs, syscall })
+            }
+            Fault::UserException { regs, number, code } => {
+                FaultMessage::UserException(UserException { regs, number, code })
+            }
+            Fault::Vm {
+                ip,
+                addr,
+                prefetch_fault,
+                fsr,
+            } => FaultMessage::VmFault(VmFault {
+                ip,
+                addr,
+                prefetch_fault,
+                fsr,
+            }),
+            #[cfg(feature = "kernel_mcs")]
+            Fault::Timeout { data, consumed } => FaultMessage::Timeout(Timeout { data, consumed }),
+            #[cfg(feature = "hardware_debug_api")]
+            Fault::Debug {
+                ip,
+                reason,
+                breakpoint,
+                address,
+            } => FaultMessage::DebugException(DebugException {
+                ip,
+                reason: DebugExceptionReason::new(reason % 4, breakpoint, address)?,
+            }),
+            #[cfg(feature = "arm_hypervisor_support")]
+            Fault::Hypervisor { kind, word } => match kind % 3 {
+                0 => FaultMessage::VgicMaintenance(VgicMaintenance {
+                    idx: (word != VgicMaintenance::NO_IDX).then_some(word),
+                }),
+                1 => FaultMessage::VcpuFault(VcpuFault { hsr: word }),
+                _ => FaultMessage::VppiEvent(VppiEvent { irq: word }),
+            },
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        })
+    }
+}
+
+// Every fault message fits in the message registers of any receiver with an IPC buffer.
+const _: () = assert!(MAX_FAULT_MESSAGE <= seL4_MsgMaxLength);
+
+/// A receiving thread: message registers in machine registers, the rest in its IPC buffer if
+/// it has one.
+struct SimReceiver {
+    registers: [usize; n_msgRegisters],
+    buffer: Option<[usize; seL4_MsgMaxLength]>,
+}
+
+impl SimReceiver {
+    /// Mirror `tcb_t::set_mr`, checking that every register written exists
+    fn set_mr(&mut self, index: usize, value: usize) -> usize {
+        if index < n_msgRegisters {
+            self.registers[index] = value;
+            return index + 1;
+        }
+        match self.buffer.as_mut() {
+            Some(buffer) => {
+                assert!(
+                    index < seL4_MsgMaxLength,
+                    "wrote past the IPC buffer: {index}"
+                );
+                buffer[index] = value;
+                index + 1
+            }
+            None => n_msgRegisters,
+        }
+    }
+
+    /// The first `length` message registers
+    fn mrs(&self, length: usize) -> Vec<usize> {
+        (0..length)
+            .map(|index| match index {
+                index if index < n_msgRegisters => self.registers[index],
+                index => self.buffer.unwrap()[index],
+            })
+            .collect()
+    }
+}
+
+fuzz_target!(|input: FaultInput| {
+    // A tag `do_fault_transfer` refuses must not decode as a fault message either.
+    let other_tag = input.other_tag as u64;
+    if !is_transferable_fault(other_tag) {
+        assert!(FaultMessage::decode(other_tag, &input.other_mrs).is_none());
+    }
+
+    let Some(message) = input.fault.message() else {
+        return;
+    };
+    assert!(is_transferable_fault(message.label()));
+
+    // The buffer of `do_fault_transfer`, an encoder writing past it panics.
+    let mut mrs = [0; MAX_FAULT_MESSAGE];
+    let length = message.encode(&mut mrs);
+    assert!(length <= MAX_FAULT_MESSAGE);
+
+    let mut receiver = SimReceiver {
+        registers: [0; n_msgRegisters],
+        buffer: input.has_ipc_buffer.then_some([0; seL4_MsgMaxLength]),
+    };
+    let sent = write_fault_mrs(&mrs[..length], |index, mr| receiver.set_mr(index, mr));
+    match receiver.buffer {
+        Some(_) => assert_eq!(sent, length),
+        None => assert_eq!(sent, length.min(n_msgRegisters)),
+    }
+    assert_eq!(receiver.mrs(sent), &mrs[..sent]);
+    if sent == length {
+        assert_eq!(
+            FaultMessage::decode(message.label(), &receiver.mrs(sent)),
+            Some(message)
+        );
+    }
+});
@@ -0,0 +1,83 @@
+//! Fuzz a shared-memory IPC ring against a queue: whatever the producer enqueues and the
+//! consumer takes, in whatever order, the ring must hand out the messages it accepted in order
+//! and unchanged, and refuse messages exactly when it is full or they do not fit a slot.
+#![no_main]
+
+use std::collections::VecDeque;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sel4_common::message_info::seL4_MessageInfo_func;
+use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
+use sel4_ipc::abi::MessageInfo;
+use sel4_ipc::ipc_ring::{Ring, RingError, RingSlot, RING_SLOT_WORDS};
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Enqueue {
+        label: u16,
+        length: u8,
+        extra_caps: bool,
+        words: [usize; RING_SLOT_WORDS],
+    },
+    Dequeue,
+}
+
+#[derive(Arbitrary, Debug)]
+struct RingInput {
+    entries_log2: u8,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: RingInput| {
+    let entries = 1 << (input.entries_log2 % 5);
+    let mut region = vec![RingSlot::default(); entries + 1];
+    let ring = Ring::init(&mut region).unwrap();
+    let mut model = VecDeque::new();
+
+    for op in input.ops.iter().take(512) {
+        match *op {
+            Op::Enqueue {
+                label,
+                length,
+                extra_caps,
+                words,
+            } => {
+                let length = length as usize % (RING_SLOT_WORDS + 2);
+                // Built with the kernel bitfields, so the ring also checks the layout of the
+                // shared message info.
+                let info = MessageInfo::from_word(
+                    seL4_MessageInfo::new(label as u64, 0, extra_caps as u64, length as u64)
+                        .to_word(),
+                );
+                let expected = if extra_caps {
+                    Err(RingError::HasCaps)
+                } else if length > RING_SLOT_WORDS {
+                    Err(RingError::TooLong)
+                } else if model.len() == entries {
+                    Err(RingError::Full)
+                } else {
+                    // Nobody sleeps on the doorbell here.
+                    Ok(false)
+                };
+                assert_eq!(ring.enqueue(info, &words), expected);
+                if expected.is_ok() {
+                    model.push_back((label as usize, words[..length].to_vec()));
+                }
+            }
+            Op::Dequeue => match (ring.dequeue(), model.pop_front()) {
+                (Some(entry), Some((label, words))) => {
+                    assert_eq!(entry.info.label(), label);
+                    assert_eq!(entry.words(), &words[..]);
+                }
+                (None, None) => {}
+                (entry, expected) => panic!(
+                    "ring gave {:?} words, expected {:?}",
+                    entry.map(|entry| entry.words().to_vec()),
+                    expected
+                ),
+            },
+        }
+        assert_eq!(ring.len(), model.len());
+    }
+});
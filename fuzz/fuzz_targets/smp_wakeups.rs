@@ -0,0 +1,86 @@
+//! Fuzz cross-core wakeups on the simulated scheduler: whatever order threads are woken in, from
+//! whatever core, every wakeup must end up on the ready queue of the thread's own core once the
+//! reschedule IPIs are delivered, and a core must get at most one IPI per kernel exit.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sel4_ipc::smp::sim::SimScheduler;
+use sel4_ipc::smp::WakeupKind;
+
+const CORES: usize = 4;
+const DEPTH: usize = 8;
+const THREADS: usize = 16;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    /// Enter the kernel on a core
+    Enter { core: u8 },
+    /// Wake a thread from the current core
+    Wake { thread: u8, switch_to: bool },
+    /// Leave the kernel, sending the pending IPIs
+    Exit,
+    /// Deliver the IPIs in flight
+    Deliver,
+    /// A ready thread runs and blocks again
+    Block { thread: u8 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct SmpInput {
+    affinity: [u8; THREADS],
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: SmpInput| {
+    let affinity = input.affinity.map(|core| core as usize % CORES);
+    let mut sched = SimScheduler::<CORES, DEPTH>::new();
+    // Threads woken and not blocked since, a thread whose wakeup was lost is left out.
+    let mut awake = [false; THREADS];
+
+    for op in input.ops.iter().take(256) {
+        match *op {
+            Op::Enter { core } => sched.current_core = core as usize % CORES,
+            Op::Wake { thread, switch_to } => {
+                let thread = thread as usize % THREADS;
+                let kind = if switch_to {
+                    WakeupKind::SwitchTo
+                } else {
+                    WakeupKind::Enqueue
+                };
+                if sched.wake(thread + 1, affinity[thread], kind) {
+                    awake[thread] = true;
+                }
+            }
+            Op::Exit => {
+                let sent = sched.ipis_sent;
+                sched.exit_kernel();
+                assert!(sched.ipis_sent - sent <= CORES);
+            }
+            Op::Deliver => sched.deliver_ipis(),
+            Op::Block { thread } => {
+                let thread = thread as usize % THREADS;
+                if sched.ready(affinity[thread]).contains(&(thread + 1)) {
+                    sched.block(affinity[thread], thread + 1);
+                    awake[thread] = false;
+                }
+            }
+        }
+        for core in 0..CORES {
+            assert!(sched.pending(core) <= DEPTH);
+            for ready in sched.ready(core) {
+                assert_eq!(affinity[ready - 1], core, "thread woken on the wrong core");
+            }
+        }
+    }
+
+    sched.exit_kernel();
+    sched.deliver_ipis();
+    for thread in 0..THREADS {
+        let ready = sched.ready(affinity[thread]);
+        assert_eq!(ready.iter().filter(|t| **t == thread + 1).count(), awake[thread] as usize);
+    }
+    for core in 0..CORES {
+        assert_eq!(sched.pending(core), 0);
+    }
+});
@@ -0,0 +1,89 @@
+//! Simulate the hypervisor faults of a VMM without virtualization hardware: every VCPU, VGIC
+//! maintenance and VPPI fault must reach a simulated receiver as a message the VMM decodes
+//! back to the same fault, and any reply must resume the guest without touching its
+//! registers.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sel4_common::message_info::seL4_MessageInfo_func;
+use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
+use sel4_ipc::fault_codec::{
+    fault_reply_layout, fault_reply_restart, FaultMessage, FaultReply, VcpuFault, VgicMaintenance,
+    VppiEvent, MAX_FAULT_MESSAGE,
+};
+
+#[derive(Arbitrary, Debug)]
+enum VmFault {
+    VgicMaintenance { idx: Option<u16> },
+    VcpuFault { hsr: u32 },
+    VppiEvent { irq: u16 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct VmFaultInput {
+    fault: VmFault,
+    has_ipc_buffer: bool,
+    reply_label: usize,
+    reply_mrs: Vec<usize>,
+}
+
+/// A receiver with the physical message registers only, as in `fault_transfer`.
+struct SimReceiver {
+    mrs: [usize; MAX_FAULT_MESSAGE],
+    has_ipc_buffer: bool,
+}
+
+impl SimReceiver {
+    const N_MSG_REGISTERS: usize = 4;
+
+    fn set_mr(&mut self, offset: usize, value: usize) -> usize {
+        if offset >= Self::N_MSG_REGISTERS && !self.has_ipc_buffer {
+            return Self::N_MSG_REGISTERS;
+        }
+        self.mrs[offset] = value;
+        offset + 1
+    }
+}
+
+fuzz_target!(|input: VmFaultInput| {
+    let fault = match input.fault {
+        VmFault::VgicMaintenance { idx } => FaultMessage::VgicMaintenance(VgicMaintenance {
+            idx: idx.map(usize::from),
+        }),
+        VmFault::VcpuFault { hsr } => FaultMessage::VcpuFault(VcpuFault { hsr: hsr as usize }),
+        VmFault::VppiEvent { irq } => FaultMessage::VppiEvent(VppiEvent { irq: irq as usize }),
+    };
+
+    // Kernel side: encode into the receiver, as `do_fault_transfer` does.
+    let mut mrs = [0; MAX_FAULT_MESSAGE];
+    let length = fault.encode(&mut mrs);
+    let mut receiver = SimReceiver {
+        mrs: [0; MAX_FAULT_MESSAGE],
+        has_ipc_buffer: input.has_ipc_buffer,
+    };
+    let mut sent = 0;
+    for (i, mr) in mrs[..length].iter().enumerate() {
+        sent = receiver.set_mr(i, *mr);
+    }
+    assert_eq!(
+        sent, length,
+        "hypervisor faults fit the physical message registers"
+    );
+    let msg_info = seL4_MessageInfo::new(fault.label(), 0, 0, sent as u64);
+
+    // VMM side: decode the message it received.
+    let info = seL4_MessageInfo::from_word_security(msg_info.to_word());
+    let decoded = FaultMessage::decode(
+        info.get_label(),
+        &receiver.mrs[..info.get_length() as usize],
+    );
+    assert_eq!(decoded, Some(fault));
+
+    // Any reply resumes the guest, and copies no registers back.
+    assert!(fault_reply_layout(fault.label()).is_none());
+    assert!(fault_reply_restart(fault.label(), input.reply_label));
+    let reply = FaultReply::decode(fault.label(), input.reply_label, &input.reply_mrs);
+    assert!(reply.restart);
+    assert_eq!(reply.length, 0);
+});
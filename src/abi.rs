@@ -0,0 +1,17 @@
+//! The IPC ABI shared with user space.
+//!
+//! What a message looks like to the threads exchanging it: the `seL4_MessageInfo` word with
+//! its label, length, extra caps and unwrapped-cap bitmap, the `caps_or_badges` words of the
+//! IPC buffer, the badge of a non-blocking receive that found nothing, and the fault messages
+//! of `fault_codec`. The kernel builds messages with these types in `do_normal_transfer` and
+//! `set_transfer_caps`, user libraries parse them with the same ones. The types live in
+//! `sel4_ipc_abi`, which only needs `core`, and are checked here against `sel4_common`.
+use sel4_common::sel4_config::{seL4_MsgMaxLength, SEL4_MSG_MAX_EXTRA_CAPS};
+
+pub use sel4_ipc_abi::{
+    fault_message, fault_message_info, FaultCodec, FaultMessage, FaultReply, MessageInfo,
+    ReceivedCap, ReceivedCaps, UnwrappedCaps, MSG_MAX_EXTRA_CAPS, MSG_MAX_LENGTH, NO_MESSAGE_BADGE,
+};
+
+const _: () = assert!(MSG_MAX_LENGTH == seL4_MsgMaxLength);
+const _: () = assert!(MSG_MAX_EXTRA_CAPS == SEL4_MSG_MAX_EXTRA_CAPS);
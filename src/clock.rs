@@ -0,0 +1,15 @@
+//! The clock used to timestamp trace records and to measure time spent queued.
+use core::ptr::addr_of_mut;
+
+static mut IPC_CLOCK: Option<fn() -> u64> = None;
+
+/// Set the clock of the IPC instrumentation, usually the cycle counter of the platform
+pub fn set_ipc_clock(clock: fn() -> u64) {
+    unsafe { *addr_of_mut!(IPC_CLOCK) = Some(clock) };
+}
+
+/// Read the clock, 0 if none is set
+#[inline]
+pub(crate) fn now() -> u64 {
+    unsafe { *addr_of_mut!(IPC_CLOCK) }.map_or(0, |clock| clock())
+}
@@ -0,0 +1,213 @@
+//! Debug dumps of endpoint and notification queues, enabled by the `debug_dump` feature.
+//!
+//! `dump_endpoint` and `dump_notification` print the state of an object and every thread queued
+//! on it, with the blocking state the thread was queued with. Threads and IPC objects can be
+//! given a name with `set_object_name`, it is printed next to their pointer.
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+use sel4_common::structures_gen::{endpoint, notification};
+use sel4_task::{tcb_queue_t, ThreadState};
+
+use crate::endpoint::{endpoint_func, EPState_Idle, EPState_Recv, EPState_Send};
+use crate::handle::{tcb_handle_func, Handle, TcbRef};
+use crate::notification::{notification_func, NtfnState_Active, NtfnState_Idle, NtfnState_Waiting};
+use crate::object_table::ObjectTable;
+
+/// Longest name kept for an object, longer names are truncated
+pub const OBJECT_NAME_LEN: usize = 32;
+/// Number of objects that can be named at once
+const MAX_NAMED_OBJECTS: usize = 256;
+/// Longest queue printed, in case the queue is corrupted
+const MAX_DUMP_QUEUE: usize = 256;
+
+#[derive(Clone, Copy)]
+struct ObjectName {
+    len: usize,
+    bytes: [u8; OBJECT_NAME_LEN],
+}
+
+static mut OBJECT_NAMES: ObjectTable<ObjectName, MAX_NAMED_OBJECTS> =
+    ObjectTable::new(ObjectName {
+        len: 0,
+        bytes: [0; OBJECT_NAME_LEN],
+    });
+
+#[inline]
+fn names() -> &'static mut ObjectTable<ObjectName, MAX_NAMED_OBJECTS> {
+    unsafe { &mut *addr_of_mut!(OBJECT_NAMES) }
+}
+
+/// Objects are named and dumped by any core, so with per-object locking the names have a lock
+/// of their own. It is not held while a dump is written.
+#[cfg(feature = "fine_grained_locking")]
+static OBJECT_NAMES_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Name a thread, endpoint or notification in the dumps. Returns false if the table is full.
+pub fn set_object_name(object: impl Handle, name: &str) -> bool {
+    ipc_lock!(lock_leaf(&OBJECT_NAMES_LOCK));
+    let mut len = core::cmp::min(name.len(), OBJECT_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    match names().get_or_insert(
+        object.raw(),
+        ObjectName {
+            len: 0,
+            bytes: [0; OBJECT_NAME_LEN],
+        },
+    ) {
+        Some(entry) => {
+            entry.bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+            entry.len = len;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Drop the name of a deleted object
+pub fn clear_object_name(object: impl Handle) {
+    ipc_lock!(lock_leaf(&OBJECT_NAMES_LOCK));
+    names().remove(object.raw());
+}
+
+/// Get the name of `object`
+fn object_name(object: usize) -> Option<ObjectName> {
+    ipc_lock!(lock_leaf(&OBJECT_NAMES_LOCK));
+    names().get(object).copied()
+}
+
+/// Print a pointer followed by the name of the object, if it has one
+struct Named(usize);
+
+impl fmt::Display for Named {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)?;
+        match object_name(self.0) {
+            Some(name) => match core::str::from_utf8(&name.bytes[..name.len]) {
+                Ok(name) => write!(f, " \"{}\"", name),
+                Err(_) => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+fn thread_state_name(ts_type: u64) -> &'static str {
+    const STATES: [(u64, &str); 8] = [
+        (ThreadState::ThreadStateInactive as u64, "Inactive"),
+        (ThreadState::ThreadStateRunning as u64, "Running"),
+        (ThreadState::ThreadStateRestart as u64, "Restart"),
+        (
+            ThreadState::ThreadStateBlockedOnReceive as u64,
+            "BlockedOnReceive",
+        ),
+        (
+            ThreadState::ThreadStateBlockedOnSend as u64,
+            "BlockedOnSend",
+        ),
+        (
+            ThreadState::ThreadStateBlockedOnReply as u64,
+            "BlockedOnReply",
+        ),
+        (
+            ThreadState::ThreadStateBlockedOnNotification as u64,
+            "BlockedOnNotification",
+        ),
+        (
+            ThreadState::ThreadStateIdleThreadState as u64,
+            "IdleThreadState",
+        ),
+    ];
+    STATES
+        .iter()
+        .find(|(state, _)| *state == ts_type)
+        .map_or("Unknown", |(_, name)| name)
+}
+
+fn dump_queue(out: &mut impl fmt::Write, queue: &tcb_queue_t) -> fmt::Result {
+    let mut next = TcbRef::from_raw(queue.head).get();
+    let mut index = 0;
+    while let Some(thread) = next {
+        if index == MAX_DUMP_QUEUE {
+            return writeln!(out, "  ... queue longer than {} threads", MAX_DUMP_QUEUE);
+        }
+        let state = &thread.tcbState;
+        write!(
+            out,
+            "  [{}] {} prio={} {} badge={:#x} can_grant={} can_grant_reply={} is_call={}",
+            index,
+            Named(thread.get_ptr()),
+            thread.tcbPriority,
+            thread_state_name(state.get_tsType()),
+            state.get_blockingIPCBadge(),
+            state.get_blockingIPCCanGrant(),
+            state.get_blockingIPCCanGrantReply(),
+            state.get_blockingIPCIsCall()
+        )?;
+        #[cfg(feature = "kernel_mcs")]
+        write!(
+            out,
+            " reply={:#x} sc={:#x}",
+            thread.reply_object(),
+            thread.sched_context()
+        )?;
+        writeln!(out)?;
+        next = thread.ep_next().get();
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Print the state of an endpoint and the threads queued on it
+pub fn dump_endpoint(ep: &endpoint, out: &mut impl fmt::Write) -> fmt::Result {
+    let state = match ep.get_state() as usize {
+        EPState_Idle => "Idle",
+        EPState_Send => "Send",
+        EPState_Recv => "Recv",
+        _ => "Corrupted",
+    };
+    let queue = ep.get_queue();
+    writeln!(
+        out,
+        "endpoint {} state={} head={:#x} tail={:#x}",
+        Named(ep.get_ptr()),
+        state,
+        queue.head,
+        queue.tail
+    )?;
+    dump_queue(out, &queue)
+}
+
+/// Print the state of a notification, its bound TCB, pending badge and waiting threads
+pub fn dump_notification(ntfn: &notification, out: &mut impl fmt::Write) -> fmt::Result {
+    let state = match ntfn.get_state() as usize {
+        NtfnState_Idle => "Idle",
+        NtfnState_Waiting => "Waiting",
+        NtfnState_Active => "Active",
+        #[cfg(feature = "lockfree_signal")]
+        busy if busy as u64 == crate::lock::signal_word::STATE_BUSY => "Busy",
+        _ => "Corrupted",
+    };
+    let queue = ntfn.get_queue();
+    write!(
+        out,
+        "notification {} state={} head={:#x} tail={:#x}",
+        Named(ntfn.get_ptr()),
+        state,
+        queue.head,
+        queue.tail
+    )?;
+    if ntfn.get_state() as usize == NtfnState_Active {
+        write!(out, " pending_badge={:#x}", ntfn.get_ntfnMsgIdentifier())?;
+    }
+    match ntfn.bound_tcb() {
+        TcbRef::NULL => write!(out, " bound_tcb=none")?,
+        tcb => write!(out, " bound_tcb={}", Named(tcb.raw()))?,
+    }
+    #[cfg(feature = "kernel_mcs")]
+    write!(out, " sc={:#x}", ntfn.get_ntfnSchedContext())?;
+    writeln!(out)?;
+    dump_queue(out, &queue)
+}
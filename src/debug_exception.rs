@@ -0,0 +1,73 @@
+//! Debug exception faults of the hardware debug API, enabled by the `hardware_debug_api`
+//! feature.
+//!
+//! Breakpoints, watchpoints and single steps are sent to the fault endpoint of the thread as
+//! a `DebugException` message. The reply of the debugger is a `DebugReply`, which resumes the
+//! thread, steps it again or clears the breakpoint that was hit, so a user-level GDB stub can
+//! drive the thread. The debug registers are programmed by the kernel through the hooks set
+//! with `set_debug_hooks`, until then replies only resume the thread.
+use core::ptr::addr_of_mut;
+
+use sel4_common::arch::ArchReg;
+use sel4_common::structures_gen::seL4_Fault;
+use sel4_task::tcb_t;
+
+use crate::fault_codec::{DebugException, DebugExceptionReason, DebugReply};
+
+#[derive(Clone, Copy)]
+/// The arch services that program the debug registers of a thread
+pub struct DebugHooks {
+    /// Stop the thread after the given number of instructions, 0 stops stepping it
+    pub configure_single_stepping: fn(&mut tcb_t, usize),
+    /// Disable a hardware breakpoint of the thread
+    pub unset_breakpoint: fn(&mut tcb_t, usize),
+}
+
+static mut DEBUG_HOOKS: Option<DebugHooks> = None;
+
+/// Set the arch services used to act on the replies to debug exceptions
+pub fn set_debug_hooks(hooks: DebugHooks) {
+    unsafe { *addr_of_mut!(DEBUG_HOOKS) = Some(hooks) };
+}
+
+/// Read the debug exception `thread` is blocked on, `None` for an unknown reason
+pub(crate) fn debug_exception_of(thread: &tcb_t) -> Option<DebugException> {
+    let fault = seL4_Fault::seL4_Fault_DebugException(&thread.tcbFault);
+    Some(DebugException {
+        ip: thread.tcbArch.get_register(ArchReg::FAULT_IP),
+        reason: DebugExceptionReason::new(
+            fault.get_exceptionReason() as usize,
+            fault.get_breakpointNumber() as usize,
+            fault.get_breakpointAddress() as usize,
+        )?,
+    })
+}
+
+/// Act on the reply of `replier` to the debug exception of `thread`
+pub(crate) fn do_debug_reply(replier: &tcb_t, thread: &mut tcb_t, label: usize) {
+    let Some(fault) = debug_exception_of(thread) else {
+        return;
+    };
+    let mrs = [replier
+        .tcbArch
+        .get_register(ArchReg::Msg(DebugReply::INSTRUCTIONS))];
+    let reply = DebugReply::decode(&fault, label, &mrs);
+    let Some(hooks) = (unsafe { *addr_of_mut!(DEBUG_HOOKS) }) else {
+        return;
+    };
+    match reply {
+        DebugReply::Step { instructions } => {
+            (hooks.configure_single_stepping)(thread, instructions)
+        }
+        DebugReply::ClearBreakpoint => {
+            if let Some(breakpoint) = fault.reason.breakpoint() {
+                (hooks.unset_breakpoint)(thread, breakpoint)
+            }
+        }
+        DebugReply::Resume => {
+            if fault.reason == DebugExceptionReason::SingleStep {
+                (hooks.configure_single_stepping)(thread, 0)
+            }
+        }
+    }
+}
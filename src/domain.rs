@@ -0,0 +1,132 @@
+//! Domain-aware IPC wakeups, enabled by the `domain_isolation` feature.
+//!
+//! The domain scheduler gives each domain its own time slices. A thread woken by IPC from
+//! another domain must not run before the slice of its domain, so the wakeup only puts it on
+//! the ready queue of its domain instead of switching to it. Optionally, IPC through endpoints
+//! between threads of different domains is logged or forbidden with `CrossDomainPolicy`.
+//! Notifications are left alone, they are how domains are meant to signal each other.
+//!
+//! The domains are provided by the kernel with `set_domain_hooks`, until then every thread is
+//! in the current domain.
+use core::ptr::addr_of_mut;
+
+use sel4_common::structures_gen::seL4_Fault_NullFault;
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
+
+use crate::error::{IpcError, IpcResult};
+use crate::handle::tcb_handle_func;
+
+#[derive(Clone, Copy)]
+/// The scheduler services needed for domain-aware wakeups
+pub struct DomainHooks {
+    /// Domain whose time slice is running
+    pub current_domain: fn() -> usize,
+    /// Domain of a thread
+    pub domain_of: fn(&tcb_t) -> usize,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What to do with IPC between threads of different domains
+pub enum CrossDomainPolicy {
+    /// Deliver it as usual
+    Allow,
+    /// Log it and deliver it as usual
+    Log,
+    /// Return `IpcError::CrossDomainIpc` without delivering it. A caller whose reply is
+    /// forbidden is made inactive, it would wait for the reply forever otherwise. So is a
+    /// sender found on the queue by a receiver of another domain, it would stay at the head of
+    /// the queue and keep every other sender from being received.
+    Forbid,
+}
+
+struct DomainState {
+    hooks: Option<DomainHooks>,
+    policy: CrossDomainPolicy,
+    /// Wakeups left on the ready queue because the thread is in another domain
+    deferred: u64,
+}
+
+static mut DOMAIN_STATE: DomainState = DomainState {
+    hooks: None,
+    policy: CrossDomainPolicy::Allow,
+    deferred: 0,
+};
+
+#[inline]
+fn domains() -> &'static mut DomainState {
+    unsafe { &mut *addr_of_mut!(DOMAIN_STATE) }
+}
+
+/// Wakeups are counted by cores holding unrelated object locks, so with per-object locking the
+/// domain state has a lock of its own
+#[cfg(feature = "fine_grained_locking")]
+static DOMAIN_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Set the scheduler services used for domain-aware wakeups
+pub fn set_domain_hooks(hooks: DomainHooks) {
+    ipc_lock!(lock_leaf(&DOMAIN_LOCK));
+    domains().hooks = Some(hooks);
+}
+
+/// Set what happens to IPC between threads of different domains, `Allow` by default
+pub fn set_cross_domain_policy(policy: CrossDomainPolicy) {
+    ipc_lock!(lock_leaf(&DOMAIN_LOCK));
+    domains().policy = policy;
+}
+
+/// Number of wakeups deferred to the time slice of another domain
+pub fn deferred_domain_wakeups() -> u64 {
+    ipc_lock!(lock_leaf(&DOMAIN_LOCK));
+    domains().deferred
+}
+
+/// Check whether `thread` may be switched to now, counting the wakeup as deferred otherwise
+pub(crate) fn in_current_domain(thread: &tcb_t) -> bool {
+    ipc_lock!(lock_leaf(&DOMAIN_LOCK));
+    let domains = domains();
+    match domains.hooks {
+        Some(hooks) if (hooks.domain_of)(thread) != (hooks.current_domain)() => {
+            domains.deferred += 1;
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Apply the policy to IPC from `thread` to `peer` through an endpoint or a reply
+pub(crate) fn check_cross_domain(thread: &tcb_t, peer: &tcb_t) -> IpcResult<()> {
+    ipc_lock!(lock_leaf(&DOMAIN_LOCK));
+    let domains = domains();
+    let hooks = match domains.hooks {
+        Some(hooks) => hooks,
+        None => return Ok(()),
+    };
+    let (domain, peer_domain) = ((hooks.domain_of)(thread), (hooks.domain_of)(peer));
+    if domain == peer_domain {
+        return Ok(());
+    }
+    match domains.policy {
+        CrossDomainPolicy::Allow => Ok(()),
+        CrossDomainPolicy::Log => {
+            log::warn!(
+                "IPC from thread {:#x} in domain {} to thread {:#x} in domain {}",
+                thread.get_ptr(),
+                domain,
+                peer.get_ptr(),
+                peer_domain
+            );
+            Ok(())
+        }
+        CrossDomainPolicy::Forbid => Err(IpcError::CrossDomainIpc {
+            thread: thread.handle(),
+            peer: peer.handle(),
+        }),
+    }
+}
+
+/// Leave a thread whose IPC the policy forbids inactive, like a fault handler that does not
+/// restart it, instead of blocked on a send or a reply that never completes
+pub(crate) fn cancel_forbidden_ipc(thread: &mut tcb_t) {
+    thread.tcbFault = seL4_Fault_NullFault::new().unsplay();
+    set_thread_state(thread, ThreadState::ThreadStateInactive);
+}
@@ -0,0 +1,279 @@
+//! What happens to a fault that cannot be delivered, enabled by the `double_fault_policy`
+//! feature.
+//!
+//! A fault is undeliverable if the thread has no valid fault handler, or if it is raised by a
+//! fault handler nested `max_depth` handlers deep, which is how loops of handlers faulting
+//! into each other are caught. The kernel sends faults with `deliver_fault`, which logs an
+//! undeliverable fault with the fault codec and applies the `DoubleFaultPolicy`. Without a
+//! policy the thread is suspended, as in seL4.
+//!
+//! A handler is at depth 1 while it handles the fault of a thread that handles none, and one
+//! deeper than the deepest fault it handles otherwise, until it replies.
+use core::ptr::addr_of_mut;
+
+use sel4_common::arch::ArchReg;
+use sel4_common::structures_gen::seL4_Fault_NullFault;
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
+
+use crate::error::IpcResult;
+use crate::fault_routing::{route_fault, send_on_route, FaultRoute};
+use crate::handle::{tcb_handle_func, EndpointRef, TcbRef};
+use crate::object_table::ObjectTable;
+use crate::outcome::IpcOutcome;
+use crate::transfer::fault_message_of;
+
+/// Number of threads that can have a secondary handler at once
+const MAX_SECONDARY_HANDLERS: usize = 64;
+/// Number of threads whose restarts can be counted at once
+const MAX_RESTARTED_THREADS: usize = 64;
+/// Number of handlers that can handle faults at once
+const MAX_HANDLING_THREADS: usize = 128;
+/// Default depth of nested fault handlers at which faults become undeliverable
+pub const DEFAULT_MAX_HANDLER_DEPTH: usize = 4;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What to do with a thread whose fault cannot be delivered
+pub enum DoubleFaultPolicy {
+    /// Leave it inactive, as in seL4
+    Suspend,
+    /// Send the fault to the secondary handler of the thread, suspend it if it has none
+    SecondaryHandler,
+    /// Send the fault to the supervisor endpoint, suspend the thread if there is none
+    Supervisor,
+    /// Drop the fault and restart the thread, up to `limit` times, then suspend it
+    Restart { limit: u32 },
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What `deliver_fault` did with a fault
+pub enum FaultDisposition {
+    /// The fault was sent to a handler, the secondary handler or the supervisor
+    Delivered(IpcOutcome),
+    /// The thread was made inactive
+    Suspended,
+    /// The fault was dropped and the thread restarted, for the `count`th time
+    Restarted { count: u32 },
+}
+
+struct DoubleFaults {
+    policy: DoubleFaultPolicy,
+    supervisor: Option<FaultRoute>,
+    secondary: ObjectTable<FaultRoute, MAX_SECONDARY_HANDLERS>,
+    restarts: ObjectTable<u32, MAX_RESTARTED_THREADS>,
+    /// Handler -> depth of the faults it handles
+    depth: ObjectTable<usize, MAX_HANDLING_THREADS>,
+    max_depth: usize,
+    /// Faults that could not be delivered
+    undeliverable: u64,
+}
+
+static mut DOUBLE_FAULTS: DoubleFaults = DoubleFaults {
+    policy: DoubleFaultPolicy::Suspend,
+    supervisor: None,
+    secondary: ObjectTable::new(FaultRoute {
+        endpoint: EndpointRef::NULL,
+        badge: 0,
+        can_grant: false,
+        can_grant_reply: false,
+    }),
+    restarts: ObjectTable::new(0),
+    depth: ObjectTable::new(0),
+    max_depth: DEFAULT_MAX_HANDLER_DEPTH,
+    undeliverable: 0,
+};
+
+#[inline]
+fn double_faults() -> &'static mut DoubleFaults {
+    unsafe { &mut *addr_of_mut!(DOUBLE_FAULTS) }
+}
+
+/// Faults are delivered by cores holding unrelated object locks, so with per-object locking the
+/// policy state has a lock of its own. It is not held while the fault is routed or sent.
+#[cfg(feature = "fine_grained_locking")]
+static DOUBLE_FAULT_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Set what happens to undeliverable faults, `Suspend` by default
+pub fn set_double_fault_policy(policy: DoubleFaultPolicy) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().policy = policy;
+}
+
+/// Set the supervisor endpoint of the `Supervisor` policy
+pub fn set_fault_supervisor(route: Option<FaultRoute>) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().supervisor = route;
+}
+
+/// Set the secondary handler of `thread` for the `SecondaryHandler` policy. Returns false if
+/// too many threads have one.
+pub fn set_secondary_fault_handler(thread: TcbRef, route: Option<FaultRoute>) -> bool {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    let secondary = &mut double_faults().secondary;
+    match route {
+        Some(route) => match secondary.get_or_insert(thread.raw(), route) {
+            Some(entry) => {
+                *entry = route;
+                true
+            }
+            None => false,
+        },
+        None => {
+            secondary.remove(thread.raw());
+            true
+        }
+    }
+}
+
+/// Set the depth of nested fault handlers at which faults become undeliverable
+pub fn set_fault_handler_depth_limit(max_depth: usize) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().max_depth = max_depth;
+}
+
+/// Start counting the restarts of `thread` from 0 again
+pub fn reset_fault_restarts(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().restarts.remove(thread.raw());
+}
+
+/// Number of faults that could not be delivered
+pub fn undeliverable_faults() -> u64 {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().undeliverable
+}
+
+/// Forget the secondary handler, restarts and depth of a deleted thread
+pub fn forget_double_fault_state(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    let double_faults = double_faults();
+    double_faults.secondary.remove(thread.raw());
+    double_faults.restarts.remove(thread.raw());
+    double_faults.depth.remove(thread.raw());
+}
+
+/// Depth of the faults `thread` handles, 0 if it handles none
+fn handler_depth(thread: &tcb_t) -> usize {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults()
+        .depth
+        .get(thread.get_ptr())
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Record that `handler` received the fault of `thread`
+pub(crate) fn fault_delivered(thread: &tcb_t, handler: &tcb_t) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    let depth = handler_depth(thread) + 1;
+    if let Some(entry) = double_faults().depth.get_or_insert(handler.get_ptr(), 0) {
+        *entry = (*entry).max(depth);
+    }
+}
+
+/// Record that `handler` replied to the faults it handles
+pub(crate) fn fault_replied(handler: &tcb_t) {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().depth.remove(handler.get_ptr());
+}
+
+/// Log the fault of `thread`, decoded by the fault codec where it can be
+fn log_undeliverable_fault(thread: &tcb_t, depth: usize) {
+    match fault_message_of(thread) {
+        Some(message) => log::warn!(
+            "undeliverable fault of {:#x} at handler depth {}: {:?}",
+            thread.handle(),
+            depth,
+            message
+        ),
+        None => log::warn!(
+            "undeliverable fault {} of {:#x} at handler depth {}, ip {:#x}",
+            thread.tcbFault.get_tag() as u64,
+            thread.handle(),
+            depth,
+            thread.tcbArch.get_register(ArchReg::FAULT_IP)
+        ),
+    }
+}
+
+/// Count the undeliverable fault of `thread` and apply the policy, returning where it is sent
+/// or what was done instead
+fn undeliverable_route(thread: &mut tcb_t) -> Result<FaultRoute, FaultDisposition> {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    let double_faults = double_faults();
+    double_faults.undeliverable += 1;
+    let route = match double_faults.policy {
+        DoubleFaultPolicy::Suspend => None,
+        DoubleFaultPolicy::SecondaryHandler => {
+            double_faults.secondary.get(thread.get_ptr()).copied()
+        }
+        DoubleFaultPolicy::Supervisor => double_faults.supervisor,
+        DoubleFaultPolicy::Restart { limit } => {
+            match double_faults.restarts.get_or_insert(thread.get_ptr(), 0) {
+                Some(count) if *count < limit => {
+                    *count += 1;
+                    thread.tcbFault = seL4_Fault_NullFault::new().unsplay();
+                    set_thread_state(thread, ThreadState::ThreadStateRestart);
+                    return Err(FaultDisposition::Restarted { count: *count });
+                }
+                _ => None,
+            }
+        }
+    };
+    match route.filter(FaultRoute::is_valid) {
+        Some(route) => Ok(route),
+        None => {
+            set_thread_state(thread, ThreadState::ThreadStateInactive);
+            Err(FaultDisposition::Suspended)
+        }
+    }
+}
+
+/// Depth of nested fault handlers at which faults become undeliverable
+fn max_handler_depth() -> usize {
+    ipc_lock!(lock_leaf(&DOUBLE_FAULT_LOCK));
+    double_faults().max_depth
+}
+
+/// Pick the route of the fault of `thread`, applying the policy if it cannot be delivered
+fn route_or_policy(
+    thread: &mut tcb_t,
+    handler: Option<FaultRoute>,
+) -> Result<FaultRoute, FaultDisposition> {
+    let depth = handler_depth(thread);
+    match route_fault(thread, handler).filter(|_| depth < max_handler_depth()) {
+        Some(route) => Ok(route),
+        None => {
+            log_undeliverable_fault(thread, depth);
+            undeliverable_route(thread)
+        }
+    }
+}
+
+#[cfg(not(feature = "kernel_mcs"))]
+/// Send the fault of `thread` to its route or `handler`, the route of its fault handler cap,
+/// `None` if the cap is not a valid endpoint cap. Applies the policy if the fault cannot be
+/// delivered.
+pub fn deliver_fault(
+    thread: &mut tcb_t,
+    handler: Option<FaultRoute>,
+) -> IpcResult<FaultDisposition> {
+    Ok(match route_or_policy(thread, handler) {
+        Ok(route) => FaultDisposition::Delivered(send_on_route(thread, route)?),
+        Err(disposition) => disposition,
+    })
+}
+
+#[cfg(feature = "kernel_mcs")]
+/// Send the fault of `thread` to its route or `handler`, the route of its fault handler cap,
+/// `None` if the cap is not a valid endpoint cap. Applies the policy if the fault cannot be
+/// delivered.
+pub fn deliver_fault(
+    thread: &mut tcb_t,
+    handler: Option<FaultRoute>,
+    can_donate: bool,
+) -> IpcResult<FaultDisposition> {
+    Ok(match route_or_policy(thread, handler) {
+        Ok(route) => FaultDisposition::Delivered(send_on_route(thread, route, can_donate)?),
+        Err(disposition) => disposition,
+    })
+}
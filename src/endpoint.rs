@@ -1,15 +1,22 @@
-use crate::transfer::Transfer;
+use crate::abi::NO_MESSAGE_BADGE;
+use crate::error::{IpcError, IpcResult};
+#[cfg(feature = "kernel_mcs")]
+use crate::handle::ReplyRef;
+use crate::handle::{tcb_handle_func, EndpointRef, TcbRef};
+use crate::outcome::{IpcOutcome, IpcTransfer};
+use crate::sched::possible_switch_to;
+#[cfg(not(feature = "kernel_mcs"))]
+use crate::sched::sched_enqueue;
+use crate::transfer::{check_transferable_fault, Transfer};
 use sel4_common::arch::ArchReg;
 use sel4_common::structures_gen::endpoint;
 #[cfg(feature = "kernel_mcs")]
 use sel4_common::structures_gen::seL4_Fault_tag::seL4_Fault_NullFault;
-use sel4_common::utils::{convert_to_mut_type_ref, convert_to_option_mut_type_ref};
+#[cfg(feature = "kernel_mcs")]
+use sel4_task::NODE_STATE;
 use sel4_task::{
-    possible_switch_to, reschedule_required, schedule_tcb, set_thread_state, tcb_queue_t, tcb_t,
-    ThreadState,
+    reschedule_required, schedule_tcb, set_thread_state, tcb_queue_t, tcb_t, ThreadState,
 };
-#[cfg(feature = "kernel_mcs")]
-use sel4_task::{reply::reply_t, sched_context::sched_context_t, NODE_STATE};
 use sel4_vspace::pptr_t;
 
 pub const EPState_Idle: usize = EPState::Idle as usize;
@@ -28,12 +35,13 @@ use sel4_common::structures_gen::cap_reply_cap;
 
 pub trait endpoint_func {
     fn get_ptr(&self) -> pptr_t;
-    fn get_ep_state(&self) -> EPState;
+    fn handle(&self) -> EndpointRef;
+    fn get_ep_state(&self) -> IpcResult<EPState>;
     fn get_queue(&self) -> tcb_queue_t;
     fn set_queue(&mut self, tcb_queue: &tcb_queue_t);
     fn cancel_ipc(&mut self, tcb: &mut tcb_t);
-    fn cancel_all_ipc(&mut self);
-    fn cancel_badged_sends(&mut self, badge: usize);
+    fn cancel_all_ipc(&mut self) -> IpcResult<()>;
+    fn cancel_badged_sends(&mut self, badge: usize) -> IpcResult<()>;
     #[cfg(not(feature = "kernel_mcs"))]
     fn send_ipc(
         &mut self,
@@ -43,7 +51,7 @@ pub trait endpoint_func {
         can_grant: bool,
         badge: usize,
         can_grant_reply: bool,
-    );
+    ) -> IpcResult<IpcOutcome>;
     #[cfg(feature = "kernel_mcs")]
     fn send_ipc(
         &mut self,
@@ -54,16 +62,21 @@ pub trait endpoint_func {
         badge: usize,
         can_grant_reply: bool,
         canDonate: bool,
-    );
+    ) -> IpcResult<IpcOutcome>;
     #[cfg(not(feature = "kernel_mcs"))]
-    fn receive_ipc(&mut self, thread: &mut tcb_t, is_blocking: bool, grant: bool);
+    fn receive_ipc(
+        &mut self,
+        thread: &mut tcb_t,
+        is_blocking: bool,
+        grant: bool,
+    ) -> IpcResult<IpcOutcome>;
     #[cfg(feature = "kernel_mcs")]
     fn receive_ipc(
         &mut self,
         thread: &mut tcb_t,
         is_blocking: bool,
         Option_reply_cap: Option<&mut cap_reply_cap>,
-    );
+    ) -> IpcResult<IpcOutcome>;
     #[cfg(feature = "kernel_mcs")]
     fn reorder_ep(&mut self, thread: &mut tcb_t);
 }
@@ -75,9 +88,22 @@ impl endpoint_func for endpoint {
     }
 
     #[inline]
-    /// Get the state of the endpoint
-    fn get_ep_state(&self) -> EPState {
-        unsafe { core::mem::transmute::<u8, EPState>(self.get_state() as u8) }
+    /// Get the typed handle of the endpoint
+    fn handle(&self) -> EndpointRef {
+        EndpointRef::of(self)
+    }
+
+    #[inline]
+    /// Get the state of the endpoint, an unknown state means the endpoint is corrupted
+    fn get_ep_state(&self) -> IpcResult<EPState> {
+        match self.get_state() as usize {
+            EPState_Idle => Ok(EPState::Idle),
+            EPState_Send => Ok(EPState::Send),
+            EPState_Recv => Ok(EPState::Recv),
+            _ => Err(IpcError::CorruptedObjectState {
+                object: self.get_ptr(),
+            }),
+        }
     }
 
     #[inline]
@@ -101,6 +127,8 @@ impl endpoint_func for endpoint {
     /// # Arguments
     /// * `tcb` - The tcb to cancel the IPC
     fn cancel_ipc(&mut self, tcb: &mut tcb_t) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::cancel_ipc");
         let mut queue = self.get_queue();
         queue.ep_dequeue(tcb);
         self.set_queue(&queue);
@@ -109,113 +137,119 @@ impl endpoint_func for endpoint {
         }
         #[cfg(feature = "kernel_mcs")]
         {
-            if let Some(reply) =
-                convert_to_option_mut_type_ref::<reply_t>(tcb.tcbState.get_replyObject() as usize)
-            {
+            if let Some(reply) = tcb.reply_object().get() {
                 reply.unlink(tcb);
             }
         }
         set_thread_state(tcb, ThreadState::ThreadStateInactive);
+        ipc_trace!(Cancel, Cancelled, self.get_ptr(), tcb.get_ptr());
+        ipc_stats!(endpoint_cancelled(self.get_ptr(), tcb.get_ptr()));
     }
 
     #[inline]
     /// Cancel all IPC in the endpoint
-    fn cancel_all_ipc(&mut self) {
-        match self.get_ep_state() {
+    fn cancel_all_ipc(&mut self) -> IpcResult<()> {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::cancel_all_ipc");
+        match self.get_ep_state()? {
             EPState::Idle => {}
             _ => {
-                let mut op_thread =
-                    convert_to_option_mut_type_ref::<tcb_t>(self.get_epQueue_head() as usize);
+                let mut op_thread = TcbRef::from_raw(self.get_epQueue_head() as usize).get();
                 self.set_state(EPState::Idle as u64);
                 self.set_epQueue_head(0);
                 self.set_epQueue_tail(0);
                 while let Some(thread) = op_thread {
                     #[cfg(feature = "kernel_mcs")]
                     {
-                        let reply_ptr = thread.tcbState.get_replyObject() as usize;
-                        if reply_ptr != 0 {
-                            convert_to_mut_type_ref::<reply_t>(reply_ptr).unlink(thread);
+                        if let Some(reply) = thread.reply_object().get() {
+                            reply.unlink(thread);
                         }
                         if thread.tcbFault.get_tag() == seL4_Fault_NullFault as u64 {
                             set_thread_state(thread, ThreadState::ThreadStateRestart);
-                            if thread.tcbSchedContext != 0
-                                && convert_to_mut_type_ref::<sched_context_t>(
-                                    thread.tcbSchedContext,
-                                )
-                                .sc_sporadic()
-                            {
-                                assert!(thread.tcbSchedContext != NODE_STATE!(ksCurSC));
-                                if thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
-                                    convert_to_mut_type_ref::<sched_context_t>(
-                                        thread.tcbSchedContext,
-                                    )
-                                    .refill_unblock_check();
+                            if let Some(sc) = thread.sched_context().get() {
+                                if sc.sc_sporadic() {
+                                    // A blocked thread does not run on the current
+                                    // scheduling context, skip the refill if it somehow does.
+                                    if thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
+                                        sc.refill_unblock_check();
+                                    }
                                 }
                             }
                             possible_switch_to(thread);
+                            ipc_trace!(Cancel, Restarted, self.get_ptr(), thread.get_ptr());
+                            ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                         } else {
                             set_thread_state(thread, ThreadState::ThreadStateInactive);
+                            ipc_trace!(Cancel, Cancelled, self.get_ptr(), thread.get_ptr());
+                            ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                         }
                     }
                     #[cfg(not(feature = "kernel_mcs"))]
                     {
                         set_thread_state(thread, ThreadState::ThreadStateRestart);
-                        thread.sched_enqueue();
+                        sched_enqueue(thread);
+                        ipc_trace!(Cancel, Restarted, self.get_ptr(), thread.get_ptr());
+                        ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                     }
 
-                    op_thread = convert_to_option_mut_type_ref::<tcb_t>(thread.tcbEPNext);
+                    op_thread = thread.ep_next().get();
                 }
                 reschedule_required();
             }
         }
+        Ok(())
     }
 
     /// Cancel badged sends in the endpoint, and set the tcb to restart
     /// # Arguments
     /// * `badge` - The badge to cancel
-    fn cancel_badged_sends(&mut self, badge: usize) {
-        match self.get_ep_state() {
+    fn cancel_badged_sends(&mut self, badge: usize) -> IpcResult<()> {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::cancel_badged_sends");
+        match self.get_ep_state()? {
             EPState::Idle | EPState::Recv => {}
             EPState::Send => {
                 let mut queue = self.get_queue();
                 self.set_state(EPState::Idle as u64);
                 self.set_epQueue_head(0);
                 self.set_epQueue_tail(0);
-                let mut thread_ptr = queue.head;
-                while thread_ptr != 0 {
-                    let thread = convert_to_mut_type_ref::<tcb_t>(thread_ptr);
-                    thread_ptr = thread.tcbEPNext;
-                    #[cfg(feature = "kernel_mcs")]
-                    {
-                        assert!(thread.tcbState.get_replyObject() == 0);
-                    }
+                let mut next = TcbRef::from_raw(queue.head);
+                while let Some(thread) = next.get() {
+                    next = thread.ep_next();
                     if thread.tcbState.get_blockingIPCBadge() as usize == badge {
                         #[cfg(not(feature = "kernel_mcs"))]
                         {
                             set_thread_state(thread, ThreadState::ThreadStateRestart);
-                            thread.sched_enqueue();
+                            sched_enqueue(thread);
                             queue.ep_dequeue(thread);
+                            ipc_trace!(Cancel, Restarted, self.get_ptr(), thread.get_ptr(), badge: badge);
+                            ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                         }
                         #[cfg(feature = "kernel_mcs")]
                         {
+                            // Senders have no reply object, unlink one anyway rather than
+                            // leave it pointing at a thread that is no longer blocked.
+                            if let Some(reply) = thread.reply_object().get() {
+                                reply.unlink(thread);
+                            }
                             if thread.tcbFault.get_tag() == seL4_Fault_NullFault {
                                 set_thread_state(thread, ThreadState::ThreadStateRestart);
-                                if convert_to_mut_type_ref::<sched_context_t>(
-                                    thread.tcbSchedContext,
-                                )
-                                .sc_sporadic()
-                                {
-                                    assert!(thread.tcbSchedContext != NODE_STATE!(ksCurSC));
-                                    if thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
-                                        convert_to_mut_type_ref::<sched_context_t>(
-                                            thread.tcbSchedContext,
-                                        )
-                                        .refill_unblock_check();
+                                if let Some(sc) = thread.sched_context().get() {
+                                    if sc.sc_sporadic() {
+                                        // A blocked thread does not run on the current
+                                        // scheduling context, skip the refill if it does.
+                                        if thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
+                                            sc.refill_unblock_check();
+                                        }
                                     }
                                 }
                                 possible_switch_to(thread);
+                                ipc_trace!(Cancel, Restarted, self.get_ptr(), thread.get_ptr(), badge: badge);
+                                ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                             } else {
                                 set_thread_state(thread, ThreadState::ThreadStateInactive);
+                                ipc_trace!(Cancel, Cancelled, self.get_ptr(), thread.get_ptr(), badge: badge);
+                                ipc_stats!(endpoint_cancelled(self.get_ptr(), thread.get_ptr()));
                             }
                             queue.ep_dequeue(thread);
                         }
@@ -228,10 +262,12 @@ impl endpoint_func for endpoint {
                 reschedule_required();
             }
         }
+        Ok(())
     }
 
     /// Send an IPC to the endpoint, if the endpoint is idle or send, the tcb will be blocked immediately
-    /// , otherwise the thread will do ipc transfer to the destination thread(queue head)
+    /// , otherwise the thread will do ipc transfer to the destination thread(queue head).
+    /// Returns what happened to the message, see `IpcOutcome`.
     /// * `src_thread` - The source thread to send the IPC
     /// * `blocking` - If the IPC is blocking
     /// * `do_call` - If the IPC is a call
@@ -247,8 +283,25 @@ impl endpoint_func for endpoint {
         can_grant: bool,
         badge: usize,
         can_grant_reply: bool,
-    ) {
-        match self.get_ep_state() {
+    ) -> IpcResult<IpcOutcome> {
+        #[cfg(feature = "deadlock_detection")]
+        if blocking && do_call && !crate::wait_graph::check_call(src_thread, self.get_ptr())? {
+            return Ok(IpcOutcome::DeadlockFaultRaised);
+        }
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::send_ipc");
+        #[cfg(feature = "kernel_services")]
+        if let Some(outcome) = crate::kernel_service::serve(
+            self,
+            src_thread,
+            do_call,
+            can_grant,
+            badge,
+            can_grant_reply,
+        ) {
+            return outcome;
+        }
+        Ok(match self.get_ep_state()? {
             EPState::Idle | EPState::Send => {
                 if blocking {
                     src_thread
@@ -271,34 +324,79 @@ impl endpoint_func for endpoint {
                     queue.ep_append(src_thread);
                     self.set_state(EPState::Send as u64);
                     self.set_queue(&queue);
+                    ipc_trace!(Block, Blocked, self.get_ptr(), src_thread.get_ptr(), badge: badge);
+                    ipc_stats!(endpoint_enqueued(
+                        self.get_ptr(),
+                        src_thread.get_ptr(),
+                        true
+                    ));
+                    IpcOutcome::BlockedOnEndpoint(self.handle())
+                } else {
+                    ipc_trace!(Send, Dropped, self.get_ptr(), src_thread.get_ptr(), badge: badge);
+                    IpcOutcome::Dropped
                 }
             }
 
             EPState::Recv => {
+                check_transferable_fault(src_thread)?;
                 let mut queue = self.get_queue();
-                let op_dest_thread = convert_to_option_mut_type_ref::<tcb_t>(queue.head);
-                assert!(op_dest_thread.is_some());
-                let dest_thread = op_dest_thread.unwrap();
+                let dest_thread =
+                    TcbRef::from_raw(queue.head)
+                        .get()
+                        .ok_or(IpcError::CorruptedObjectState {
+                            object: self.get_ptr(),
+                        })?;
+                #[cfg(feature = "domain_isolation")]
+                crate::domain::check_cross_domain(src_thread, dest_thread)?;
                 queue.ep_dequeue(dest_thread);
+                ipc_stats!(endpoint_dequeued(self.get_ptr(), dest_thread.get_ptr()));
+                ipc_stats!(endpoint_delivered(self.get_ptr(), do_call));
                 self.set_queue(&queue);
                 if queue.empty() {
                     self.set_state(EPState::Idle as u64);
                 }
-                src_thread.do_ipc_transfer(dest_thread, Some(self), badge, can_grant);
+                src_thread.do_ipc_transfer(dest_thread, Some(self), badge, can_grant)?;
+                let mut transfer = IpcTransfer::new(src_thread, dest_thread, badge);
+                ipc_trace!(
+                    Send,
+                    Delivered,
+                    self.get_ptr(),
+                    src_thread.get_ptr(),
+                    peer: dest_thread.get_ptr(),
+                    badge: badge,
+                    label: crate::trace::received_info(dest_thread).get_label(),
+                    length: crate::trace::received_info(dest_thread).get_length(),
+                );
                 let reply_can_grant = dest_thread.tcbState.get_blockingIPCCanGrant() != 0;
                 set_thread_state(dest_thread, ThreadState::ThreadStateRunning);
                 possible_switch_to(dest_thread);
+                ipc_trace!(Wakeup, Delivered, self.get_ptr(), dest_thread.get_ptr());
                 if do_call {
                     if can_grant || can_grant_reply {
                         dest_thread.setup_caller_cap(src_thread, reply_can_grant);
+                        transfer.caller_cap = true;
+                        ipc_wait_graph!(record_call(src_thread.get_ptr(), dest_thread.get_ptr()));
                     } else {
                         set_thread_state(src_thread, ThreadState::ThreadStateInactive);
                     }
                 }
+                IpcOutcome::Transferred(transfer)
             }
-        }
+        })
     }
-    // TODO: MCS
+    /// Send an IPC to the endpoint, like the non-MCS `send_ipc`. A call pushes the reply object
+    /// of the receiver on the call stack of the caller, which donates the scheduling context of
+    /// the caller to a passive receiver if `canDonate` is set. A plain send donates it only if
+    /// the receiver has none.
+    /// # Arguments
+    /// * `src_thread` - The source thread to send the IPC
+    /// * `blocking` - If the IPC is blocking
+    /// * `do_call` - If the IPC is a call
+    /// * `can_grant` - If the IPC can grant
+    /// * `badge` - The badge of the IPC
+    /// * `can_grant_reply` - If the IPC can grant the reply
+    /// * `canDonate` - If the scheduling context of the sender may be donated, false for faults
+    ///   sent by the kernel
     #[cfg(feature = "kernel_mcs")]
     fn send_ipc(
         &mut self,
@@ -309,8 +407,25 @@ impl endpoint_func for endpoint {
         badge: usize,
         can_grant_reply: bool,
         canDonate: bool,
-    ) {
-        match self.get_ep_state() {
+    ) -> IpcResult<IpcOutcome> {
+        #[cfg(feature = "deadlock_detection")]
+        if blocking && do_call && !crate::wait_graph::check_call(src_thread, self.get_ptr())? {
+            return Ok(IpcOutcome::DeadlockFaultRaised);
+        }
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::send_ipc");
+        #[cfg(feature = "kernel_services")]
+        if let Some(outcome) = crate::kernel_service::serve(
+            self,
+            src_thread,
+            do_call,
+            can_grant,
+            badge,
+            can_grant_reply,
+        ) {
+            return outcome;
+        }
+        Ok(match self.get_ep_state()? {
             EPState::Idle | EPState::Send => {
                 if blocking {
                     src_thread
@@ -333,75 +448,128 @@ impl endpoint_func for endpoint {
                     queue.ep_append(src_thread);
                     self.set_state(EPState::Send as u64);
                     self.set_queue(&queue);
+                    ipc_trace!(Block, Blocked, self.get_ptr(), src_thread.get_ptr(), badge: badge);
+                    ipc_stats!(endpoint_enqueued(
+                        self.get_ptr(),
+                        src_thread.get_ptr(),
+                        true
+                    ));
+                    IpcOutcome::BlockedOnEndpoint(self.handle())
+                } else {
+                    ipc_trace!(Send, Dropped, self.get_ptr(), src_thread.get_ptr(), badge: badge);
+                    IpcOutcome::Dropped
                 }
             }
 
             EPState::Recv => {
+                check_transferable_fault(src_thread)?;
                 let mut queue = self.get_queue();
-                let op_dest_thread = convert_to_option_mut_type_ref::<tcb_t>(queue.head);
-                assert!(op_dest_thread.is_some());
-                let dest_thread = op_dest_thread.unwrap();
+                let dest_thread =
+                    TcbRef::from_raw(queue.head)
+                        .get()
+                        .ok_or(IpcError::CorruptedObjectState {
+                            object: self.get_ptr(),
+                        })?;
+                #[cfg(feature = "domain_isolation")]
+                crate::domain::check_cross_domain(src_thread, dest_thread)?;
                 queue.ep_dequeue(dest_thread);
+                ipc_stats!(endpoint_dequeued(self.get_ptr(), dest_thread.get_ptr()));
+                ipc_stats!(endpoint_delivered(self.get_ptr(), do_call));
                 self.set_queue(&queue);
                 if queue.empty() {
                     self.set_state(EPState::Idle as u64);
                 }
-                src_thread.do_ipc_transfer(dest_thread, Some(self), badge, can_grant);
+                src_thread.do_ipc_transfer(dest_thread, Some(self), badge, can_grant)?;
+                let mut transfer = IpcTransfer::new(src_thread, dest_thread, badge);
+                ipc_trace!(
+                    Send,
+                    Delivered,
+                    self.get_ptr(),
+                    src_thread.get_ptr(),
+                    peer: dest_thread.get_ptr(),
+                    badge: badge,
+                    label: crate::trace::received_info(dest_thread).get_label(),
+                    length: crate::trace::received_info(dest_thread).get_length(),
+                );
 
-                let replyptr = dest_thread.tcbState.get_replyObject() as usize;
-                if replyptr != 0 {
-                    convert_to_mut_type_ref::<reply_t>(replyptr).unlink(dest_thread);
+                let src_sc = src_thread.sched_context();
+                let reply = dest_thread.reply_object();
+                if let Some(reply) = reply.get() {
+                    reply.unlink(dest_thread);
                 }
                 if do_call || src_thread.tcbFault.get_tag() != seL4_Fault_NullFault {
-                    if replyptr != 0 && (can_grant || can_grant_reply) {
-                        convert_to_mut_type_ref::<reply_t>(replyptr).push(
-                            src_thread,
-                            dest_thread,
-                            canDonate,
-                        );
-                    } else {
-                        set_thread_state(dest_thread, ThreadState::ThreadStateInactive);
+                    match reply.get() {
+                        Some(reply) if can_grant || can_grant_reply => {
+                            reply.push(src_thread, dest_thread, canDonate);
+                            transfer.caller_cap = true;
+                            ipc_wait_graph!(record_call(
+                                src_thread.get_ptr(),
+                                dest_thread.get_ptr()
+                            ));
+                        }
+                        _ => set_thread_state(src_thread, ThreadState::ThreadStateInactive),
+                    }
+                } else if canDonate && dest_thread.sched_context().is_null() {
+                    if let Some(sc) = src_thread.sched_context().get() {
+                        sc.sched_context_donate(dest_thread);
                     }
-                } else if canDonate && dest_thread.tcbSchedContext == 0 {
-                    convert_to_mut_type_ref::<sched_context_t>(src_thread.tcbSchedContext)
-                        .sched_context_donate(dest_thread);
                 }
+                transfer.donated = !src_sc.is_null() && dest_thread.sched_context() == src_sc;
 
-                assert!(
-                    dest_thread.tcbSchedContext == 0
-                        || convert_to_mut_type_ref::<sched_context_t>(dest_thread.tcbSchedContext)
-                            .refill_sufficient(0)
-                );
-                assert!(
-                    dest_thread.tcbSchedContext == 0
-                        || convert_to_mut_type_ref::<sched_context_t>(dest_thread.tcbSchedContext)
-                            .refill_ready()
-                );
                 set_thread_state(dest_thread, ThreadState::ThreadStateRunning);
-                if let Some(sc) =
-                    convert_to_option_mut_type_ref::<sched_context_t>(dest_thread.tcbSchedContext)
-                {
+                if let Some(sc) = dest_thread.sched_context().get() {
                     if sc.sc_sporadic() && dest_thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
                         sc.refill_unblock_check();
                     }
                 }
+                // The budget of the caller was checked on kernel entry and the receiver runs
+                // on it now, or on its own which was ready when it blocked. Otherwise the
+                // message is delivered but the receiver is not switched to.
+                if dest_thread
+                    .sched_context()
+                    .get()
+                    .is_some_and(|sc| !sc.refill_sufficient(0) || !sc.refill_ready())
+                {
+                    return Err(IpcError::ImpossibleThreadState {
+                        thread: dest_thread.handle(),
+                    });
+                }
                 possible_switch_to(dest_thread);
+                ipc_trace!(Wakeup, Delivered, self.get_ptr(), dest_thread.get_ptr());
+                IpcOutcome::Transferred(transfer)
             }
-        }
+        })
     }
 
     /// Receive an IPC from the endpoint, if the endpoint is idle or recv, the tcb will be blocked immediately
-    /// , otherwise the thread will be transferred from the src thread(queue head)
+    /// , otherwise the thread will be transferred from the src thread(queue head).
+    /// Returns what the thread received, see `IpcOutcome`.
     /// # Arguments
     /// * `thread` - The thread to receive the IPC
     /// * `is_blocking` - If the IPC is blocking
     /// * `grant` - If the IPC can grant
     #[cfg(not(feature = "kernel_mcs"))]
-    fn receive_ipc(&mut self, thread: &mut tcb_t, is_blocking: bool, grant: bool) {
+    fn receive_ipc(
+        &mut self,
+        thread: &mut tcb_t,
+        is_blocking: bool,
+        grant: bool,
+    ) -> IpcResult<IpcOutcome> {
+        ipc_lock!(lock_objects(self.get_ptr(), thread.tcbBoundNotification));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::receive_ipc");
         if thread.complete_signal() {
-            return;
+            ipc_trace!(
+                Receive,
+                Delivered,
+                thread.tcbBoundNotification,
+                thread.get_ptr()
+            );
+            return Ok(IpcOutcome::SignalReceived {
+                badge: thread.tcbArch.get_register(ArchReg::Badge),
+            });
         }
-        match self.get_ep_state() {
+        ipc_wait_graph!(record_server(self.get_ptr(), thread.get_ptr()));
+        Ok(match self.get_ep_state()? {
             EPState::Idle | EPState::Recv => {
                 if is_blocking {
                     thread.tcbState.set_blockingObject(self.get_ptr() as u64);
@@ -411,68 +579,131 @@ impl endpoint_func for endpoint {
                     queue.ep_append(thread);
                     self.set_state(EPState::Recv as u64);
                     self.set_queue(&queue);
+                    ipc_trace!(Block, Blocked, self.get_ptr(), thread.get_ptr());
+                    ipc_stats!(endpoint_enqueued(self.get_ptr(), thread.get_ptr(), false));
+                    IpcOutcome::BlockedOnEndpoint(self.handle())
                 } else {
                     // NBReceive failed
-                    thread.tcbArch.set_register(ArchReg::Badge, 0);
+                    thread
+                        .tcbArch
+                        .set_register(ArchReg::Badge, NO_MESSAGE_BADGE);
+                    ipc_trace!(Receive, Dropped, self.get_ptr(), thread.get_ptr());
+                    IpcOutcome::Dropped
                 }
             }
             EPState::Send => {
                 let mut queue = self.get_queue();
-                assert!(!queue.empty());
-                let sender = convert_to_mut_type_ref::<tcb_t>(queue.head);
+                let sender =
+                    TcbRef::from_raw(queue.head)
+                        .get()
+                        .ok_or(IpcError::CorruptedObjectState {
+                            object: self.get_ptr(),
+                        })?;
+                check_transferable_fault(sender)?;
+                #[cfg(feature = "domain_isolation")]
+                let cross_domain = crate::domain::check_cross_domain(sender, thread);
                 queue.ep_dequeue(sender);
+                ipc_stats!(endpoint_dequeued(self.get_ptr(), sender.get_ptr()));
                 self.set_queue(&queue);
                 if queue.empty() {
                     self.set_state(EPState::Idle as u64);
                 }
+                // A forbidden sender left at the head would block the senders behind it.
+                #[cfg(feature = "domain_isolation")]
+                if let Err(error) = cross_domain {
+                    crate::domain::cancel_forbidden_ipc(sender);
+                    ipc_trace!(Cancel, Cancelled, self.get_ptr(), sender.get_ptr(), peer: thread.get_ptr());
+                    return Err(error);
+                }
                 let badge = sender.tcbState.get_blockingIPCBadge() as usize;
                 let can_grant = sender.tcbState.get_blockingIPCCanGrant() != 0;
                 let can_grant_reply = sender.tcbState.get_blockingIPCCanGrantReply() != 0;
-                sender.do_ipc_transfer(thread, Some(self), badge, can_grant);
+                sender.do_ipc_transfer(thread, Some(self), badge, can_grant)?;
+                let mut transfer = IpcTransfer::new(sender, thread, badge);
+                ipc_trace!(
+                    Receive,
+                    Delivered,
+                    self.get_ptr(),
+                    thread.get_ptr(),
+                    peer: sender.get_ptr(),
+                    badge: badge,
+                    label: crate::trace::received_info(thread).get_label(),
+                    length: crate::trace::received_info(thread).get_length(),
+                );
                 let do_call = sender.tcbState.get_blockingIPCIsCall() != 0;
+                ipc_stats!(endpoint_delivered(self.get_ptr(), do_call));
                 if do_call {
                     if can_grant || can_grant_reply {
                         thread.setup_caller_cap(sender, grant);
+                        transfer.caller_cap = true;
+                        ipc_wait_graph!(record_call(sender.get_ptr(), thread.get_ptr()));
                     } else {
                         set_thread_state(sender, ThreadState::ThreadStateInactive);
                     }
                 } else {
                     set_thread_state(sender, ThreadState::ThreadStateRunning);
                     possible_switch_to(sender);
+                    ipc_trace!(Wakeup, Delivered, self.get_ptr(), sender.get_ptr());
                 }
+                IpcOutcome::Transferred(transfer)
             }
-        }
+        })
     }
-    //TODO: MCS
+    /// Receive an IPC from the endpoint, like the non-MCS `receive_ipc`. The receiver blocks
+    /// with its reply object, a caller found on the queue is pushed on it and donates its
+    /// scheduling context unless it is sending a timeout fault. A reply object still holding
+    /// the reply of an earlier call cancels that caller first.
+    /// # Arguments
+    /// * `thread` - The thread to receive the IPC
+    /// * `is_blocking` - If the IPC is blocking
+    /// * `Option_reply_cap` - The reply object to receive with, if any
     #[cfg(feature = "kernel_mcs")]
     fn receive_ipc(
         &mut self,
         thread: &mut tcb_t,
         is_blocking: bool,
         Option_reply_cap: Option<&mut cap_reply_cap>,
-    ) {
+    ) -> IpcResult<IpcOutcome> {
         use core::intrinsics::unlikely;
         use log::debug;
-        use sel4_common::structures_gen::{notification_t, seL4_Fault_tag};
+        use sel4_common::structures_gen::seL4_Fault_tag;
 
         use crate::notification_func;
-        let mut replyptr: usize = 0;
+        let mut reply = ReplyRef::NULL;
         if let Some(reply_cap_data) = Option_reply_cap {
-            replyptr = reply_cap_data.get_capReplyPtr() as usize;
-            let reply = convert_to_mut_type_ref::<reply_t>(replyptr);
-            if unlikely(reply.replyTCB != 0 && reply.replyTCB != thread.get_ptr()) {
-                debug!("Reply object already has unexecuted reply!");
-                convert_to_mut_type_ref::<tcb_t>(reply.replyTCB as usize).cancel_ipc();
+            reply = ReplyRef::from_raw(reply_cap_data.get_capReplyPtr() as usize);
+            if let Some(caller) = reply
+                .get()
+                .and_then(|reply| TcbRef::from_raw(reply.replyTCB).get())
+            {
+                if unlikely(caller.get_ptr() != thread.get_ptr()) {
+                    debug!("Reply object already has unexecuted reply!");
+                    caller.cancel_ipc()?;
+                }
             }
         }
+        // Cancelling the stale reply locks the object its caller is blocked on, so the
+        // endpoint is locked only once that is done.
+        ipc_lock!(lock_objects(self.get_ptr(), thread.tcbBoundNotification));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::receive_ipc");
         if thread.complete_signal() {
-            return;
+            ipc_trace!(
+                Receive,
+                Delivered,
+                thread.tcbBoundNotification,
+                thread.get_ptr()
+            );
+            return Ok(IpcOutcome::SignalReceived {
+                badge: thread.tcbArch.get_register(ArchReg::Badge),
+            });
         }
-        if thread.tcbBoundNotification != 0 && is_blocking {
-            convert_to_mut_type_ref::<notification_t>(thread.tcbBoundNotification)
-                .maybe_return_sched_context(thread);
+        if is_blocking {
+            if let Some(ntfn) = thread.bound_notification().get() {
+                ntfn.maybe_return_sched_context(thread);
+            }
         }
-        match self.get_ep_state() {
+        ipc_wait_graph!(record_server(self.get_ptr(), thread.get_ptr()));
+        Ok(match self.get_ep_state()? {
             EPState::Idle | EPState::Recv => {
                 if is_blocking {
                     thread
@@ -480,69 +711,118 @@ impl endpoint_func for endpoint {
                         .set_tsType(ThreadState::ThreadStateBlockedOnReceive as u64);
                     thread.tcbState.set_blockingObject(self.get_ptr() as u64);
                     // MCS
-                    thread.tcbState.set_replyObject(replyptr as u64);
-                    if replyptr != 0 {
-                        convert_to_mut_type_ref::<reply_t>(replyptr).replyTCB = thread.get_ptr();
+                    thread.tcbState.set_replyObject(reply.raw() as u64);
+                    if let Some(reply) = reply.get() {
+                        reply.replyTCB = thread.get_ptr();
                     }
                     schedule_tcb(&thread);
                     let mut queue = self.get_queue();
                     queue.ep_append(thread);
                     self.set_state(EPState::Recv as u64);
                     self.set_queue(&queue);
+                    ipc_trace!(Block, Blocked, self.get_ptr(), thread.get_ptr());
+                    ipc_stats!(endpoint_enqueued(self.get_ptr(), thread.get_ptr(), false));
+                    IpcOutcome::BlockedOnEndpoint(self.handle())
                 } else {
                     // NBReceive failed
-                    thread.tcbArch.set_register(ArchReg::Badge, 0);
+                    thread
+                        .tcbArch
+                        .set_register(ArchReg::Badge, NO_MESSAGE_BADGE);
+                    ipc_trace!(Receive, Dropped, self.get_ptr(), thread.get_ptr());
+                    IpcOutcome::Dropped
                 }
             }
             EPState::Send => {
                 let mut queue = self.get_queue();
-                assert!(!queue.empty());
-                let sender = convert_to_mut_type_ref::<tcb_t>(queue.head);
+                let sender =
+                    TcbRef::from_raw(queue.head)
+                        .get()
+                        .ok_or(IpcError::CorruptedObjectState {
+                            object: self.get_ptr(),
+                        })?;
+                check_transferable_fault(sender)?;
+                #[cfg(feature = "domain_isolation")]
+                let cross_domain = crate::domain::check_cross_domain(sender, thread);
                 queue.ep_dequeue(sender);
+                ipc_stats!(endpoint_dequeued(self.get_ptr(), sender.get_ptr()));
                 self.set_queue(&queue);
                 if queue.empty() {
                     self.set_state(EPState::Idle as u64);
                 }
+                // A forbidden sender left at the head would block the senders behind it.
+                #[cfg(feature = "domain_isolation")]
+                if let Err(error) = cross_domain {
+                    crate::domain::cancel_forbidden_ipc(sender);
+                    ipc_trace!(Cancel, Cancelled, self.get_ptr(), sender.get_ptr(), peer: thread.get_ptr());
+                    return Err(error);
+                }
                 let badge = sender.tcbState.get_blockingIPCBadge() as usize;
                 let can_grant = sender.tcbState.get_blockingIPCCanGrant() != 0;
                 let can_grant_reply = sender.tcbState.get_blockingIPCCanGrantReply() != 0;
-                sender.do_ipc_transfer(thread, Some(self), badge, can_grant);
+                sender.do_ipc_transfer(thread, Some(self), badge, can_grant)?;
+                let mut transfer = IpcTransfer::new(sender, thread, badge);
+                ipc_trace!(
+                    Receive,
+                    Delivered,
+                    self.get_ptr(),
+                    thread.get_ptr(),
+                    peer: sender.get_ptr(),
+                    badge: badge,
+                    label: crate::trace::received_info(thread).get_label(),
+                    length: crate::trace::received_info(thread).get_length(),
+                );
                 let do_call = sender.tcbState.get_blockingIPCIsCall() != 0;
+                ipc_stats!(endpoint_delivered(self.get_ptr(), do_call));
                 // MCS
-                if let Some(sc) =
-                    convert_to_option_mut_type_ref::<sched_context_t>(sender.tcbSchedContext)
-                {
+                if let Some(sc) = sender.sched_context().get() {
                     if sc.sc_sporadic() {
-                        assert!(sender.tcbSchedContext != NODE_STATE!(ksCurSC));
+                        // The sender was blocked, so its scheduling context is not the
+                        // current one, skip the refill if it somehow is.
                         if sender.tcbSchedContext != NODE_STATE!(ksCurSC) {
                             sc.refill_unblock_check();
                         }
                     }
                 }
                 if do_call || sender.tcbFault.get_tag() != seL4_Fault_tag::seL4_Fault_NullFault {
-                    if (can_grant || can_grant_reply) && replyptr != 0 {
-                        let canDonate = sender.tcbSchedContext != 0
-                            && sender.tcbFault.get_tag() != seL4_Fault_tag::seL4_Fault_Timeout;
-                        convert_to_mut_type_ref::<reply_t>(replyptr)
-                            .push(sender, thread, canDonate);
-                    } else {
-                        set_thread_state(sender, ThreadState::ThreadStateInactive);
+                    match reply.get() {
+                        Some(reply) if can_grant || can_grant_reply => {
+                            let canDonate = !sender.sched_context().is_null()
+                                && sender.tcbFault.get_tag() != seL4_Fault_tag::seL4_Fault_Timeout;
+                            let sender_sc = sender.sched_context();
+                            reply.push(sender, thread, canDonate);
+                            transfer.caller_cap = true;
+                            transfer.donated =
+                                !sender_sc.is_null() && thread.sched_context() == sender_sc;
+                            ipc_wait_graph!(record_call(sender.get_ptr(), thread.get_ptr()));
+                        }
+                        _ => set_thread_state(sender, ThreadState::ThreadStateInactive),
                     }
                 } else {
                     set_thread_state(sender, ThreadState::ThreadStateRunning);
+                    // A sender blocked with its scheduling context only after its budget was
+                    // checked. Otherwise the message is delivered but the sender is not
+                    // switched to.
+                    if sender
+                        .sched_context()
+                        .get()
+                        .is_some_and(|sc| !sc.refill_sufficient(0))
+                    {
+                        return Err(IpcError::ImpossibleThreadState {
+                            thread: sender.handle(),
+                        });
+                    }
                     possible_switch_to(sender);
-                    assert!(
-                        sender.tcbSchedContext == 0
-                            || convert_to_mut_type_ref::<sched_context_t>(sender.tcbSchedContext)
-                                .refill_sufficient(0)
-                    );
+                    ipc_trace!(Wakeup, Delivered, self.get_ptr(), sender.get_ptr());
                 }
+                IpcOutcome::Transferred(transfer)
             }
-        }
+        })
     }
     #[cfg(feature = "kernel_mcs")]
     #[no_mangle]
     fn reorder_ep(&mut self, thread: &mut tcb_t) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(Endpoint, self.get_ptr(), "endpoint::reorder_ep");
         let mut queue = self.get_queue();
         queue.ep_dequeue(thread);
         queue.ep_append(thread);
@@ -0,0 +1,28 @@
+use crate::handle::{EndpointRef, TcbRef};
+
+/// The error of an IPC operation. None of them happen while the kernel objects are consistent,
+/// they are returned instead of halting so the kernel can log the problem, suspend the
+/// offending thread and carry on.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum IpcError {
+    /// The state or the queue of an endpoint or notification is corrupted, the object may be
+    /// either so it stays an address
+    CorruptedObjectState { object: usize },
+    /// The fault of a thread has a tag the fault protocol does not know
+    InvalidFaultTag { thread: TcbRef, tag: u64 },
+    /// A thread is not in the state the operation requires
+    ImpossibleThreadState { thread: TcbRef },
+    /// The operation needs the IPC buffer of a thread, but it has none
+    MissingIpcBuffer { thread: TcbRef },
+    /// A blocking call would close a cycle in the wait-for graph and the `DeadlockPolicy` fails
+    /// it, see `wait_graph.rs`
+    DeadlockDetected {
+        thread: TcbRef,
+        endpoint: EndpointRef,
+    },
+    /// IPC between threads of different domains is forbidden, see `domain.rs`
+    CrossDomainIpc { thread: TcbRef, peer: TcbRef },
+}
+
+/// The result of an IPC operation
+pub type IpcResult<T> = Result<T, IpcError>;
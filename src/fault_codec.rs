@@ -0,0 +1,39 @@
+//! The messages of the fault protocol.
+//!
+//! The messages are defined in `sel4_ipc_abi`, so that user-level pagers and supervisors share
+//! them without the kernel crates. They spell out the fault tags and register counts of the
+//! kernel, which are checked here against `sel4_common` at compile time.
+use sel4_common::structures_gen::seL4_Fault_tag;
+use sel4_common::{arch, sel4_config};
+
+pub use sel4_ipc_abi::fault_codec::*;
+
+macro_rules! assert_label {
+    ($fault:ty, $tag:ident) => {
+        const _: () = assert!(<$fault as FaultCodec>::LABEL == seL4_Fault_tag::$tag as u64);
+    };
+}
+
+assert_label!(CapFault, seL4_Fault_CapFault);
+assert_label!(UnknownSyscall, seL4_Fault_UnknownSyscall);
+assert_label!(UserException, seL4_Fault_UserException);
+assert_label!(VmFault, seL4_Fault_VMFault);
+#[cfg(feature = "kernel_mcs")]
+assert_label!(Timeout, seL4_Fault_Timeout);
+#[cfg(feature = "hardware_debug_api")]
+assert_label!(DebugException, seL4_Fault_DebugException);
+#[cfg(feature = "arm_hypervisor_support")]
+assert_label!(VgicMaintenance, seL4_Fault_VGICMaintenance);
+#[cfg(feature = "arm_hypervisor_support")]
+assert_label!(VcpuFault, seL4_Fault_VCPUFault);
+#[cfg(feature = "arm_hypervisor_support")]
+assert_label!(VppiEvent, seL4_Fault_VPPIEvent);
+
+const _: () = assert!(N_SYSCALL_MESSAGE == arch::N_SYSCALL_MESSAGE);
+const _: () = assert!(N_EXCEPTON_MESSAGE == arch::N_EXCEPTON_MESSAGE);
+const _: () = assert!(MESSAGE_ID_SYSCALL == sel4_config::MESSAGE_ID_SYSCALL);
+const _: () = assert!(MESSAGE_ID_EXCEPTION == sel4_config::MESSAGE_ID_EXCEPTION);
+#[cfg(feature = "kernel_mcs")]
+const _: () = assert!(N_TIMEOUT_MESSAGE == arch::N_TIMEOUT_MESSAGE);
+#[cfg(feature = "kernel_mcs")]
+const _: () = assert!(MESSAGE_ID_TIMEOUT_REPLY == sel4_config::MESSAGE_ID_TIMEOUT_REPLY);
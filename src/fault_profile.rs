@@ -0,0 +1,169 @@
+//! Per-thread fault message profiles, enabled by the `fault_profiles` feature.
+//!
+//! `UnknownSyscall` and `UserException` messages carry a fixed, arch-defined subset of the
+//! registers of the faulting thread. A thread with a `FaultProfile` sends the registers of its
+//! profile instead, e.g. the full integer register file and FP state for a syscall emulator
+//! of a foreign ABI, followed by the usual words of the fault. Messages longer than the
+//! message registers spill into the IPC buffer of the receiver, like any long message, and are
+//! cut short if it has none. The reply to such a fault writes back the same registers, in the
+//! same order.
+//!
+//! Registers are read and written with the hooks set by `set_profile_hooks`, which number
+//! them as the kernel likes, e.g. `seL4_UserContext` order followed by the FP registers. Until
+//! they are set, profiles are ignored.
+use core::ptr::addr_of_mut;
+
+use sel4_task::tcb_t;
+
+use crate::handle::{tcb_handle_func, TcbRef};
+use crate::object_table::ObjectTable;
+use crate::transfer::get_mr;
+
+/// Number of threads that can have a profile at once
+const MAX_PROFILED_THREADS: usize = 64;
+/// Most registers in a profile
+pub const MAX_PROFILE_REGISTERS: usize = 96;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The registers sent in the `UnknownSyscall` and `UserException` messages of a thread
+pub struct FaultProfile {
+    registers: [u8; MAX_PROFILE_REGISTERS],
+    count: usize,
+}
+
+impl FaultProfile {
+    /// Build a profile of `registers`, in message order. Returns `None` if there are more than
+    /// `MAX_PROFILE_REGISTERS`.
+    pub fn new(registers: &[u8]) -> Option<Self> {
+        let mut profile = FaultProfile {
+            registers: [0; MAX_PROFILE_REGISTERS],
+            count: registers.len(),
+        };
+        profile
+            .registers
+            .get_mut(..registers.len())?
+            .copy_from_slice(registers);
+        Some(profile)
+    }
+
+    /// The registers of the profile, in message order
+    pub fn registers(&self) -> &[u8] {
+        &self.registers[..self.count]
+    }
+
+    /// Message register of the syscall number of an `UnknownSyscall`
+    pub fn syscall_index(&self) -> usize {
+        self.count
+    }
+
+    /// Message register of the exception number of a `UserException`, the code follows it
+    pub fn exception_number_index(&self) -> usize {
+        self.count
+    }
+}
+
+#[derive(Clone, Copy)]
+/// The arch services that read and write the registers of a profile
+pub struct ProfileHooks {
+    pub read_register: fn(&tcb_t, usize) -> usize,
+    /// Must sanitise the value like `sanitiseRegister` in seL4
+    pub write_register: fn(&mut tcb_t, usize, usize),
+}
+
+struct FaultProfiles {
+    profiles: ObjectTable<FaultProfile, MAX_PROFILED_THREADS>,
+    hooks: Option<ProfileHooks>,
+}
+
+static mut FAULT_PROFILES: FaultProfiles = FaultProfiles {
+    profiles: ObjectTable::new(FaultProfile {
+        registers: [0; MAX_PROFILE_REGISTERS],
+        count: 0,
+    }),
+    hooks: None,
+};
+
+#[inline]
+fn profiles() -> &'static mut FaultProfiles {
+    unsafe { &mut *addr_of_mut!(FAULT_PROFILES) }
+}
+
+/// Profiles are looked up by cores holding unrelated object locks, so with per-object locking
+/// the table has a lock of its own. It is not held while the registers are copied.
+#[cfg(feature = "fine_grained_locking")]
+static FAULT_PROFILE_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Set the arch services used to read and write the registers of a profile
+pub fn set_profile_hooks(hooks: ProfileHooks) {
+    ipc_lock!(lock_leaf(&FAULT_PROFILE_LOCK));
+    profiles().hooks = Some(hooks);
+}
+
+/// Get the arch services reading and writing the registers of a profile
+fn profile_hooks() -> Option<ProfileHooks> {
+    ipc_lock!(lock_leaf(&FAULT_PROFILE_LOCK));
+    profiles().hooks
+}
+
+/// Set the fault message profile of `thread`, or go back to the registers of the
+/// architecture with `None`. Returns false if too many threads have profiles.
+pub fn set_fault_profile(thread: TcbRef, profile: Option<FaultProfile>) -> bool {
+    ipc_lock!(lock_leaf(&FAULT_PROFILE_LOCK));
+    let table = &mut profiles().profiles;
+    match profile {
+        Some(profile) => match table.get_or_insert(thread.raw(), profile) {
+            Some(entry) => {
+                *entry = profile;
+                true
+            }
+            None => false,
+        },
+        None => {
+            table.remove(thread.raw());
+            true
+        }
+    }
+}
+
+/// Get the fault message profile of `thread`
+pub fn fault_profile(thread: TcbRef) -> Option<FaultProfile> {
+    ipc_lock!(lock_leaf(&FAULT_PROFILE_LOCK));
+    profiles().profiles.get(thread.raw()).copied()
+}
+
+/// Forget the profile of a deleted thread
+pub fn forget_fault_profile(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&FAULT_PROFILE_LOCK));
+    profiles().profiles.remove(thread.raw());
+}
+
+/// Copy the registers of the profile of `sender` to the message registers of `receiver`.
+/// Returns the profile, `None` if `sender` has none and the registers of the architecture
+/// must be sent instead.
+pub(crate) fn copy_profile_fault_mrs(sender: &tcb_t, receiver: &mut tcb_t) -> Option<FaultProfile> {
+    let hooks = profile_hooks()?;
+    let profile = fault_profile(sender.handle())?;
+    for (index, register) in profile.registers().iter().enumerate() {
+        receiver.set_mr(index, (hooks.read_register)(sender, *register as usize));
+    }
+    Some(profile)
+}
+
+/// Write back the registers of the profile of `thread` from the reply of `replier` with
+/// `length` message registers. Returns false if `thread` has no profile and the registers of
+/// the architecture must be copied instead.
+pub(crate) fn copy_profile_reply(replier: &tcb_t, thread: &mut tcb_t, length: usize) -> bool {
+    let Some(hooks) = profile_hooks() else {
+        return false;
+    };
+    let Some(profile) = fault_profile(thread.handle()) else {
+        return false;
+    };
+    for (index, register) in profile.registers().iter().enumerate().take(length) {
+        match get_mr(replier, index) {
+            Some(value) => (hooks.write_register)(thread, *register as usize, value),
+            None => break,
+        }
+    }
+    true
+}
@@ -0,0 +1,212 @@
+//! Per-fault-class handler endpoints, enabled by the `fault_routing` feature.
+//!
+//! A thread normally sends every fault to its one fault handler endpoint. With routing, it can
+//! register another endpoint for a class of faults, e.g. VM faults to the pager and unknown
+//! syscalls to an emulation server, and all other faults still go to the fault handler. The
+//! kernel sends faults with `send_fault_ipc`, passing the route of the fault handler cap as
+//! the default. The receiver gets the badge of the route that was taken, so routes that
+//! share an endpoint are told apart by their badges.
+use core::ptr::addr_of_mut;
+
+use sel4_common::structures_gen::{seL4_Fault, seL4_Fault_tag};
+use sel4_task::tcb_t;
+
+use crate::endpoint::endpoint_func;
+use crate::error::IpcResult;
+use crate::handle::{tcb_handle_func, EndpointRef, TcbRef};
+use crate::object_table::ObjectTable;
+use crate::outcome::IpcOutcome;
+
+/// Number of threads that can have routes at once
+const MAX_ROUTED_THREADS: usize = 128;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A class of faults that can be routed to its own endpoint
+pub enum FaultClass {
+    Cap,
+    UnknownSyscall,
+    UserException,
+    Vm,
+    #[cfg(feature = "kernel_mcs")]
+    Timeout,
+    #[cfg(feature = "hardware_debug_api")]
+    Debug,
+    /// VCPU, VGIC maintenance and VPPI faults
+    #[cfg(feature = "arm_hypervisor_support")]
+    Hypervisor,
+}
+
+/// Number of fault classes with every feature enabled
+const N_FAULT_CLASSES: usize = 7;
+
+impl FaultClass {
+    /// Get the class of a fault, `None` if it has none
+    pub fn of(fault: &seL4_Fault) -> Option<Self> {
+        match fault.get_tag() {
+            seL4_Fault_tag::seL4_Fault_CapFault => Some(FaultClass::Cap),
+            seL4_Fault_tag::seL4_Fault_UnknownSyscall => Some(FaultClass::UnknownSyscall),
+            seL4_Fault_tag::seL4_Fault_UserException => Some(FaultClass::UserException),
+            seL4_Fault_tag::seL4_Fault_VMFault => Some(FaultClass::Vm),
+            #[cfg(feature = "kernel_mcs")]
+            seL4_Fault_tag::seL4_Fault_Timeout => Some(FaultClass::Timeout),
+            #[cfg(feature = "hardware_debug_api")]
+            seL4_Fault_tag::seL4_Fault_DebugException => Some(FaultClass::Debug),
+            #[cfg(feature = "arm_hypervisor_support")]
+            seL4_Fault_tag::seL4_Fault_VGICMaintenance
+            | seL4_Fault_tag::seL4_Fault_VCPUFault
+            | seL4_Fault_tag::seL4_Fault_VPPIEvent => Some(FaultClass::Hypervisor),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// Where a fault is sent, with the rights of an endpoint cap
+pub struct FaultRoute {
+    pub endpoint: EndpointRef,
+    /// The badge the handler receives
+    pub badge: usize,
+    pub can_grant: bool,
+    pub can_grant_reply: bool,
+}
+
+impl FaultRoute {
+    /// Check that the route has an endpoint
+    pub fn is_valid(&self) -> bool {
+        !self.endpoint.is_null()
+    }
+}
+
+struct FaultRouting {
+    /// Thread -> its route for each fault class
+    routes: ObjectTable<[Option<FaultRoute>; N_FAULT_CLASSES], MAX_ROUTED_THREADS>,
+}
+
+static mut FAULT_ROUTING: FaultRouting = FaultRouting {
+    routes: ObjectTable::new([None; N_FAULT_CLASSES]),
+};
+
+#[inline]
+fn routing() -> &'static mut FaultRouting {
+    unsafe { &mut *addr_of_mut!(FAULT_ROUTING) }
+}
+
+/// Routes are looked up by cores holding unrelated object locks, so with per-object locking the
+/// table has a lock of its own. It is not held while the fault is sent.
+#[cfg(feature = "fine_grained_locking")]
+static FAULT_ROUTING_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Route the faults of `class` of `thread` to `route`, or back to the fault handler with
+/// `None`. Returns false if too many threads have routes.
+pub fn set_fault_route(thread: TcbRef, class: FaultClass, route: Option<FaultRoute>) -> bool {
+    ipc_lock!(lock_leaf(&FAULT_ROUTING_LOCK));
+    let routes = &mut routing().routes;
+    match route {
+        Some(route) => match routes.get_or_insert(thread.raw(), [None; N_FAULT_CLASSES]) {
+            Some(entry) => {
+                entry[class.index()] = Some(route);
+                true
+            }
+            None => false,
+        },
+        None => {
+            if let Some(entry) = routes.get_mut(thread.raw()) {
+                entry[class.index()] = None;
+                if entry.iter().all(Option::is_none) {
+                    routes.remove(thread.raw());
+                }
+            }
+            true
+        }
+    }
+}
+
+/// Get the route of the faults of `class` of `thread`, `None` if they go to the fault handler
+pub fn fault_route(thread: TcbRef, class: FaultClass) -> Option<FaultRoute> {
+    ipc_lock!(lock_leaf(&FAULT_ROUTING_LOCK));
+    routing()
+        .routes
+        .get(thread.raw())
+        .and_then(|entry| entry[class.index()])
+}
+
+/// Forget the routes of a deleted thread
+pub fn forget_fault_routes(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&FAULT_ROUTING_LOCK));
+    routing().routes.remove(thread.raw());
+}
+
+/// Pick the route of the current fault of `thread`, `default` if its class has no route or
+/// the route has no endpoint. Returns `None` if neither has an endpoint.
+pub(crate) fn route_fault(thread: &tcb_t, default: Option<FaultRoute>) -> Option<FaultRoute> {
+    FaultClass::of(&thread.tcbFault)
+        .and_then(|class| fault_route(thread.handle(), class))
+        .filter(FaultRoute::is_valid)
+        .or(default.filter(FaultRoute::is_valid))
+}
+
+#[cfg(not(feature = "kernel_mcs"))]
+/// Send the fault of `thread` on `route`
+pub(crate) fn send_on_route(thread: &mut tcb_t, route: FaultRoute) -> IpcResult<IpcOutcome> {
+    match route.endpoint.get() {
+        Some(ep) => ep.send_ipc(
+            thread,
+            true,
+            true,
+            route.can_grant,
+            route.badge,
+            route.can_grant_reply,
+        ),
+        None => Ok(IpcOutcome::Dropped),
+    }
+}
+
+#[cfg(feature = "kernel_mcs")]
+/// Send the fault of `thread` on `route`
+pub(crate) fn send_on_route(
+    thread: &mut tcb_t,
+    route: FaultRoute,
+    can_donate: bool,
+) -> IpcResult<IpcOutcome> {
+    match route.endpoint.get() {
+        Some(ep) => ep.send_ipc(
+            thread,
+            true,
+            false,
+            route.can_grant,
+            route.badge,
+            route.can_grant_reply,
+            can_donate,
+        ),
+        None => Ok(IpcOutcome::Dropped),
+    }
+}
+
+#[cfg(not(feature = "kernel_mcs"))]
+/// Send the fault of `thread` to the endpoint of its route, the fault handler described by
+/// `default` if it has none. Returns `IpcOutcome::Dropped` if that endpoint is gone too.
+pub fn send_fault_ipc(thread: &mut tcb_t, default: FaultRoute) -> IpcResult<IpcOutcome> {
+    match route_fault(thread, Some(default)) {
+        Some(route) => send_on_route(thread, route),
+        None => Ok(IpcOutcome::Dropped),
+    }
+}
+
+#[cfg(feature = "kernel_mcs")]
+/// Send the fault of `thread` to the endpoint of its route, the fault handler described by
+/// `default` if it has none. Returns `IpcOutcome::Dropped` if that endpoint is gone too.
+pub fn send_fault_ipc(
+    thread: &mut tcb_t,
+    default: FaultRoute,
+    can_donate: bool,
+) -> IpcResult<IpcOutcome> {
+    match route_fault(thread, Some(default)) {
+        Some(route) => send_on_route(thread, route, can_donate),
+        None => Ok(IpcOutcome::Dropped),
+    }
+}
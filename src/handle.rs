@@ -0,0 +1,173 @@
+//! Typed handles of the kernel objects IPC works with.
+//!
+//! The kernel objects link each other with raw addresses: the object a thread is blocked on,
+//! the next thread in a queue, the bound TCB of a notification. A handle is such an address
+//! together with the kind of object it points to, so an endpoint cannot be passed where a
+//! notification is expected. `NULL` is the handle of no object, like the address 0.
+use core::fmt;
+
+use sel4_common::structures_gen::{endpoint, notification};
+use sel4_common::utils::convert_to_option_mut_type_ref;
+#[cfg(feature = "kernel_mcs")]
+use sel4_task::{reply::reply_t, sched_context::sched_context_t};
+use sel4_task::{tcb_t, ThreadState};
+
+/// The operations common to all handles
+pub trait Handle: Copy {
+    /// Address of the object, 0 for `NULL`
+    fn raw(self) -> usize;
+}
+
+macro_rules! object_handle {
+    ($(#[$doc:meta])* $name:ident, $object:ty) => {
+        $(#[$doc])*
+        #[repr(transparent)]
+        #[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+        pub struct $name(usize);
+
+        impl $name {
+            /// The handle of no object
+            pub const NULL: Self = $name(0);
+
+            /// The handle of the object at `ptr`, `NULL` for 0. The caller vouches for the kind of
+            /// the object.
+            #[inline]
+            pub const fn from_raw(ptr: usize) -> Self {
+                $name(ptr)
+            }
+
+            /// The handle of `object`
+            #[inline]
+            pub fn of(object: &$object) -> Self {
+                $name(object as *const $object as usize)
+            }
+
+            /// Address of the object, 0 for `NULL`
+            #[inline]
+            pub const fn raw(self) -> usize {
+                self.0
+            }
+
+            #[inline]
+            pub const fn is_null(self) -> bool {
+                self.0 == 0
+            }
+
+            /// The object, `None` for `NULL`
+            #[inline]
+            pub fn get(self) -> Option<&'static mut $object> {
+                convert_to_option_mut_type_ref::<$object>(self.0)
+            }
+        }
+
+        impl Handle for $name {
+            #[inline]
+            fn raw(self) -> usize {
+                self.0
+            }
+        }
+
+        impl fmt::LowerHex for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+object_handle!(
+    /// A handle of a thread
+    TcbRef,
+    tcb_t
+);
+object_handle!(
+    /// A handle of an endpoint
+    EndpointRef,
+    endpoint
+);
+object_handle!(
+    /// A handle of a notification
+    NotificationRef,
+    notification
+);
+#[cfg(feature = "kernel_mcs")]
+object_handle!(
+    /// A handle of a reply object
+    ReplyRef,
+    reply_t
+);
+#[cfg(feature = "kernel_mcs")]
+object_handle!(
+    /// A handle of a scheduling context
+    SchedContextRef,
+    sched_context_t
+);
+
+/// Typed access to the links of a thread
+pub trait tcb_handle_func {
+    fn handle(&self) -> TcbRef;
+    fn blocking_endpoint(&self) -> EndpointRef;
+    fn blocking_notification(&self) -> NotificationRef;
+    fn ep_next(&self) -> TcbRef;
+    fn bound_notification(&self) -> NotificationRef;
+    #[cfg(feature = "kernel_mcs")]
+    fn sched_context(&self) -> SchedContextRef;
+    #[cfg(feature = "kernel_mcs")]
+    fn reply_object(&self) -> ReplyRef;
+}
+
+impl tcb_handle_func for tcb_t {
+    #[inline]
+    /// Get the handle of the thread
+    fn handle(&self) -> TcbRef {
+        TcbRef::of(self)
+    }
+
+    #[inline]
+    /// Get the endpoint the thread is blocked sending or receiving on, `NULL` if it is not
+    fn blocking_endpoint(&self) -> EndpointRef {
+        match self.get_state() {
+            ThreadState::ThreadStateBlockedOnSend | ThreadState::ThreadStateBlockedOnReceive => {
+                EndpointRef::from_raw(self.tcbState.get_blockingObject() as usize)
+            }
+            _ => EndpointRef::NULL,
+        }
+    }
+
+    #[inline]
+    /// Get the notification the thread is blocked on, `NULL` if it is not
+    fn blocking_notification(&self) -> NotificationRef {
+        match self.get_state() {
+            ThreadState::ThreadStateBlockedOnNotification => {
+                NotificationRef::from_raw(self.tcbState.get_blockingObject() as usize)
+            }
+            _ => NotificationRef::NULL,
+        }
+    }
+
+    #[inline]
+    /// Get the next thread in the queue of the endpoint or notification the thread is on
+    fn ep_next(&self) -> TcbRef {
+        TcbRef::from_raw(self.tcbEPNext)
+    }
+
+    #[inline]
+    /// Get the notification bound to the thread
+    fn bound_notification(&self) -> NotificationRef {
+        NotificationRef::from_raw(self.tcbBoundNotification)
+    }
+
+    #[cfg(feature = "kernel_mcs")]
+    #[inline]
+    /// Get the scheduling context the thread runs on
+    fn sched_context(&self) -> SchedContextRef {
+        SchedContextRef::from_raw(self.tcbSchedContext)
+    }
+
+    #[cfg(feature = "kernel_mcs")]
+    #[inline]
+    /// Get the reply object the thread is blocked with
+    fn reply_object(&self) -> ReplyRef {
+        ReplyRef::from_raw(self.tcbState.get_replyObject() as usize)
+    }
+}
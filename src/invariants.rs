@@ -0,0 +1,274 @@
+//! Runtime consistency checks for IPC objects, enabled by the `ipc_invariants` feature.
+//!
+//! Every public operation of `endpoint_func`, `notification_func` and `Transfer` creates an
+//! `InvariantGuard` which validates the object it works on when the operation starts and again
+//! when it returns, so a corrupted queue is reported by the operation that corrupted it. The
+//! `Transfer` operations between two threads guard the receiver as well as the sender as
+//! `TransferPeer`s: the endpoint operations dequeue the peer before the transfer and set its
+//! state after, so it may still be blocked on an endpoint it is no longer queued on.
+use core::fmt;
+
+use sel4_common::structures_gen::{endpoint, notification};
+use sel4_task::{tcb_t, ThreadState};
+
+use crate::endpoint::{endpoint_func, EPState};
+use crate::notification::{notification_func, NtfnState};
+
+/// Upper bound of the threads walked in one queue, a longer queue is treated as a cycle.
+const MAX_QUEUE_WALK: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
+/// The kind of object an invariant guard watches
+pub(crate) enum ObjectKind {
+    Endpoint,
+    Notification,
+    Thread,
+    /// A thread in a transfer, which need not be in the queue it is blocked on
+    TransferPeer,
+}
+
+/// A broken invariant, naming the offending thread if there is one
+struct Violation {
+    thread: usize,
+    reason: &'static str,
+}
+
+impl Violation {
+    fn new(thread: usize, reason: &'static str) -> Self {
+        Violation { thread, reason }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.thread != 0 {
+            write!(f, "thread {:#x}: {}", self.thread, self.reason)
+        } else {
+            write!(f, "{}", self.reason)
+        }
+    }
+}
+
+/// Checks an IPC object on creation and on drop, see the `ipc_invariants!` macro
+pub(crate) struct InvariantGuard {
+    kind: ObjectKind,
+    ptr: usize,
+    op: &'static str,
+}
+
+impl InvariantGuard {
+    #[inline]
+    pub(crate) fn new(kind: ObjectKind, ptr: usize, op: &'static str) -> Self {
+        let guard = InvariantGuard { kind, ptr, op };
+        guard.check("entry");
+        guard
+    }
+
+    fn check(&self, when: &str) {
+        let result = match self.kind {
+            ObjectKind::Endpoint => check_endpoint(unsafe { &*(self.ptr as *const endpoint) }),
+            ObjectKind::Notification => {
+                check_notification(unsafe { &*(self.ptr as *const notification) })
+            }
+            ObjectKind::Thread => check_thread(unsafe { &*(self.ptr as *const tcb_t) }, true),
+            ObjectKind::TransferPeer => {
+                check_thread(unsafe { &*(self.ptr as *const tcb_t) }, false)
+            }
+        };
+        if let Err(violation) = result {
+            log::error!(
+                "IPC invariant violated on {} of {}: {:?} {:#x}, {}",
+                when,
+                self.op,
+                self.kind,
+                self.ptr,
+                violation
+            );
+            panic!("IPC invariant violated in {}", self.op);
+        }
+    }
+}
+
+impl Drop for InvariantGuard {
+    #[inline]
+    fn drop(&mut self) {
+        self.check("exit");
+    }
+}
+
+#[inline]
+fn tcb_ref(ptr: usize) -> &'static tcb_t {
+    unsafe { &*(ptr as *const tcb_t) }
+}
+
+/// Walk a doubly-linked `tcbEPNext`/`tcbEPPrev` queue, checking the links, the head and tail,
+/// and that every thread is blocked on `object` in the state `ts_type`.
+fn check_queue(
+    head: usize,
+    tail: usize,
+    object: usize,
+    ts_type: ThreadState,
+) -> Result<(), Violation> {
+    if (head == 0) != (tail == 0) {
+        return Err(Violation::new(0, "only one of queue head and tail is null"));
+    }
+    if head == 0 {
+        return Ok(());
+    }
+    if tcb_ref(head).tcbEPPrev != 0 {
+        return Err(Violation::new(head, "queue head has a previous thread"));
+    }
+    let mut prev = 0;
+    let mut current = head;
+    let mut walked = 0;
+    while current != 0 {
+        let thread = tcb_ref(current);
+        if thread.tcbEPPrev != prev {
+            return Err(Violation::new(
+                current,
+                "tcbEPPrev does not match the queue",
+            ));
+        }
+        if thread.get_state() != ts_type {
+            return Err(Violation::new(
+                current,
+                "queued thread has the wrong tsType",
+            ));
+        }
+        if thread.tcbState.get_blockingObject() as usize != object {
+            return Err(Violation::new(
+                current,
+                "queued thread is blocked on another object",
+            ));
+        }
+        walked += 1;
+        if walked > MAX_QUEUE_WALK {
+            return Err(Violation::new(current, "queue does not terminate"));
+        }
+        prev = current;
+        current = thread.tcbEPNext;
+    }
+    if prev != tail {
+        return Err(Violation::new(prev, "queue tail is not the last thread"));
+    }
+    Ok(())
+}
+
+/// Check if `thread` is linked into the queue starting at `head`, the queue must have been
+/// checked by `check_queue` before.
+fn queue_contains(head: usize, thread: usize) -> bool {
+    let mut current = head;
+    while current != 0 {
+        if current == thread {
+            return true;
+        }
+        current = tcb_ref(current).tcbEPNext;
+    }
+    false
+}
+
+/// Check an endpoint: its queue, and that the state matches the queue
+fn check_endpoint(ep: &endpoint) -> Result<(), Violation> {
+    let queue = ep.get_queue();
+    let state = ep
+        .get_ep_state()
+        .map_err(|_| Violation::new(0, "endpoint has an unknown state"))?;
+    let ts_type = match state {
+        EPState::Idle => {
+            if queue.head != 0 || queue.tail != 0 {
+                return Err(Violation::new(queue.head, "idle endpoint has a queue"));
+            }
+            return Ok(());
+        }
+        EPState::Send => ThreadState::ThreadStateBlockedOnSend,
+        EPState::Recv => ThreadState::ThreadStateBlockedOnReceive,
+    };
+    if queue.head == 0 {
+        return Err(Violation::new(0, "non-idle endpoint has an empty queue"));
+    }
+    check_queue(queue.head, queue.tail, ep.get_ptr(), ts_type)
+}
+
+/// Check a notification: its queue, the state matching the queue and the bound TCB
+fn check_notification(ntfn: &notification) -> Result<(), Violation> {
+    let queue = ntfn.get_queue();
+    let state = ntfn
+        .get_ntfn_state()
+        .map_err(|_| Violation::new(0, "notification has an unknown state"))?;
+    match state {
+        NtfnState::Waiting => {
+            if queue.head == 0 {
+                return Err(Violation::new(0, "waiting notification has an empty queue"));
+            }
+            check_queue(
+                queue.head,
+                queue.tail,
+                ntfn.get_ptr(),
+                ThreadState::ThreadStateBlockedOnNotification,
+            )?;
+        }
+        NtfnState::Idle | NtfnState::Active => {
+            if queue.head != 0 || queue.tail != 0 {
+                return Err(Violation::new(
+                    queue.head,
+                    "notification with waiters is not waiting",
+                ));
+            }
+        }
+    }
+    let bound = ntfn.get_ntfnBoundTCB() as usize;
+    if bound != 0 && tcb_ref(bound).tcbBoundNotification != ntfn.get_ptr() {
+        return Err(Violation::new(
+            bound,
+            "ntfnBoundTCB is not bound to the notification",
+        ));
+    }
+    Ok(())
+}
+
+/// Check a thread: the objects it is blocked on and its bound notification. With `queued`, a
+/// blocked thread must also be in the queue of the object.
+fn check_thread(tcb: &tcb_t, queued: bool) -> Result<(), Violation> {
+    let tcb_ptr = tcb.get_ptr();
+    match tcb.get_state() {
+        ThreadState::ThreadStateBlockedOnSend | ThreadState::ThreadStateBlockedOnReceive => {
+            let ep_ptr = tcb.tcbState.get_blockingObject() as usize;
+            if ep_ptr == 0 {
+                return Err(Violation::new(tcb_ptr, "blocked on a null endpoint"));
+            }
+            let ep = unsafe { &*(ep_ptr as *const endpoint) };
+            check_endpoint(ep)?;
+            if queued && !queue_contains(ep.get_queue().head, tcb_ptr) {
+                return Err(Violation::new(
+                    tcb_ptr,
+                    "blocked thread is not in the endpoint queue",
+                ));
+            }
+        }
+        ThreadState::ThreadStateBlockedOnNotification => {
+            let ntfn_ptr = tcb.tcbState.get_blockingObject() as usize;
+            if ntfn_ptr == 0 {
+                return Err(Violation::new(tcb_ptr, "blocked on a null notification"));
+            }
+            let ntfn = unsafe { &*(ntfn_ptr as *const notification) };
+            check_notification(ntfn)?;
+            if queued && !queue_contains(ntfn.get_queue().head, tcb_ptr) {
+                return Err(Violation::new(
+                    tcb_ptr,
+                    "blocked thread is not in the notification queue",
+                ));
+            }
+        }
+        _ => {}
+    }
+    if tcb.tcbBoundNotification != 0 {
+        let ntfn = unsafe { &*(tcb.tcbBoundNotification as *const notification) };
+        if ntfn.get_ntfnBoundTCB() as usize != tcb_ptr {
+            return Err(Violation::new(
+                tcb_ptr,
+                "tcbBoundNotification is not bound to the thread",
+            ));
+        }
+    }
+    Ok(())
+}
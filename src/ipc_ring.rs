@@ -0,0 +1,195 @@
+//! Shared-memory IPC rings with notification doorbells, enabled by the `ipc_rings` feature.
+//!
+//! A ring channel is a submission ring and a completion ring in frames shared by two address
+//! spaces, each with a notification as its doorbell. The producer of a ring enqueues without a
+//! syscall while the consumer is busy, and signals the doorbell with `send_signal` only when
+//! `Ring::enqueue` says the consumer sleeps. A completion ring without a doorbell is polled.
+//!
+//! Each ring is a `RingHeader` followed by a power of two of `RingSlot`s, a slot is a message
+//! info word in the `seL4_MessageInfo` layout and its message words. Rings carry no caps. Both
+//! sides work on the ring with `Ring`, single producer, single consumer. The consumer of a ring
+//! waits on its doorbell with `wait_ipc_ring`, which sets `RING_NEED_DOORBELL` and checks the
+//! ring again before blocking, so that a message enqueued meanwhile is never slept through.
+//! The ring types live in `sel4_ipc_abi`, which only needs `core`, so user libraries share
+//! them.
+//!
+//! The kernel validates the channel in `setup_ipc_ring`, given kernel addresses of the shared
+//! frames. It only ever reads the indices and flags of a ring, which the user may scribble
+//! over at the cost of its own wakeups.
+use core::mem::size_of;
+use core::ptr::addr_of_mut;
+
+use sel4_common::structures_gen::notification;
+use sel4_task::tcb_t;
+
+use crate::error::IpcResult;
+use crate::handle::NotificationRef;
+use crate::notification::notification_func;
+use crate::object_table::ObjectTable;
+use crate::outcome::IpcOutcome;
+
+pub use sel4_ipc_abi::ring::*;
+
+/// Number of doorbells that can guard a ring at once
+const MAX_RING_DOORBELLS: usize = 64;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A ring channel to set up, the regions are kernel addresses of the shared frames
+pub struct RingConfig {
+    pub submission: usize,
+    pub completion: usize,
+    /// Bytes of each region
+    pub size: usize,
+    /// Slots in each ring
+    pub entries: usize,
+    pub submission_doorbell: NotificationRef,
+    /// `NULL` if completions are polled
+    pub completion_doorbell: NotificationRef,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// Why a ring channel could not be set up
+pub enum RingSetupError {
+    /// The number of slots is not a power of two of at most `MAX_RING_ENTRIES`
+    InvalidEntries,
+    /// A region is not aligned to a slot
+    Misaligned,
+    /// A region is smaller than `ring_bytes(entries)`
+    TooSmall,
+    /// The regions overlap
+    Overlapping,
+    /// The submission doorbell is `NULL` or both rings have the same doorbell
+    InvalidDoorbell,
+    /// A doorbell already guards a ring
+    DoorbellInUse,
+    /// Too many doorbells guard rings
+    TooManyRings,
+}
+
+#[derive(Clone, Copy)]
+/// The ring guarded by a doorbell
+struct GuardedRing {
+    base: usize,
+    entries: usize,
+}
+
+static mut RING_DOORBELLS: ObjectTable<GuardedRing, MAX_RING_DOORBELLS> =
+    ObjectTable::new(GuardedRing {
+        base: 0,
+        entries: 0,
+    });
+
+#[inline]
+fn doorbells() -> &'static mut ObjectTable<GuardedRing, MAX_RING_DOORBELLS> {
+    unsafe { &mut *addr_of_mut!(RING_DOORBELLS) }
+}
+
+/// Doorbells are looked up by cores holding unrelated object locks, so with per-object locking
+/// the table has a lock of its own. It is not held while the consumer waits.
+#[cfg(feature = "fine_grained_locking")]
+static RING_DOORBELLS_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Check the layout of a ring channel
+fn validate(config: &RingConfig) -> Result<(), RingSetupError> {
+    if !config.entries.is_power_of_two() || config.entries > MAX_RING_ENTRIES {
+        return Err(RingSetupError::InvalidEntries);
+    }
+    let align = size_of::<RingSlot>();
+    if config.submission % align != 0 || config.completion % align != 0 || config.size % align != 0
+    {
+        return Err(RingSetupError::Misaligned);
+    }
+    if config.size < ring_bytes(config.entries) {
+        return Err(RingSetupError::TooSmall);
+    }
+    let (low, high) = if config.submission < config.completion {
+        (config.submission, config.completion)
+    } else {
+        (config.completion, config.submission)
+    };
+    if low.checked_add(config.size).is_none_or(|end| end > high)
+        || high.checked_add(config.size).is_none()
+    {
+        return Err(RingSetupError::Overlapping);
+    }
+    if config.submission_doorbell.is_null()
+        || config.submission_doorbell == config.completion_doorbell
+    {
+        return Err(RingSetupError::InvalidDoorbell);
+    }
+    for doorbell in [config.submission_doorbell, config.completion_doorbell] {
+        if doorbells().get(doorbell.raw()).is_some() {
+            return Err(RingSetupError::DoorbellInUse);
+        }
+    }
+    Ok(())
+}
+
+/// Validate a ring channel, reset its rings and guard them with their doorbells.
+///
+/// # Safety
+/// The regions must be kernel addresses of frames that both address spaces map, and stay
+/// mapped until the doorbells are forgotten with `forget_ipc_ring`. The kernel writes the
+/// regions here and reads them in `wait_ipc_ring`.
+pub unsafe fn setup_ipc_ring(config: &RingConfig) -> Result<(), RingSetupError> {
+    ipc_lock!(lock_leaf(&RING_DOORBELLS_LOCK));
+    validate(config)?;
+    let rings = [
+        (config.submission_doorbell, config.submission),
+        (config.completion_doorbell, config.completion),
+    ];
+    for (doorbell, base) in rings
+        .into_iter()
+        .filter(|(doorbell, _)| !doorbell.is_null())
+    {
+        let guarded = GuardedRing {
+            base,
+            entries: config.entries,
+        };
+        if doorbells().get_or_insert(doorbell.raw(), guarded).is_none() {
+            forget_ipc_ring(config.submission_doorbell);
+            return Err(RingSetupError::TooManyRings);
+        }
+    }
+    for base in [config.submission, config.completion] {
+        let region = base as *mut RingSlot;
+        let slots = core::slice::from_raw_parts_mut(region, config.entries + 1);
+        Ring::init(slots);
+    }
+    Ok(())
+}
+
+/// Stop guarding a ring with `doorbell`, before the ring is unmapped or the notification
+/// deleted
+pub fn forget_ipc_ring(doorbell: NotificationRef) {
+    ipc_lock!(lock_leaf(&RING_DOORBELLS_LOCK));
+    doorbells().remove(doorbell.raw());
+}
+
+/// Get the ring guarded by `doorbell`
+fn guarded_ring(doorbell: usize) -> Option<GuardedRing> {
+    ipc_lock!(lock_leaf(&RING_DOORBELLS_LOCK));
+    doorbells().get(doorbell).copied()
+}
+
+/// Wait on `ntfn` as `thread`, the consumer of the ring it guards. Blocks only if the ring is
+/// empty once `RING_NEED_DOORBELL` is set, otherwise takes the pending signal, if any, without
+/// blocking. A notification that guards no ring is waited on as usual.
+pub fn wait_ipc_ring(
+    ntfn: &mut notification,
+    thread: &mut tcb_t,
+    is_blocking: bool,
+) -> IpcResult<IpcOutcome> {
+    let Some(guarded) = guarded_ring(ntfn.get_ptr()) else {
+        return ntfn.receive_signal(thread, is_blocking);
+    };
+    let ring = unsafe { Ring::from_raw(guarded.base, guarded.entries) };
+    if !ring.is_empty() {
+        return ntfn.receive_signal(thread, false);
+    }
+    if !ring.prepare_wait() || !is_blocking {
+        ring.cancel_wait();
+        return ntfn.receive_signal(thread, false);
+    }
+    ntfn.receive_signal(thread, true)
+}
@@ -0,0 +1,194 @@
+//! Endpoints served by the kernel, enabled by the `kernel_services` feature.
+//!
+//! A `KernelService` registered on an endpoint with `register_kernel_service` handles every
+//! normal message sent to the endpoint, synchronously in the `send_ipc` of the sender, instead
+//! of a user thread receiving it. This makes cheap services such as the timer, the console or
+//! the IPC statistics possible without a context switch.
+//!
+//! The service sees the request as a user server would: the badge of the cap it was sent with
+//! and the message words, but no caps, since there is no receive slot to put them in. The
+//! reply of a Call is written back to the caller as `do_reply` would, with badge 0, if the cap
+//! could grant or grant a reply. A Call on a cap that can do neither gets no reply and leaves
+//! the caller inactive, like a caller that could not be given a reply cap. The reply of a
+//! plain Send is dropped. Faults sent to the endpoint are queued as usual. A request longer
+//! than the message registers from a caller without an IPC buffer is refused with
+//! `IpcError::MissingIpcBuffer` rather than served cut short.
+//!
+//! Under MCS the service runs on the scheduling context of the caller, which is never blocked,
+//! so nothing is donated and no reply object is involved. A passive server calling a service
+//! keeps running on the scheduling context donated to it.
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use sel4_common::arch::ArchReg;
+use sel4_common::structures_gen::{endpoint, seL4_Fault_tag};
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
+
+use crate::abi::MessageInfo;
+use crate::endpoint::endpoint_func;
+use crate::error::{IpcError, IpcResult};
+use crate::handle::{tcb_handle_func, EndpointRef, TcbRef};
+use crate::object_table::ObjectTable;
+use crate::outcome::IpcOutcome;
+use crate::transfer::{do_kernel_reply, get_mr};
+
+/// Number of endpoints that can be served by the kernel
+const MAX_KERNEL_SERVICES: usize = 16;
+/// Longest request or reply of a kernel service, longer requests are cut short
+pub const MAX_SERVICE_WORDS: usize = 32;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A message sent to a kernel-served endpoint
+pub struct ServiceRequest {
+    pub caller: TcbRef,
+    /// The badge of the cap the message was sent with
+    pub badge: usize,
+    pub label: usize,
+    mrs: [usize; MAX_SERVICE_WORDS],
+    length: usize,
+    /// The caller waits for the reply
+    pub call: bool,
+}
+
+impl ServiceRequest {
+    /// The message words of the request
+    pub fn words(&self) -> &[usize] {
+        &self.mrs[..self.length]
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The reply of a kernel service
+pub struct ServiceReply {
+    pub label: usize,
+    mrs: [usize; MAX_SERVICE_WORDS],
+    length: usize,
+}
+
+impl ServiceReply {
+    /// An empty reply with `label`
+    pub fn new(label: usize) -> Self {
+        ServiceReply {
+            label,
+            mrs: [0; MAX_SERVICE_WORDS],
+            length: 0,
+        }
+    }
+
+    /// Append a word to the reply, false if it is full
+    pub fn push(&mut self, word: usize) -> bool {
+        match self.mrs.get_mut(self.length) {
+            Some(mr) => {
+                *mr = word;
+                self.length += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The message words of the reply
+    pub fn words(&self) -> &[usize] {
+        &self.mrs[..self.length]
+    }
+}
+
+/// A service the kernel runs on behalf of an endpoint
+pub trait KernelService: Sync {
+    /// Serve `request`. The reply is dropped if the caller does not wait for one.
+    fn serve(&self, request: &ServiceRequest) -> ServiceReply;
+}
+
+type KernelServices = ObjectTable<Option<&'static dyn KernelService>, MAX_KERNEL_SERVICES>;
+
+static mut KERNEL_SERVICES: KernelServices = ObjectTable::new(None);
+
+/// Requests served, counted by every core serving one
+static SERVED: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+fn kernel_services() -> &'static mut KernelServices {
+    unsafe { &mut *addr_of_mut!(KERNEL_SERVICES) }
+}
+
+/// Services are looked up by cores holding unrelated object locks, so with per-object locking
+/// the table has a lock of its own. It is released before the service runs.
+#[cfg(feature = "fine_grained_locking")]
+static KERNEL_SERVICE_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Serve the normal messages sent to `ep` with `service`. Returns false if too many
+/// endpoints are served by the kernel.
+pub fn register_kernel_service(ep: EndpointRef, service: &'static dyn KernelService) -> bool {
+    ipc_lock!(lock_leaf(&KERNEL_SERVICE_LOCK));
+    match kernel_services().get_or_insert(ep.raw(), None) {
+        Some(entry) => {
+            *entry = Some(service);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Go back to serving `ep` by user threads, e.g. before it is deleted
+pub fn unregister_kernel_service(ep: EndpointRef) {
+    ipc_lock!(lock_leaf(&KERNEL_SERVICE_LOCK));
+    kernel_services().remove(ep.raw());
+}
+
+/// Number of requests served by kernel services
+pub fn kernel_served_requests() -> u64 {
+    SERVED.load(Ordering::Relaxed)
+}
+
+/// Get the service registered on `ep`
+fn take_service(ep: &endpoint) -> Option<&'static dyn KernelService> {
+    ipc_lock!(lock_leaf(&KERNEL_SERVICE_LOCK));
+    *kernel_services().get(ep.get_ptr())?
+}
+
+/// Serve the message `src_thread` sends to `ep` if a kernel service is registered on it.
+/// Returns `None` if the message is left to `send_ipc`.
+pub(crate) fn serve(
+    ep: &endpoint,
+    src_thread: &mut tcb_t,
+    do_call: bool,
+    can_grant: bool,
+    badge: usize,
+    can_grant_reply: bool,
+) -> Option<IpcResult<IpcOutcome>> {
+    if src_thread.tcbFault.get_tag() != seL4_Fault_tag::seL4_Fault_NullFault {
+        return None;
+    }
+    let service = take_service(ep)?;
+
+    let info = MessageInfo::from_word_security(src_thread.tcbArch.get_register(ArchReg::MsgInfo));
+    let mut request = ServiceRequest {
+        caller: src_thread.handle(),
+        badge,
+        label: info.label(),
+        mrs: [0; MAX_SERVICE_WORDS],
+        length: 0,
+        call: do_call,
+    };
+    for (index, mr) in request.mrs.iter_mut().take(info.length()).enumerate() {
+        *mr = match get_mr(src_thread, index) {
+            Some(value) => value,
+            None => {
+                return Some(Err(IpcError::MissingIpcBuffer {
+                    thread: src_thread.handle(),
+                }))
+            }
+        };
+        request.length = index + 1;
+    }
+    SERVED.fetch_add(1, Ordering::Relaxed);
+    let reply = service.serve(&request);
+
+    let replied = do_call && (can_grant || can_grant_reply);
+    if replied {
+        do_kernel_reply(src_thread, reply.label, reply.words());
+    } else if do_call {
+        set_thread_state(src_thread, ThreadState::ThreadStateInactive);
+    }
+    Some(Ok(IpcOutcome::KernelServed { badge, replied }))
+}
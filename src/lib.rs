@@ -8,10 +8,143 @@
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
 
+/// Check the queue and state invariants of an IPC object on entry to and exit from the
+/// enclosing operation. Expands to nothing unless the `ipc_invariants` feature is enabled.
+macro_rules! ipc_invariants {
+    ($kind:ident, $ptr:expr, $op:literal) => {
+        #[cfg(feature = "ipc_invariants")]
+        let _invariants = $crate::invariants::InvariantGuard::new(
+            $crate::invariants::ObjectKind::$kind,
+            $ptr,
+            $op,
+        );
+    };
+}
+
+/// Record an IPC event in the trace buffer, see `trace/mod.rs`. Expands to nothing unless the
+/// `trace` feature is enabled, the arguments are not evaluated then.
+macro_rules! ipc_trace {
+    ($event:ident, $outcome:ident, $object:expr, $thread:expr $(, $field:ident: $value:expr)* $(,)?) => {
+        #[cfg(feature = "trace")]
+        $crate::trace::trace_record($crate::trace::TraceRecord {
+            object: $object as u64,
+            thread: $thread as u64,
+            kind: $crate::trace::TraceRecord::kind_of(
+                $crate::trace::TraceEvent::$event,
+                $crate::trace::TraceOutcome::$outcome,
+            ),
+            $($field: $value as u64,)*
+            ..$crate::trace::TraceRecord::EMPTY
+        });
+    };
+}
+
+/// Update the statistics of an IPC object, see `stats.rs`. Expands to nothing unless the
+/// `stats` feature is enabled.
+macro_rules! ipc_stats {
+    ($update:ident($($arg:expr),* $(,)?)) => {
+        #[cfg(feature = "stats")]
+        $crate::stats::$update($($arg),*);
+    };
+}
+
+/// Update the wait-for graph, see `wait_graph.rs`. Expands to nothing unless the
+/// `deadlock_detection` or `priority_inheritance` feature is enabled.
+macro_rules! ipc_wait_graph {
+    ($update:ident($($arg:expr),* $(,)?)) => {
+        #[cfg(any(feature = "deadlock_detection", feature = "priority_inheritance"))]
+        $crate::wait_graph::$update($($arg),*);
+    };
+}
+
+/// Lock IPC objects until the end of the enclosing scope, see `lock/mod.rs`. Expands to nothing
+/// unless the `fine_grained_locking` feature is enabled.
+macro_rules! ipc_lock {
+    ($lock:ident($($arg:expr),* $(,)?)) => {
+        #[cfg(feature = "fine_grained_locking")]
+        let _lock = $crate::lock::$lock($($arg),*);
+    };
+}
+
+/// Claim the state word of a notification until the end of the enclosing scope, so that the
+/// lock-free signal fast path backs off, see `lock/ntfn.rs`. Expands to nothing unless the
+/// `lockfree_signal` feature is enabled.
+macro_rules! ipc_ntfn_claim {
+    ($ntfn:expr) => {
+        // The claim is linked by its address and stays in this local until it is dropped.
+        #[cfg(feature = "lockfree_signal")]
+        let mut _claim = $crate::lock::NtfnClaim::new();
+        #[cfg(feature = "lockfree_signal")]
+        unsafe {
+            _claim.claim($ntfn)
+        };
+    };
+}
+
+pub mod abi;
+#[cfg(any(feature = "trace", feature = "stats"))]
+mod clock;
+#[cfg(feature = "debug_dump")]
+pub mod debug_dump;
+#[cfg(feature = "hardware_debug_api")]
+pub mod debug_exception;
+#[cfg(feature = "domain_isolation")]
+pub mod domain;
+#[cfg(feature = "double_fault_policy")]
+pub mod double_fault;
 mod endpoint;
+mod error;
+pub mod fault_codec;
+#[cfg(feature = "fault_profiles")]
+pub mod fault_profile;
+#[cfg(feature = "fault_routing")]
+pub mod fault_routing;
+mod handle;
+#[cfg(feature = "ipc_invariants")]
+mod invariants;
+#[cfg(feature = "ipc_rings")]
+pub mod ipc_ring;
+#[cfg(feature = "kernel_services")]
+pub mod kernel_service;
+#[cfg(feature = "fine_grained_locking")]
+pub mod lock;
 mod notification;
+#[cfg(any(
+    feature = "stats",
+    feature = "deadlock_detection",
+    feature = "priority_inheritance",
+    feature = "debug_dump",
+    feature = "fault_routing",
+    feature = "fault_profiles",
+    feature = "double_fault_policy",
+    feature = "kernel_services",
+    feature = "ipc_rings"
+))]
+mod object_table;
+mod outcome;
+#[cfg(feature = "priority_inheritance")]
+pub mod priority_inheritance;
+#[cfg(feature = "register_replies")]
+pub mod register_reply;
+mod sched;
+#[cfg(feature = "smp")]
+pub mod smp;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "trace")]
+pub mod trace;
 mod transfer;
+#[cfg(any(feature = "deadlock_detection", feature = "priority_inheritance"))]
+pub mod wait_graph;
 
+#[cfg(any(feature = "trace", feature = "stats"))]
+pub use clock::set_ipc_clock;
 pub use endpoint::*;
+pub use error::*;
+pub use fault_codec::{
+    fault_reply_copy, fault_reply_layout, fault_reply_restart, is_transferable_fault,
+};
+pub use handle::*;
 pub use notification::*;
+pub use outcome::*;
 pub use transfer::*;
@@ -0,0 +1,140 @@
+//! Per-object locking of endpoints and notifications, enabled by the `fine_grained_locking`
+//! feature.
+//!
+//! Every IPC operation locks the objects whose queues it touches, so operations on unrelated
+//! objects run in parallel instead of behind the big kernel lock. Operations touching two
+//! objects lock both up front, in the order of their lock index:
+//! * `receive_ipc` locks the endpoint and the bound notification of the receiver, which
+//!   `complete_signal` consumes.
+//! * `send_signal` locks the notification and, if its bound TCB is blocked receiving on an
+//!   endpoint, that endpoint, which the TCB is cancelled from.
+//! * `Transfer::cancel_ipc` locks the object the thread is blocked on.
+//!
+//! The side tables of the optional features, such as the statistics, the trace buffer, the
+//! wait-for graph or the fault routes, and the remote wakeup queues of the `smp` feature each
+//! have a leaf lock of their own. A leaf lock is only held while its table is read or written,
+//! never while taking an object lock, another leaf lock or calling into IPC. The hooks and
+//! policies the kernel sets at boot are read without a lock, they must be set before other
+//! cores enter the kernel.
+//!
+//! The ready queues and reply objects are the scheduler's business and are not covered here,
+//! they are still only protected by the big kernel lock, which the kernel must hold around the
+//! scheduler services and reply operations this crate calls.
+//!
+//! With the `lockfree_signal` feature `send_signal` skips the locks when it wakes nobody, see
+//! `ntfn.rs`.
+#[cfg(feature = "lockfree_signal")]
+mod ntfn;
+#[cfg(feature = "lockfree_signal")]
+pub mod signal_word;
+mod spin;
+
+#[cfg(feature = "lockfree_signal")]
+pub(crate) use ntfn::{ntfn_state, set_ntfn_state, try_signal_fast, FastSignal, NtfnClaim};
+pub use spin::{LockTable, ObjectLock};
+
+use core::ptr::addr_of;
+
+use sel4_common::structures_gen::notification;
+use sel4_task::{tcb_t, ThreadState};
+
+use crate::handle::tcb_handle_func;
+use crate::notification::notification_func;
+
+/// Number of locks shared by all endpoints and notifications
+pub const OBJECT_LOCK_STRIPES: usize = 64;
+
+static OBJECT_LOCKS: LockTable<OBJECT_LOCK_STRIPES> = LockTable::new();
+static mut LOCK_CORE: Option<fn() -> usize> = None;
+
+/// Set how the locks find the index of the current core, every lock is taken by core 0 until
+/// then
+pub fn set_lock_core_hook(current_core: fn() -> usize) {
+    unsafe { *core::ptr::addr_of_mut!(LOCK_CORE) = Some(current_core) };
+}
+
+#[inline]
+pub(crate) fn current_core() -> usize {
+    unsafe { *addr_of!(LOCK_CORE) }.map_or(0, |current_core| current_core())
+}
+
+/// The locks of one or two objects, released when dropped
+pub(crate) struct ObjectGuard {
+    taken: [Option<usize>; 2],
+    core: usize,
+}
+
+impl Drop for ObjectGuard {
+    fn drop(&mut self) {
+        OBJECT_LOCKS.unlock_pair(self.taken, self.core);
+    }
+}
+
+/// Lock the objects at `a` and `b`, either may be 0
+pub(crate) fn lock_objects(a: usize, b: usize) -> ObjectGuard {
+    let core = current_core();
+    ObjectGuard {
+        taken: OBJECT_LOCKS.lock_pair(a, b, core),
+        core,
+    }
+}
+
+/// A lock outside the lock order, released when dropped. Only the optional features take
+/// leaf locks.
+#[allow(dead_code)]
+pub(crate) struct LeafGuard {
+    lock: &'static ObjectLock,
+    core: usize,
+}
+
+impl Drop for LeafGuard {
+    fn drop(&mut self) {
+        self.lock.unlock(self.core);
+    }
+}
+
+/// Take a lock that is never held while taking another one, so it may be taken with any
+/// object locked
+#[allow(dead_code)]
+pub(crate) fn lock_leaf(lock: &'static ObjectLock) -> LeafGuard {
+    let core = current_core();
+    lock.lock(core);
+    LeafGuard { lock, core }
+}
+
+/// Lock the endpoint or notification `thread` is blocked on, if any
+pub(crate) fn lock_blocking_object(thread: &tcb_t) -> ObjectGuard {
+    fn blocking_object(thread: &tcb_t) -> usize {
+        match thread.blocking_endpoint().raw() {
+            0 => thread.blocking_notification().raw(),
+            ep => ep,
+        }
+    }
+    loop {
+        let object = blocking_object(thread);
+        let guard = lock_objects(object, 0);
+        // The thread may have been woken or moved before the lock was taken.
+        if blocking_object(thread) == object {
+            return guard;
+        }
+    }
+}
+
+/// Lock a notification and the endpoint its bound TCB is blocked receiving on, if any
+pub(crate) fn lock_signal_target(ntfn: &notification) -> ObjectGuard {
+    fn receiving_on(ntfn: &notification) -> usize {
+        match ntfn.bound_tcb().get() {
+            Some(tcb) if tcb.get_state() == ThreadState::ThreadStateBlockedOnReceive => {
+                tcb.blocking_endpoint().raw()
+            }
+            _ => 0,
+        }
+    }
+    loop {
+        let ep = receiving_on(ntfn);
+        let guard = lock_objects(ntfn.get_ptr(), ep);
+        if receiving_on(ntfn) == ep {
+            return guard;
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! The lock-free signal fast path, enabled by the `lockfree_signal` feature. See
+//! `signal_word.rs` for the protocol.
+//!
+//! While a locked path has claimed a notification its state field reads busy, the state it
+//! works with is kept in the claim, which `get_ntfn_state` and `set_ntfn_state` find through
+//! the claims of the current core.
+//!
+//! The locked paths write the rest of the state word, the queue tail, with the plain stores of
+//! the generated bitfield, while fast signals on other cores load and compare-and-swap it. This
+//! is sound because only the core that made the word busy stores to it until it releases it:
+//! every locked path touching the word claims it first, and the others only load a busy word
+//! and back off or wait. The setters read-modify-write the word on the owning core, so the busy
+//! bits they read are the ones that core stored and survive, and `release` publishes the plain
+//! stores with a release store. Aligned word stores are single-copy atomic on the platforms
+//! the kernel runs on, so a concurrent load sees the word before or after such a store, busy
+//! either way.
+use core::ptr::{addr_of_mut, null_mut};
+use core::sync::atomic::AtomicU64;
+
+use sel4_common::structures_gen::notification;
+
+use super::signal_word::{StateField, STATE_ACTIVE, STATE_BUSY, STATE_IDLE};
+use crate::notification::notification_func;
+
+/// Number of cores that can claim notifications
+const MAX_CLAIM_CORES: usize = 64;
+
+/// The word of the generated bitfield holding the state of a notification. The state is the
+/// lowest field of the first word, below the queue tail, in both the MCS and the non-MCS
+/// layout of `structures.bf`.
+const STATE_WORD: usize = 0;
+const STATE_FIELD: StateField = StateField { shift: 0 };
+
+#[inline]
+fn state_word(ntfn: &notification) -> &'static AtomicU64 {
+    unsafe { &*(ntfn.words.as_ptr().add(STATE_WORD) as *const AtomicU64) }
+}
+
+/// The innermost claim of each core, only ever touched by that core
+static mut INNERMOST_CLAIMS: [*mut NtfnClaim; MAX_CLAIM_CORES] = [null_mut(); MAX_CLAIM_CORES];
+
+#[inline]
+fn innermost() -> &'static mut *mut NtfnClaim {
+    unsafe { &mut (*addr_of_mut!(INNERMOST_CLAIMS))[super::current_core()] }
+}
+
+/// A notification claimed by a locked path, released when dropped. The claims of a core are
+/// linked from the innermost to the outermost, so a core may hold any number of them.
+pub(crate) struct NtfnClaim {
+    ntfn: usize,
+    /// The state of the notification while its word is busy, `None` if an outer claim of the
+    /// same core holds the notification already
+    state: Option<u64>,
+    outer: *mut NtfnClaim,
+}
+
+impl NtfnClaim {
+    pub(crate) const fn new() -> Self {
+        NtfnClaim {
+            ntfn: 0,
+            state: None,
+            outer: null_mut(),
+        }
+    }
+
+    /// Claim `ntfn` for the rest of a locked operation. The object lock must be held.
+    ///
+    /// # Safety
+    ///
+    /// The claim is linked by its address, it must not move until it is dropped.
+    /// `ipc_ntfn_claim!` keeps it in a local nothing else can name.
+    pub(crate) unsafe fn claim(&mut self, ntfn: &notification) {
+        self.ntfn = ntfn.get_ptr();
+        if claimed_state(self.ntfn).is_none() {
+            self.state = Some(STATE_FIELD.claim(state_word(ntfn)));
+        }
+        let innermost = innermost();
+        self.outer = *innermost;
+        *innermost = self;
+    }
+}
+
+impl Drop for NtfnClaim {
+    fn drop(&mut self) {
+        if self.ntfn == 0 {
+            return;
+        }
+        // Claims live in the locals of nested scopes, so the innermost one is dropped first.
+        *innermost() = self.outer;
+        if let Some(state) = self.state {
+            let ntfn = unsafe { &*(self.ntfn as *const notification) };
+            STATE_FIELD.release(state_word(ntfn), state);
+        }
+    }
+}
+
+/// The state kept by the claim of the current core on `ntfn`, if it holds one
+fn claimed_state(ntfn: usize) -> Option<&'static mut u64> {
+    let mut claim = *innermost();
+    while let Some(current) = unsafe { claim.as_mut() } {
+        if current.ntfn == ntfn {
+            if let Some(state) = current.state.as_mut() {
+                return Some(state);
+            }
+        }
+        claim = current.outer;
+    }
+    None
+}
+
+/// Read the state of `ntfn`: the claimed state if the current core holds it, otherwise the
+/// state once no fast signal is in flight
+pub(crate) fn ntfn_state(ntfn: &notification) -> u64 {
+    if let Some(state) = claimed_state(ntfn.get_ptr()) {
+        return *state;
+    }
+    loop {
+        let state = ntfn.get_state();
+        if state != STATE_BUSY {
+            return state;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Set the state of `ntfn`, published when the current core releases its claim
+pub(crate) fn set_ntfn_state(ntfn: &mut notification, state: u64) {
+    match claimed_state(ntfn.get_ptr()) {
+        Some(claimed) => *claimed = state,
+        None => ntfn.set_state(state),
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What a fast signal did
+pub(crate) enum FastSignal {
+    /// An Idle notification without a bound TCB became Active
+    Activated,
+    /// The badge was OR-ed into an Active notification
+    Coalesced,
+}
+
+/// Signal `ntfn` without its lock if nobody has to be woken. Returns `None` if the signal must
+/// take the locked path.
+pub(crate) fn try_signal_fast(ntfn: &mut notification, badge: usize) -> Option<FastSignal> {
+    let word = state_word(ntfn);
+    let state = STATE_FIELD.try_claim_fast(word)?;
+    if state == STATE_IDLE {
+        // Binding takes a claim, so the bound TCB cannot change while the word is busy.
+        if ntfn.get_ntfnBoundTCB() != 0 {
+            STATE_FIELD.release(word, STATE_IDLE);
+            return None;
+        }
+        ntfn.set_ntfnMsgIdentifier(badge as u64);
+        STATE_FIELD.release(word, STATE_ACTIVE);
+        Some(FastSignal::Activated)
+    } else {
+        ntfn.set_ntfnMsgIdentifier(ntfn.get_ntfnMsgIdentifier() | badge as u64);
+        STATE_FIELD.release(word, STATE_ACTIVE);
+        Some(FastSignal::Coalesced)
+    }
+}
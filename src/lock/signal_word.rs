@@ -0,0 +1,93 @@
+//! The protocol of the lock-free signal fast path on the word holding the notification state.
+//! Like `spin.rs` this file only depends on the atomics, so `tools/lock_model` checks it too.
+//!
+//! The state field has two bits and three states, the fourth value marks the word as busy.
+//! A fast signal turns an Idle or Active word busy with a compare-and-swap, updates the badge
+//! and publishes the Active state. The locked paths claim the word the same way, waiting for a
+//! fast signal in flight, and keep it busy until they are done, so a fast signal never
+//! interleaves with them and falls back to the locked path instead.
+#[cfg(not(loom))]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU64, Ordering};
+
+pub const STATE_IDLE: u64 = 0;
+pub const STATE_WAITING: u64 = 1;
+pub const STATE_ACTIVE: u64 = 2;
+pub const STATE_BUSY: u64 = 3;
+
+#[derive(Clone, Copy)]
+/// The position of the two-bit state field in its word
+pub struct StateField {
+    pub shift: u32,
+}
+
+impl StateField {
+    #[inline]
+    pub fn get(self, word: u64) -> u64 {
+        (word >> self.shift) & 3
+    }
+
+    #[inline]
+    pub fn with(self, word: u64, state: u64) -> u64 {
+        (word & !(3 << self.shift)) | (state << self.shift)
+    }
+
+    /// Make the word busy for a locked path, waiting for a fast signal in flight. Returns the
+    /// state the word had.
+    pub fn claim(self, word: &AtomicU64) -> u64 {
+        loop {
+            let current = word.load(Ordering::Relaxed);
+            let state = self.get(current);
+            if state == STATE_BUSY {
+                #[cfg(loom)]
+                loom::thread::yield_now();
+                #[cfg(not(loom))]
+                core::hint::spin_loop();
+                continue;
+            }
+            if word
+                .compare_exchange_weak(
+                    current,
+                    self.with(current, STATE_BUSY),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return state;
+            }
+        }
+    }
+
+    /// Make the word busy for a fast signal if it is Idle or Active. Returns the state it had,
+    /// or `None` if the signal must take the locked path.
+    pub fn try_claim_fast(self, word: &AtomicU64) -> Option<u64> {
+        loop {
+            let current = word.load(Ordering::Relaxed);
+            let state = self.get(current);
+            if state != STATE_IDLE && state != STATE_ACTIVE {
+                return None;
+            }
+            if word
+                .compare_exchange_weak(
+                    current,
+                    self.with(current, STATE_BUSY),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return Some(state);
+            }
+        }
+    }
+
+    /// Publish `state` and everything written while the word was busy
+    pub fn release(self, word: &AtomicU64, state: u64) {
+        debug_assert!(state != STATE_BUSY);
+        // Nobody else writes the word while it is busy.
+        let current = word.load(Ordering::Relaxed);
+        word.store(self.with(current, state), Ordering::Release);
+    }
+}
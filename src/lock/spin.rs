@@ -0,0 +1,133 @@
+//! The spinlocks protecting endpoints and notifications. This file only depends on the atomics,
+//! which come from loom under `cfg(loom)`, so the host model test in `tools/lock_model` checks
+//! the same code the kernel runs.
+#[cfg(not(loom))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicUsize, Ordering};
+
+/// A spinlock a core may take again while holding it. Operations that touch two objects lock
+/// both up front in `LockTable` order, and the nested operations they call on those objects
+/// take the locks again instead of deadlocking.
+pub struct ObjectLock {
+    /// 0 if free, otherwise the index of the owning core plus one
+    owner: AtomicUsize,
+    /// Number of times the owner took the lock, only touched by the owner
+    depth: AtomicUsize,
+}
+
+impl ObjectLock {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        ObjectLock {
+            owner: AtomicUsize::new(0),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        ObjectLock {
+            owner: AtomicUsize::new(0),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn lock(&self, core: usize) {
+        let me = core + 1;
+        // Only this core can have stored its own index, so a relaxed load is enough.
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.depth
+                .store(self.depth.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+            return;
+        }
+        while self
+            .owner
+            .compare_exchange_weak(0, me, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            #[cfg(loom)]
+            loom::thread::yield_now();
+            #[cfg(not(loom))]
+            core::hint::spin_loop();
+        }
+        self.depth.store(1, Ordering::Relaxed);
+    }
+
+    pub fn unlock(&self, core: usize) {
+        debug_assert_eq!(self.owner.load(Ordering::Relaxed), core + 1);
+        let depth = self.depth.load(Ordering::Relaxed) - 1;
+        self.depth.store(depth, Ordering::Relaxed);
+        if depth == 0 {
+            self.owner.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl Default for ObjectLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fixed set of locks shared by all objects, an object uses the lock its address hashes to.
+/// Locks are always taken in increasing index order, which is the lock order of the objects.
+pub struct LockTable<const N: usize> {
+    locks: [ObjectLock; N],
+}
+
+impl<const N: usize> LockTable<N> {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        LockTable {
+            locks: [const { ObjectLock::new() }; N],
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        LockTable {
+            locks: core::array::from_fn(|_| ObjectLock::new()),
+        }
+    }
+
+    /// Index of the lock of the object at `ptr`
+    pub fn index(ptr: usize) -> usize {
+        // Same hash as the object tables, see `object_table.rs`.
+        ((((ptr as u64) >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize) % N
+    }
+
+    /// Lock the objects at `a` and `b`, either may be 0 for no object. Returns the indices of
+    /// the locks taken, in the order they were taken.
+    pub fn lock_pair(&self, a: usize, b: usize, core: usize) -> [Option<usize>; 2] {
+        let taken = match (a, b) {
+            (0, 0) => [None, None],
+            (ptr, 0) | (0, ptr) => [Some(Self::index(ptr)), None],
+            (a, b) => {
+                let (a, b) = (Self::index(a), Self::index(b));
+                if a == b {
+                    [Some(a), None]
+                } else {
+                    [Some(a.min(b)), Some(a.max(b))]
+                }
+            }
+        };
+        for index in taken.iter().flatten() {
+            self.locks[*index].lock(core);
+        }
+        taken
+    }
+
+    /// Unlock what `lock_pair` returned
+    pub fn unlock_pair(&self, taken: [Option<usize>; 2], core: usize) {
+        for index in taken.iter().rev().flatten() {
+            self.locks[*index].unlock(core);
+        }
+    }
+}
+
+impl<const N: usize> Default for LockTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,12 +1,22 @@
+use crate::abi::NO_MESSAGE_BADGE;
+use crate::error::{IpcError, IpcResult};
+#[cfg(feature = "kernel_mcs")]
+use crate::handle::SchedContextRef;
+use crate::handle::{tcb_handle_func, NotificationRef, TcbRef};
+use crate::outcome::IpcOutcome;
+use crate::sched::possible_switch_to;
+#[cfg(not(feature = "kernel_mcs"))]
+use crate::sched::sched_enqueue;
 use crate::transfer::Transfer;
 use sel4_common::arch::ArchReg;
 use sel4_common::structures_gen::notification;
-use sel4_common::utils::{convert_to_mut_type_ref, convert_to_option_mut_type_ref};
-use sel4_task::{
-    possible_switch_to, reschedule_required, set_thread_state, tcb_queue_t, tcb_t, ThreadState,
-};
 #[cfg(feature = "kernel_mcs")]
-use sel4_task::{sched_context::sched_context_t, NODE_STATE};
+use sel4_task::NODE_STATE;
+use sel4_task::{reschedule_required, set_thread_state, tcb_queue_t, tcb_t, ThreadState};
+
+pub const NtfnState_Idle: usize = NtfnState::Idle as usize;
+pub const NtfnState_Waiting: usize = NtfnState::Waiting as usize;
+pub const NtfnState_Active: usize = NtfnState::Active as usize;
 
 #[derive(PartialEq, Eq, Debug)]
 /// The state of a notification
@@ -17,18 +27,27 @@ pub enum NtfnState {
 }
 
 pub trait notification_func {
-    fn get_ntfn_state(&self) -> NtfnState;
+    fn get_ntfn_state(&self) -> IpcResult<NtfnState>;
+    fn set_ntfn_state(&mut self, state: NtfnState);
     fn get_queue(&self) -> tcb_queue_t;
     fn set_queue(&mut self, queue: &tcb_queue_t);
     fn active(&mut self, badge: usize);
     fn cancel_signal(&mut self, tcb: &mut tcb_t);
-    fn cacncel_all_signal(&mut self);
+    fn cacncel_all_signal(&mut self) -> IpcResult<()>;
     fn bind_tcb(&mut self, tcb: &mut tcb_t);
     fn unbind_tcb(&mut self);
     fn safe_unbind_tcb(&mut self);
     fn get_ptr(&self) -> usize;
-    fn send_signal(&mut self, badge: usize);
-    fn receive_signal(&mut self, recv_thread: &mut tcb_t, is_blocking: bool);
+    fn handle(&self) -> NotificationRef;
+    fn bound_tcb(&self) -> TcbRef;
+    #[cfg(feature = "kernel_mcs")]
+    fn sched_context(&self) -> SchedContextRef;
+    fn send_signal(&mut self, badge: usize) -> IpcResult<IpcOutcome>;
+    fn receive_signal(
+        &mut self,
+        recv_thread: &mut tcb_t,
+        is_blocking: bool,
+    ) -> IpcResult<IpcOutcome>;
     #[cfg(feature = "kernel_mcs")]
     fn reorder_ntfn(&mut self, thread: &mut tcb_t);
     #[cfg(feature = "kernel_mcs")]
@@ -36,9 +55,29 @@ pub trait notification_func {
 }
 impl notification_func for notification {
     #[inline]
-    /// Get the state of the notification
-    fn get_ntfn_state(&self) -> NtfnState {
-        unsafe { core::mem::transmute::<u8, NtfnState>(self.get_state() as u8) }
+    /// Get the state of the notification, an unknown state means the notification is corrupted
+    fn get_ntfn_state(&self) -> IpcResult<NtfnState> {
+        #[cfg(feature = "lockfree_signal")]
+        let state = crate::lock::ntfn_state(self);
+        #[cfg(not(feature = "lockfree_signal"))]
+        let state = self.get_state();
+        match state as usize {
+            NtfnState_Idle => Ok(NtfnState::Idle),
+            NtfnState_Waiting => Ok(NtfnState::Waiting),
+            NtfnState_Active => Ok(NtfnState::Active),
+            _ => Err(IpcError::CorruptedObjectState {
+                object: self.get_ptr(),
+            }),
+        }
+    }
+
+    #[inline]
+    /// Set the state of the notification
+    fn set_ntfn_state(&mut self, state: NtfnState) {
+        #[cfg(feature = "lockfree_signal")]
+        crate::lock::set_ntfn_state(self, state as u64);
+        #[cfg(not(feature = "lockfree_signal"))]
+        self.set_state(state as u64);
     }
 
     #[inline]
@@ -62,7 +101,10 @@ impl notification_func for notification {
     /// # Arguments
     /// * `badge` - The badge to set
     fn active(&mut self, badge: usize) {
-        self.set_state(NtfnState::Active as u64);
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(Notification, self.get_ptr(), "notification::active");
+        self.set_ntfn_state(NtfnState::Active);
         self.set_ntfnMsgIdentifier(badge as u64);
     }
 
@@ -71,33 +113,43 @@ impl notification_func for notification {
     /// # Arguments
     /// * `tcb` - The tcb to cancel
     fn cancel_signal(&mut self, tcb: &mut tcb_t) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(Notification, self.get_ptr(), "notification::cancel_signal");
         let mut queue = self.get_queue();
         queue.ep_dequeue(tcb);
         self.set_queue(&queue);
         if queue.head == 0 {
-            self.set_state(NtfnState::Idle as u64);
+            self.set_ntfn_state(NtfnState::Idle);
         }
         set_thread_state(tcb, ThreadState::ThreadStateInactive);
+        ipc_trace!(Cancel, Cancelled, self.get_ptr(), tcb.get_ptr());
+        ipc_stats!(notification_cancelled(self.get_ptr(), tcb.get_ptr()));
     }
 
     #[inline]
     /// Cancel all signal in the notification
-    fn cacncel_all_signal(&mut self) {
-        if self.get_ntfn_state() == NtfnState::Waiting {
-            let mut op_thread =
-                convert_to_option_mut_type_ref::<tcb_t>(self.get_ntfnQueue_head() as usize);
-            self.set_state(NtfnState::Idle as u64);
+    fn cacncel_all_signal(&mut self) -> IpcResult<()> {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(
+            Notification,
+            self.get_ptr(),
+            "notification::cacncel_all_signal"
+        );
+        if self.get_ntfn_state()? == NtfnState::Waiting {
+            let mut op_thread = TcbRef::from_raw(self.get_ntfnQueue_head() as usize).get();
+            self.set_ntfn_state(NtfnState::Idle);
             self.set_ntfnQueue_head(0);
             self.set_ntfnQueue_tail(0);
             while let Some(thread) = op_thread {
                 set_thread_state(thread, ThreadState::ThreadStateRestart);
                 #[cfg(feature = "kernel_mcs")]
                 {
-                    if let Some(sc) =
-                        convert_to_option_mut_type_ref::<sched_context_t>(thread.tcbSchedContext)
-                    {
-                        if sc.sc_sporadic() {
-                            assert!(thread.tcbSchedContext != NODE_STATE!(ksCurSC));
+                    if let Some(sc) = thread.sched_context().get() {
+                        // A waiter does not run on the current scheduling context, skip the
+                        // refill if it somehow does.
+                        if sc.sc_sporadic() && thread.tcbSchedContext != NODE_STATE!(ksCurSC) {
                             sc.refill_unblock_check();
                         }
                     }
@@ -105,33 +157,47 @@ impl notification_func for notification {
                 }
                 #[cfg(not(feature = "kernel_mcs"))]
                 {
-                    thread.sched_enqueue();
+                    sched_enqueue(thread);
                 }
-                op_thread = convert_to_option_mut_type_ref::<tcb_t>(thread.tcbEPNext);
+                ipc_trace!(Cancel, Restarted, self.get_ptr(), thread.get_ptr());
+                ipc_stats!(notification_cancelled(self.get_ptr(), thread.get_ptr()));
+                op_thread = thread.ep_next().get();
             }
             reschedule_required();
         }
+        Ok(())
     }
 
     #[inline]
     /// Bind the tcb to the notification
     fn bind_tcb(&mut self, tcb: &mut tcb_t) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
         self.set_ntfnBoundTCB(tcb.get_ptr() as u64);
     }
 
     #[inline]
     /// Unbind the tcb to the notification
     fn unbind_tcb(&mut self) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
         self.set_ntfnBoundTCB(0);
     }
 
     #[inline]
     /// Safely unbind the tcb to the notification
     fn safe_unbind_tcb(&mut self) {
-        let tcb = self.get_ntfnBoundTCB() as usize;
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(
+            Notification,
+            self.get_ptr(),
+            "notification::safe_unbind_tcb"
+        );
+        let tcb = self.bound_tcb();
         self.unbind_tcb();
-        if tcb != 0 {
-            convert_to_mut_type_ref::<tcb_t>(tcb).unbind_notification();
+        if let Some(tcb) = tcb.get() {
+            tcb.unbind_notification();
         }
     }
 
@@ -141,23 +207,65 @@ impl notification_func for notification {
         self as *const notification as usize
     }
 
+    #[inline]
+    /// Get the typed handle of the notification
+    fn handle(&self) -> NotificationRef {
+        NotificationRef::of(self)
+    }
+
+    #[inline]
+    /// Get the tcb bound to the notification
+    fn bound_tcb(&self) -> TcbRef {
+        TcbRef::from_raw(self.get_ntfnBoundTCB() as usize)
+    }
+
+    #[cfg(feature = "kernel_mcs")]
+    #[inline]
+    /// Get the scheduling context bound to the notification
+    fn sched_context(&self) -> SchedContextRef {
+        SchedContextRef::from_raw(self.get_ntfnSchedContext() as usize)
+    }
+
     #[inline]
     /// Send a signal to the notification.
     /// 1: If the notification is idle, the badge is sent to the bound tcb if it exists, otherwise the notification is set to active.
     /// 2: If the notification is waiting, the badge is sent to the head of the queue.
     /// 3: If the notification is active, the badge is added to the message identifier.
+    /// Returns which of these happened, see `IpcOutcome`.
+    ///
+    /// With the `lockfree_signal` feature the cases that wake nobody, an idle notification
+    /// without a bound tcb and an active one, take no lock.
     /// # Arguments
     /// * `badge` - The badge to send
-    fn send_signal(&mut self, badge: usize) {
-        match self.get_ntfn_state() {
+    fn send_signal(&mut self, badge: usize) -> IpcResult<IpcOutcome> {
+        #[cfg(feature = "lockfree_signal")]
+        if let Some(signal) = crate::lock::try_signal_fast(self, badge) {
+            ipc_stats!(notification_signalled(self.get_ptr()));
+            return Ok(match signal {
+                crate::lock::FastSignal::Activated => {
+                    ipc_trace!(Signal, Activated, self.get_ptr(), 0, badge: badge);
+                    IpcOutcome::Activated
+                }
+                crate::lock::FastSignal::Coalesced => {
+                    ipc_trace!(Signal, Coalesced, self.get_ptr(), 0, badge: badge);
+                    ipc_stats!(notification_coalesced(self.get_ptr()));
+                    IpcOutcome::Coalesced
+                }
+            });
+        }
+        ipc_lock!(lock_signal_target(self));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(Notification, self.get_ptr(), "notification::send_signal");
+        ipc_stats!(notification_signalled(self.get_ptr()));
+        Ok(match self.get_ntfn_state()? {
             NtfnState::Idle => {
-                if let Some(tcb) =
-                    convert_to_option_mut_type_ref::<tcb_t>(self.get_ntfnBoundTCB() as usize)
-                {
+                if let Some(tcb) = self.bound_tcb().get() {
                     if tcb.get_state() == ThreadState::ThreadStateBlockedOnReceive {
-                        tcb.cancel_ipc();
+                        tcb.cancel_ipc()?;
                         set_thread_state(tcb, ThreadState::ThreadStateRunning);
                         tcb.tcbArch.set_register(ArchReg::Badge, badge);
+                        ipc_trace!(Signal, Delivered, self.get_ptr(), 0, peer: tcb.get_ptr(), badge: badge);
+                        ipc_stats!(notification_bound_delivered(self.get_ptr()));
                         #[cfg(feature = "kernel_mcs")]
                         {
                             maybe_donate_sched_context(tcb, self);
@@ -168,13 +276,9 @@ impl notification_func for notification {
                         #[cfg(not(feature = "kernel_mcs"))]
                         possible_switch_to(tcb);
                         #[cfg(feature = "kernel_mcs")]
-                        if let Some(tcbsc) =
-                            convert_to_option_mut_type_ref::<sched_context_t>(tcb.tcbSchedContext)
-                        {
+                        if let Some(tcbsc) = tcb.sched_context().get() {
                             if tcbsc.sc_active() {
-                                if let Some(sc) = convert_to_option_mut_type_ref::<sched_context_t>(
-                                    self.get_ntfnSchedContext() as usize,
-                                ) {
+                                if let Some(sc) = self.sched_context().get() {
                                     if tcbsc.get_ptr() == sc.get_ptr()
                                         && sc.sc_sporadic()
                                         && !tcbsc.is_current()
@@ -184,23 +288,33 @@ impl notification_func for notification {
                                 }
                             }
                         }
+                        IpcOutcome::WokeBoundTcb {
+                            thread: tcb.handle(),
+                            badge,
+                        }
                     } else {
                         self.active(badge);
+                        ipc_trace!(Signal, Activated, self.get_ptr(), 0, badge: badge);
+                        IpcOutcome::Activated
                     }
                 } else {
                     self.active(badge);
+                    ipc_trace!(Signal, Activated, self.get_ptr(), 0, badge: badge);
+                    IpcOutcome::Activated
                 }
             }
             NtfnState::Waiting => {
                 let mut queue = self.get_queue();
-                if let Some(dest) = convert_to_option_mut_type_ref::<tcb_t>(queue.head) {
+                if let Some(dest) = TcbRef::from_raw(queue.head).get() {
                     queue.ep_dequeue(dest);
+                    ipc_stats!(notification_dequeued(self.get_ptr(), dest.get_ptr()));
                     self.set_queue(&queue);
                     if queue.empty() {
-                        self.set_state(NtfnState::Idle as u64);
+                        self.set_ntfn_state(NtfnState::Idle);
                     }
                     set_thread_state(dest, ThreadState::ThreadStateRunning);
                     dest.tcbArch.set_register(ArchReg::Badge, badge);
+                    ipc_trace!(Signal, Delivered, self.get_ptr(), 0, peer: dest.get_ptr(), badge: badge);
                     #[cfg(feature = "kernel_mcs")]
                     {
                         maybe_donate_sched_context(dest, self);
@@ -211,36 +325,50 @@ impl notification_func for notification {
                     #[cfg(not(feature = "kernel_mcs"))]
                     possible_switch_to(dest);
                     #[cfg(feature = "kernel_mcs")]
-                    if let Some(sc) =
-                        convert_to_option_mut_type_ref::<sched_context_t>(dest.tcbSchedContext)
-                    {
-                        if sc.sc_sporadic() {
-                            assert!(!sc.is_current());
-                            if !sc.is_current() {
-                                sc.refill_unblock_check();
-                            }
+                    if let Some(sc) = dest.sched_context().get() {
+                        // The woken waiter does not run on the current scheduling context, skip
+                        // the refill if it somehow does.
+                        if sc.sc_sporadic() && !sc.is_current() {
+                            sc.refill_unblock_check();
                         }
                     }
+                    IpcOutcome::WokeWaiter {
+                        thread: dest.handle(),
+                        badge,
+                    }
                 } else {
-                    panic!("queue is empty!")
+                    return Err(IpcError::CorruptedObjectState {
+                        object: self.get_ptr(),
+                    });
                 }
             }
             NtfnState::Active => {
                 let mut badge2 = self.get_ntfnMsgIdentifier() as usize;
                 badge2 |= badge;
                 self.set_ntfnMsgIdentifier(badge2 as u64);
+                ipc_trace!(Signal, Coalesced, self.get_ptr(), 0, badge: badge);
+                ipc_stats!(notification_coalesced(self.get_ptr()));
+                IpcOutcome::Coalesced
             }
-        }
+        })
     }
 
     /// Receive a signal from the notification.
     /// 1: If the notification is idle or waiting, the receive thread is blocked immediately.
     /// 2: If the notification is active, the badge is sent to the receive thread.
+    /// Returns which of these happened, see `IpcOutcome`.
     /// # Arguments
     /// * `recv_thread` - The thread to receive the signal
     /// * `is_blocking` - If the signal is blocking
-    fn receive_signal(&mut self, recv_thread: &mut tcb_t, is_blocking: bool) {
-        match self.get_ntfn_state() {
+    fn receive_signal(
+        &mut self,
+        recv_thread: &mut tcb_t,
+        is_blocking: bool,
+    ) -> IpcResult<IpcOutcome> {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(Notification, self.get_ptr(), "notification::receive_signal");
+        Ok(match self.get_ntfn_state()? {
             NtfnState::Idle | NtfnState::Waiting => {
                 if is_blocking {
                     recv_thread
@@ -249,38 +377,52 @@ impl notification_func for notification {
                     set_thread_state(recv_thread, ThreadState::ThreadStateBlockedOnNotification);
                     let mut queue = self.get_queue();
                     queue.ep_append(recv_thread);
-                    self.set_state(NtfnState::Waiting as u64);
+                    self.set_ntfn_state(NtfnState::Waiting);
                     self.set_queue(&queue);
+                    ipc_trace!(Block, Blocked, self.get_ptr(), recv_thread.get_ptr());
+                    ipc_stats!(notification_enqueued(self.get_ptr(), recv_thread.get_ptr()));
                     #[cfg(feature = "kernel_mcs")]
                     self.maybe_return_sched_context(recv_thread);
+                    IpcOutcome::BlockedOnNotification(self.handle())
                 } else {
-                    recv_thread.tcbArch.set_register(ArchReg::Badge, 0);
+                    recv_thread
+                        .tcbArch
+                        .set_register(ArchReg::Badge, NO_MESSAGE_BADGE);
+                    ipc_trace!(Receive, Dropped, self.get_ptr(), recv_thread.get_ptr());
+                    IpcOutcome::Dropped
                 }
             }
 
             NtfnState::Active => {
-                recv_thread
-                    .tcbArch
-                    .set_register(ArchReg::Badge, self.get_ntfnMsgIdentifier() as usize);
-                self.set_state(NtfnState::Idle as u64);
+                let badge = self.get_ntfnMsgIdentifier() as usize;
+                recv_thread.tcbArch.set_register(ArchReg::Badge, badge);
+                self.set_ntfn_state(NtfnState::Idle);
+                ipc_trace!(
+                    Receive,
+                    Delivered,
+                    self.get_ptr(),
+                    recv_thread.get_ptr(),
+                    badge: self.get_ntfnMsgIdentifier(),
+                );
                 #[cfg(feature = "kernel_mcs")]
                 {
                     self.maybe_return_sched_context(recv_thread);
-                    if recv_thread.tcbSchedContext != NODE_STATE!(ksCurSC)
-                        && recv_thread.tcbSchedContext != 0
-                        && convert_to_mut_type_ref::<sched_context_t>(recv_thread.tcbSchedContext)
-                            .sc_sporadic()
-                    {
-                        convert_to_mut_type_ref::<sched_context_t>(recv_thread.tcbSchedContext)
-                            .refill_unblock_check();
+                    if let Some(sc) = recv_thread.sched_context().get() {
+                        if recv_thread.tcbSchedContext != NODE_STATE!(ksCurSC) && sc.sc_sporadic() {
+                            sc.refill_unblock_check();
+                        }
                     }
                 }
+                IpcOutcome::SignalReceived { badge }
             }
-        }
+        })
     }
     #[cfg(feature = "kernel_mcs")]
     #[no_mangle]
     fn reorder_ntfn(&mut self, thread: &mut tcb_t) {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_ntfn_claim!(self);
+        ipc_invariants!(Notification, self.get_ptr(), "notification::reorder_ntfn");
         let mut queue = self.get_queue();
         queue.ep_dequeue(thread);
         queue.ep_append(thread);
@@ -289,9 +431,13 @@ impl notification_func for notification {
     #[cfg(feature = "kernel_mcs")]
     #[inline]
     fn maybe_return_sched_context(&mut self, thread: &mut tcb_t) {
-        if let Some(sc) =
-            convert_to_option_mut_type_ref::<sched_context_t>(self.get_ntfnSchedContext() as usize)
-        {
+        ipc_lock!(lock_objects(self.get_ptr(), 0));
+        ipc_invariants!(
+            Notification,
+            self.get_ptr(),
+            "notification::maybe_return_sched_context"
+        );
+        if let Some(sc) = self.sched_context().get() {
             if sc.get_ptr() == thread.tcbSchedContext {
                 thread.tcbSchedContext = 0;
                 sc.scTcb = 0;
@@ -305,9 +451,7 @@ impl notification_func for notification {
 #[cfg(feature = "kernel_mcs")]
 pub fn maybe_donate_sched_context(tcb: &mut tcb_t, ntfnptr: &notification) {
     if tcb.tcbSchedContext == 0 {
-        if let Some(sc) = convert_to_option_mut_type_ref::<sched_context_t>(
-            ntfnptr.get_ntfnSchedContext() as usize,
-        ) {
+        if let Some(sc) = ntfnptr.sched_context().get() {
             if sc.scTcb == 0 {
                 sc.sched_context_donate(tcb);
                 sc.sched_context_resume();
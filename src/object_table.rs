@@ -0,0 +1,105 @@
+//! A fixed-size table attaching extra state to kernel objects.
+//!
+//! The layout of endpoints, notifications and TCBs is fixed by the generated bitfield
+//! structures, so the optional features of this crate keep their per-object state here, keyed
+//! by the object pointer. Lookups hash the pointer and probe linearly.
+
+/// Marks a slot that was removed, kernel objects are aligned so 1 is never a valid key
+const TOMBSTONE: usize = 1;
+
+pub(crate) struct ObjectTable<V: Copy, const N: usize> {
+    keys: [usize; N],
+    values: [V; N],
+}
+
+// Each feature using a table needs only some of the methods.
+#[allow(dead_code)]
+impl<V: Copy, const N: usize> ObjectTable<V, N> {
+    /// Create an empty table, `empty` is the value of newly inserted entries
+    pub(crate) const fn new(empty: V) -> Self {
+        ObjectTable {
+            keys: [0; N],
+            values: [empty; N],
+        }
+    }
+
+    #[inline]
+    fn start(key: usize) -> usize {
+        // Drop the alignment bits before hashing, they are the same for every object, and take
+        // the high bits of the product, the low ones are still zero for aligned objects.
+        ((((key as u64) >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as usize) % N
+    }
+
+    fn find(&self, key: usize) -> Option<usize> {
+        if key == 0 || key == TOMBSTONE {
+            return None;
+        }
+        let start = Self::start(key);
+        for i in 0..N {
+            let index = (start + i) % N;
+            match self.keys[index] {
+                0 => return None,
+                k if k == key => return Some(index),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Get the value of `key`
+    pub(crate) fn get(&self, key: usize) -> Option<&V> {
+        self.find(key).map(|index| &self.values[index])
+    }
+
+    /// Get the value of `key` mutably
+    pub(crate) fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+        self.find(key).map(|index| &mut self.values[index])
+    }
+
+    /// Get the value of `key`, inserting `empty` if it has none. Returns `None` if the table
+    /// is full.
+    pub(crate) fn get_or_insert(&mut self, key: usize, empty: V) -> Option<&mut V> {
+        if let Some(index) = self.find(key) {
+            return Some(&mut self.values[index]);
+        }
+        if key == 0 || key == TOMBSTONE {
+            return None;
+        }
+        let start = Self::start(key);
+        for i in 0..N {
+            let index = (start + i) % N;
+            if self.keys[index] == 0 || self.keys[index] == TOMBSTONE {
+                self.keys[index] = key;
+                self.values[index] = empty;
+                return Some(&mut self.values[index]);
+            }
+        }
+        None
+    }
+
+    /// Remove `key` from the table, returning its value
+    pub(crate) fn remove(&mut self, key: usize) -> Option<V> {
+        self.find(key).map(|index| {
+            self.keys[index] = TOMBSTONE;
+            self.values[index]
+        })
+    }
+
+    /// Remove the entries for which `f` returns false
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(usize, &V) -> bool) {
+        for (key, value) in self.keys.iter_mut().zip(self.values.iter()) {
+            if *key != 0 && *key != TOMBSTONE && !f(*key, value) {
+                *key = TOMBSTONE;
+            }
+        }
+    }
+
+    /// Visit every entry of the table
+    pub(crate) fn for_each(&self, mut f: impl FnMut(usize, &V)) {
+        for (key, value) in self.keys.iter().zip(self.values.iter()) {
+            if *key != 0 && *key != TOMBSTONE {
+                f(*key, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! What an IPC operation did.
+//!
+//! `send_ipc`, `receive_ipc`, `send_signal` and `receive_signal` return an `IpcOutcome`, so the
+//! syscall layer and tests can tell a blocked thread from a delivered message without looking
+//! at the thread state again.
+use sel4_common::arch::ArchReg;
+use sel4_common::message_info::seL4_MessageInfo_func;
+use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
+use sel4_common::structures_gen::seL4_Fault_tag;
+use sel4_task::tcb_t;
+
+use crate::handle::{tcb_handle_func, EndpointRef, NotificationRef, TcbRef};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A message delivered through an endpoint
+pub struct IpcTransfer {
+    pub sender: TcbRef,
+    pub receiver: TcbRef,
+    pub badge: usize,
+    /// Number of message words the receiver got
+    pub words: usize,
+    /// Number of caps the receiver got
+    pub caps: usize,
+    /// The message is the fault of the sender rather than a normal message
+    pub fault: bool,
+    /// The sender waits for the reply of the receiver, with a caller cap or, under MCS, on the
+    /// reply object of the receiver
+    pub caller_cap: bool,
+    /// The scheduling context of the sender was donated to the receiver, always false without
+    /// `kernel_mcs`
+    pub donated: bool,
+}
+
+impl IpcTransfer {
+    /// Describe the message `receiver` just got from `sender`
+    pub(crate) fn new(sender: &tcb_t, receiver: &tcb_t, badge: usize) -> Self {
+        let info = seL4_MessageInfo::from_word(receiver.tcbArch.get_register(ArchReg::MsgInfo));
+        IpcTransfer {
+            sender: sender.handle(),
+            receiver: receiver.handle(),
+            badge,
+            words: info.get_length() as usize,
+            caps: info.get_extraCaps() as usize,
+            fault: sender.tcbFault.get_tag() != seL4_Fault_tag::seL4_Fault_NullFault,
+            caller_cap: false,
+            donated: false,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The effect of an IPC operation
+pub enum IpcOutcome {
+    /// The thread was queued on the endpoint
+    BlockedOnEndpoint(EndpointRef),
+    /// The thread was queued on the notification
+    BlockedOnNotification(NotificationRef),
+    /// A non-blocking operation found nobody to talk to
+    Dropped,
+    /// A message was delivered
+    Transferred(IpcTransfer),
+    /// The call would have closed a cycle of the wait-for graph, so the caller was handed to
+    /// the deadlock fault handler instead of blocking
+    DeadlockFaultRaised,
+    /// The signal woke a thread waiting on the notification
+    WokeWaiter { thread: TcbRef, badge: usize },
+    /// The signal woke the bound TCB out of its receive on an endpoint
+    WokeBoundTcb { thread: TcbRef, badge: usize },
+    /// The signal made the notification active
+    Activated,
+    /// The signal was or'ed into the badge of an active notification
+    Coalesced,
+    /// The receiver took the badge of an active notification, its own for `receive_ipc`
+    SignalReceived { badge: usize },
+    /// A kernel service on the endpoint handled the message, and replied to the caller if
+    /// `replied`
+    #[cfg(feature = "kernel_services")]
+    KernelServed { badge: usize, replied: bool },
+}
@@ -0,0 +1,147 @@
+//! Priority inheritance for Call in the non-MCS kernel, enabled by the `priority_inheritance`
+//! feature. Under MCS it does nothing: the caller donates its scheduling context, and the
+//! server runs on it instead of at a priority of its own.
+//!
+//! A server holding the reply cap of a caller runs at least at the priority of that caller, so
+//! a thread of medium priority cannot hold up a call made at high priority. The callers of a
+//! server are the call edges of the wait-for graph, every change of an edge recomputes the
+//! priority of the server. A server blocked on a call of its own passes its priority on to the
+//! next server, so the whole call chain inherits.
+//!
+//! The own priority of a boosted thread is kept here until the boost ends. Priorities are
+//! changed with the hook set by `set_priority_hook`, which must requeue the thread like
+//! `setPriority` in seL4. Until then priorities are left alone, and the first boost skipped
+//! for lack of the hook is logged. The hook runs with the wait-for graph locked and must not
+//! call into IPC.
+use core::ptr::addr_of_mut;
+
+use sel4_task::tcb_t;
+
+use crate::handle::TcbRef;
+use crate::object_table::ObjectTable;
+#[cfg(feature = "fine_grained_locking")]
+use crate::wait_graph::WAIT_GRAPH_LOCK;
+use crate::wait_graph::{for_each_caller, server_of};
+
+/// Number of threads that can be boosted at once
+const MAX_BOOSTED_THREADS: usize = 256;
+/// Longest call chain followed, in case the graph has a cycle
+const MAX_CHAIN: usize = 64;
+
+struct PriorityInheritance {
+    /// Boosted thread -> its own priority
+    base: ObjectTable<usize, MAX_BOOSTED_THREADS>,
+    set_priority: Option<fn(&mut tcb_t, usize)>,
+    /// Boosts skipped because the table was full
+    untracked: u64,
+    /// Whether a boost was skipped because there is no priority hook
+    missing_hook_logged: bool,
+}
+
+static mut PRIORITY_INHERITANCE: PriorityInheritance = PriorityInheritance {
+    base: ObjectTable::new(0),
+    set_priority: None,
+    untracked: 0,
+    missing_hook_logged: false,
+};
+
+#[inline]
+fn inheritance() -> &'static mut PriorityInheritance {
+    unsafe { &mut *addr_of_mut!(PRIORITY_INHERITANCE) }
+}
+
+/// Set how the priority of a thread is changed, e.g. the `setPriority` of the kernel
+pub fn set_priority_hook(set_priority: fn(&mut tcb_t, usize)) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    inheritance().set_priority = Some(set_priority);
+}
+
+/// Get the own priority of `thread`, without what it inherits
+pub fn base_priority(thread: &tcb_t) -> usize {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    inheritance()
+        .base
+        .get(thread.get_ptr())
+        .copied()
+        .unwrap_or(thread.tcbPriority)
+}
+
+/// Set the own priority of `thread`. The kernel must use this instead of the priority hook for
+/// threads that may be boosted, so that the boost is kept. Without the hook the priority of a
+/// thread that is not boosted is written directly, the caller requeues it if it is ready.
+pub fn set_base_priority(thread: &mut tcb_t, priority: usize) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let pi = inheritance();
+    match pi.base.get_mut(thread.get_ptr()) {
+        Some(base) => *base = priority,
+        None => match pi.set_priority {
+            Some(set_priority) => set_priority(thread, priority),
+            None => thread.tcbPriority = priority,
+        },
+    }
+    update_inherited_priority(thread.get_ptr());
+}
+
+/// Forget the own priority of a deleted thread
+pub fn forget_priority_boost(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    inheritance().base.remove(thread.raw());
+}
+
+/// Number of boosts skipped because too many threads were boosted at once
+pub fn untracked_priority_boosts() -> u64 {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    inheritance().untracked
+}
+
+/// Give `server` the highest priority of its own and of the callers waiting on it, then do
+/// the same along the call chain it is blocked in
+pub(crate) fn update_inherited_priority(server: usize) {
+    if cfg!(feature = "kernel_mcs") {
+        return;
+    }
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let pi = inheritance();
+    let mut next = TcbRef::from_raw(server).get();
+    for _ in 0..MAX_CHAIN {
+        let thread = match next {
+            Some(thread) => thread,
+            None => return,
+        };
+        let base = base_priority(thread);
+        let mut priority = base;
+        for_each_caller(thread.get_ptr(), |caller| {
+            priority = core::cmp::max(priority, caller.tcbPriority);
+        });
+        if priority != thread.tcbPriority {
+            let set_priority = match pi.set_priority {
+                Some(set_priority) => set_priority,
+                None => {
+                    if !pi.missing_hook_logged {
+                        log::warn!(
+                            "priority boost of thread {:#x} skipped, no priority hook is set",
+                            thread.get_ptr()
+                        );
+                        pi.missing_hook_logged = true;
+                    }
+                    return;
+                }
+            };
+            if priority == base {
+                pi.base.remove(thread.get_ptr());
+            } else if pi.base.get_or_insert(thread.get_ptr(), base).is_none() {
+                log::warn!(
+                    "priority boost of thread {:#x} skipped, too many boosted threads",
+                    thread.get_ptr()
+                );
+                pi.untracked += 1;
+                return;
+            }
+            set_priority(thread, priority);
+        } else if thread.get_ptr() != server {
+            // Nothing changes further down the chain either.
+            return;
+        }
+        next = server_of(thread).and_then(|server| TcbRef::from_raw(server).get());
+    }
+}
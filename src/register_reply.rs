@@ -0,0 +1,54 @@
+//! Register-modifying replies to VM and cap faults, enabled by the `register_replies`
+//! feature.
+//!
+//! A pager that emulates the faulting instruction replies with `RegisterReply::LABEL` and the
+//! registers to set, e.g. the PC past the instruction and its result register. The registers
+//! are checked against `WRITABLE_REPLY_REGISTERS` and written through the hook set with
+//! `set_register_reply_hook`, a reply that fails the check writes nothing and leaves the
+//! thread inactive. Replies with any other label restart the thread unchanged, as before.
+use core::ptr::addr_of_mut;
+
+use sel4_task::tcb_t;
+
+use crate::fault_codec::RegisterReply;
+use crate::handle::tcb_handle_func;
+use crate::transfer::get_mr;
+
+static mut REGISTER_REPLY_HOOK: Option<fn(&mut tcb_t, usize, usize)> = None;
+
+/// Set the arch service that writes a register of a thread, numbered as in
+/// `seL4_UserContext`. Until it is set, register replies are rejected.
+pub fn set_register_reply_hook(write_register: fn(&mut tcb_t, usize, usize)) {
+    unsafe { *addr_of_mut!(REGISTER_REPLY_HOOK) = Some(write_register) };
+}
+
+/// Read the register reply of `replier` with `length` message registers
+fn read_register_reply(replier: &tcb_t, length: usize) -> Option<RegisterReply> {
+    let mut mrs = [0; RegisterReply::MAX_LENGTH];
+    for (index, mr) in mrs.get_mut(..length)?.iter_mut().enumerate() {
+        *mr = get_mr(replier, index)?;
+    }
+    RegisterReply::decode(&mrs[..length])
+}
+
+/// Apply the register reply of `replier` with `length` message registers to `thread`.
+/// Returns whether `thread` is restarted.
+pub(crate) fn do_register_reply(replier: &tcb_t, thread: &mut tcb_t, length: usize) -> bool {
+    let write_register = unsafe { *addr_of_mut!(REGISTER_REPLY_HOOK) };
+    match (write_register, read_register_reply(replier, length)) {
+        (Some(write_register), Some(reply)) => {
+            for write in reply.writes() {
+                write_register(thread, write.register, write.value);
+            }
+            true
+        }
+        _ => {
+            log::warn!(
+                "rejected register reply of {:#x} to {:#x}",
+                replier.handle(),
+                thread.handle()
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! The scheduler calls of the IPC paths. Waking a thread goes through here so that, with the
+//! `smp` feature, a thread bound to another core is woken there instead of on the current one,
+//! and with the `domain_isolation` feature a thread of another domain waits for its domain.
+use sel4_task::tcb_t;
+
+#[cfg(feature = "smp")]
+use crate::smp::{wake_remote, WakeupKind};
+
+/// Make `thread` runnable, switching to it if it should preempt the current thread
+#[inline]
+pub(crate) fn possible_switch_to(thread: &mut tcb_t) {
+    #[cfg(feature = "domain_isolation")]
+    if !crate::domain::in_current_domain(thread) {
+        #[cfg(feature = "kernel_mcs")]
+        if !thread.is_schedulable() {
+            return;
+        }
+        #[cfg(feature = "smp")]
+        if wake_remote(thread, WakeupKind::Enqueue) {
+            return;
+        }
+        thread.sched_enqueue();
+        return;
+    }
+    #[cfg(feature = "smp")]
+    if wake_remote(thread, WakeupKind::SwitchTo) {
+        return;
+    }
+    sel4_task::possible_switch_to(thread);
+}
+
+/// Append `thread` to the ready queue of its core
+#[cfg(not(feature = "kernel_mcs"))]
+#[inline]
+pub(crate) fn sched_enqueue(thread: &mut tcb_t) {
+    #[cfg(feature = "smp")]
+    if wake_remote(thread, WakeupKind::Enqueue) {
+        return;
+    }
+    thread.sched_enqueue();
+}
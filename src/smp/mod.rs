@@ -0,0 +1,130 @@
+//! Cross-core IPC wakeups, enabled by the `smp` feature.
+//!
+//! A thread woken by an IPC operation must run on the core of its affinity. When that is not
+//! the current core the wakeup is queued for the target core and the core is marked as owed a
+//! reschedule IPI, like `remoteQueueUpdate` in seL4. The kernel sends the IPIs with
+//! `send_reschedule_ipis` on its way out, and the IPI handler of the target core applies the
+//! wakeups with `handle_remote_wakeups`. All of this runs under the big kernel lock.
+//!
+//! The scheduler services are not available to this crate on every platform, so they are
+//! provided by the kernel with `set_smp_hooks`. Until then every wakeup is local.
+mod remote;
+pub mod sim;
+
+pub use remote::{RemoteWakeups, WakeupKind};
+
+use core::ptr::addr_of_mut;
+
+use sel4_task::tcb_t;
+
+use crate::handle::TcbRef;
+
+/// Number of cores wakeups can be queued for, must cover the cores of the platform
+pub const SMP_MAX_CORES: usize = 8;
+/// Number of wakeups that can be queued for one core
+pub const REMOTE_WAKEUP_DEPTH: usize = 64;
+
+#[derive(Clone, Copy)]
+/// The scheduler services needed for cross-core wakeups
+pub struct SmpHooks {
+    /// Index of the current core
+    pub current_core: fn() -> usize,
+    /// Core a thread is bound to
+    pub affinity_of: fn(&tcb_t) -> usize,
+    /// Send a reschedule IPI to a core
+    pub send_ipi: fn(usize),
+}
+
+struct SmpState {
+    hooks: Option<SmpHooks>,
+    remote: RemoteWakeups<SMP_MAX_CORES, REMOTE_WAKEUP_DEPTH>,
+    /// Wakeups applied locally because the queue of their core was full
+    overflowed: u64,
+}
+
+static mut SMP_STATE: SmpState = SmpState {
+    hooks: None,
+    remote: RemoteWakeups::new(),
+    overflowed: 0,
+};
+
+/// Wakeups are queued by cores holding unrelated object locks, so with per-object locking the
+/// queues have a lock of their own
+#[cfg(feature = "fine_grained_locking")]
+static REMOTE_WAKEUP_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+#[inline]
+fn smp() -> &'static mut SmpState {
+    unsafe { &mut *addr_of_mut!(SMP_STATE) }
+}
+
+/// Set the scheduler services used for cross-core wakeups
+pub fn set_smp_hooks(hooks: SmpHooks) {
+    smp().hooks = Some(hooks);
+}
+
+/// Queue the wakeup of `thread` if it belongs to another core. Returns false if the wakeup must
+/// be applied on the current core.
+pub(crate) fn wake_remote(thread: &tcb_t, kind: WakeupKind) -> bool {
+    ipc_lock!(lock_leaf(&REMOTE_WAKEUP_LOCK));
+    let smp = smp();
+    let hooks = match smp.hooks {
+        Some(hooks) => hooks,
+        None => return false,
+    };
+    let core = (hooks.affinity_of)(thread);
+    if core == (hooks.current_core)() {
+        return false;
+    }
+    if core >= SMP_MAX_CORES || !smp.remote.push(core, thread.get_ptr(), kind) {
+        log::error!(
+            "remote wakeup of thread {:#x} on core {} dropped to the current core",
+            thread.get_ptr(),
+            core
+        );
+        smp.overflowed += 1;
+        return false;
+    }
+    true
+}
+
+/// Send the reschedule IPIs owed to other cores, on the way out of the kernel
+pub fn send_reschedule_ipis() {
+    ipc_lock!(lock_leaf(&REMOTE_WAKEUP_LOCK));
+    let smp = smp();
+    if let Some(hooks) = smp.hooks {
+        smp.remote.take_ipis(hooks.send_ipi);
+    }
+}
+
+/// Apply the wakeups queued for the current core, from its reschedule IPI handler.
+/// Threads that stopped being runnable since they were queued are skipped.
+pub fn handle_remote_wakeups() {
+    ipc_lock!(lock_leaf(&REMOTE_WAKEUP_LOCK));
+    let smp = smp();
+    let hooks = match smp.hooks {
+        Some(hooks) => hooks,
+        None => return,
+    };
+    smp.remote.drain((hooks.current_core)(), |thread, kind| {
+        if let Some(thread) = TcbRef::from_raw(thread).get() {
+            if thread.is_runnable() {
+                match kind {
+                    WakeupKind::SwitchTo => sel4_task::possible_switch_to(thread),
+                    WakeupKind::Enqueue => thread.sched_enqueue(),
+                }
+            }
+        }
+    });
+}
+
+/// Drop the queued wakeups of a deleted thread
+pub fn forget_remote_wakeups(thread: TcbRef) {
+    ipc_lock!(lock_leaf(&REMOTE_WAKEUP_LOCK));
+    smp().remote.remove(thread.raw());
+}
+
+/// Number of remote wakeups applied on the wrong core because their queue was full
+pub fn overflowed_remote_wakeups() -> u64 {
+    smp().overflowed
+}
@@ -0,0 +1,94 @@
+//! The queues of wakeups waiting for another core. This file has no kernel dependencies, it is
+//! shared by the kernel path and the simulated scheduler in `sim.rs`, and tested on the host by
+//! `tools/smp_model`.
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// How a thread woken on another core is made runnable there
+pub enum WakeupKind {
+    /// `possible_switch_to`, the thread may preempt the current one
+    SwitchTo,
+    /// `sched_enqueue`, the thread joins the ready queue
+    Enqueue,
+}
+
+/// A queue of pending wakeups per core and the cores owed a reschedule IPI. A thread is queued
+/// at most once, waking it again only upgrades an `Enqueue` to a `SwitchTo`.
+pub struct RemoteWakeups<const CORES: usize, const DEPTH: usize> {
+    threads: [[usize; DEPTH]; CORES],
+    kinds: [[WakeupKind; DEPTH]; CORES],
+    lens: [usize; CORES],
+    ipi_pending: [bool; CORES],
+}
+
+impl<const CORES: usize, const DEPTH: usize> RemoteWakeups<CORES, DEPTH> {
+    pub const fn new() -> Self {
+        RemoteWakeups {
+            threads: [[0; DEPTH]; CORES],
+            kinds: [[WakeupKind::Enqueue; DEPTH]; CORES],
+            lens: [0; CORES],
+            ipi_pending: [false; CORES],
+        }
+    }
+
+    /// Queue a wakeup of `thread` on `core` and mark the core as owed an IPI.
+    /// Returns false if the queue of the core is full, the wakeup is not queued then.
+    pub fn push(&mut self, core: usize, thread: usize, kind: WakeupKind) -> bool {
+        let len = self.lens[core];
+        if let Some(index) = self.threads[core][..len].iter().position(|t| *t == thread) {
+            if kind == WakeupKind::SwitchTo {
+                self.kinds[core][index] = kind;
+            }
+            self.ipi_pending[core] = true;
+            return true;
+        }
+        if len == DEPTH {
+            return false;
+        }
+        self.threads[core][len] = thread;
+        self.kinds[core][len] = kind;
+        self.lens[core] = len + 1;
+        self.ipi_pending[core] = true;
+        true
+    }
+
+    /// Remove the pending wakeups of `thread`, e.g. because it was deleted
+    pub fn remove(&mut self, thread: usize) {
+        for core in 0..CORES {
+            let len = self.lens[core];
+            if let Some(index) = self.threads[core][..len].iter().position(|t| *t == thread) {
+                self.threads[core].copy_within(index + 1..len, index);
+                self.kinds[core].copy_within(index + 1..len, index);
+                self.lens[core] = len - 1;
+            }
+        }
+    }
+
+    /// Visit, and clear, the cores owed a reschedule IPI
+    pub fn take_ipis(&mut self, mut send_ipi: impl FnMut(usize)) {
+        for core in 0..CORES {
+            if self.ipi_pending[core] {
+                self.ipi_pending[core] = false;
+                send_ipi(core);
+            }
+        }
+    }
+
+    /// Visit, and clear, the wakeups queued for `core` in the order they were queued
+    pub fn drain(&mut self, core: usize, mut wake: impl FnMut(usize, WakeupKind)) {
+        let len = core::mem::replace(&mut self.lens[core], 0);
+        for index in 0..len {
+            wake(self.threads[core][index], self.kinds[core][index]);
+        }
+    }
+
+    /// Number of wakeups queued for `core`
+    pub fn pending(&self, core: usize) -> usize {
+        self.lens[core]
+    }
+}
+
+impl<const CORES: usize, const DEPTH: usize> Default for RemoteWakeups<CORES, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,121 @@
+//! A simulated multi-node scheduler to test cross-core wakeups on the host.
+//!
+//! Threads are plain ids with a fixed affinity, each core has a ready queue, and IPIs are
+//! delivered when the test calls `deliver_ipis`. Wakeups go through the same `RemoteWakeups`
+//! the kernel path uses. This file only depends on `remote.rs`, the host test in
+//! `tools/smp_model` and the `smp_wakeups` fuzz target run it.
+use super::remote::{RemoteWakeups, WakeupKind};
+
+/// Longest ready queue of a simulated core
+pub const SIM_READY_DEPTH: usize = 64;
+
+pub struct SimScheduler<const CORES: usize, const DEPTH: usize> {
+    /// Core the simulated kernel is running on
+    pub current_core: usize,
+    ready: [[usize; SIM_READY_DEPTH]; CORES],
+    ready_lens: [usize; CORES],
+    /// Thread each core was asked to switch to by its last `SwitchTo` wakeup
+    pub switched_to: [Option<usize>; CORES],
+    /// IPIs sent and not yet delivered
+    ipis_in_flight: [bool; CORES],
+    /// IPIs sent so far
+    pub ipis_sent: usize,
+    remote: RemoteWakeups<CORES, DEPTH>,
+}
+
+impl<const CORES: usize, const DEPTH: usize> SimScheduler<CORES, DEPTH> {
+    pub const fn new() -> Self {
+        SimScheduler {
+            current_core: 0,
+            ready: [[0; SIM_READY_DEPTH]; CORES],
+            ready_lens: [0; CORES],
+            switched_to: [None; CORES],
+            ipis_in_flight: [false; CORES],
+            ipis_sent: 0,
+            remote: RemoteWakeups::new(),
+        }
+    }
+
+    fn make_ready(&mut self, core: usize, thread: usize, kind: WakeupKind) {
+        if kind == WakeupKind::SwitchTo {
+            self.switched_to[core] = Some(thread);
+        }
+        let len = self.ready_lens[core];
+        if self.ready[core][..len].contains(&thread) {
+            return;
+        }
+        assert!(
+            len < SIM_READY_DEPTH,
+            "ready queue of core {core} overflowed"
+        );
+        self.ready[core][len] = thread;
+        self.ready_lens[core] = len + 1;
+    }
+
+    /// Wake `thread`, whose affinity is `affinity`, from the current core like the IPC paths do.
+    /// Returns false if the remote queue was full and the wakeup was lost.
+    pub fn wake(&mut self, thread: usize, affinity: usize, kind: WakeupKind) -> bool {
+        if affinity == self.current_core {
+            self.make_ready(affinity, thread, kind);
+            true
+        } else {
+            self.remote.push(affinity, thread, kind)
+        }
+    }
+
+    /// Leave the kernel on the current core, sending the pending reschedule IPIs
+    pub fn exit_kernel(&mut self) {
+        let (in_flight, sent) = (&mut self.ipis_in_flight, &mut self.ipis_sent);
+        self.remote.take_ipis(|core| {
+            in_flight[core] = true;
+            *sent += 1;
+        });
+    }
+
+    /// Deliver the IPIs in flight, each target core drains its remote wakeups
+    pub fn deliver_ipis(&mut self) {
+        for core in 0..CORES {
+            if core::mem::replace(&mut self.ipis_in_flight[core], false) {
+                let mut woken = [(0, WakeupKind::Enqueue); DEPTH];
+                let mut count = 0;
+                self.remote.drain(core, |thread, kind| {
+                    woken[count] = (thread, kind);
+                    count += 1;
+                });
+                for (thread, kind) in woken.into_iter().take(count) {
+                    self.make_ready(core, thread, kind);
+                }
+            }
+        }
+    }
+
+    /// Threads ready on `core`
+    pub fn ready(&self, core: usize) -> &[usize] {
+        &self.ready[core][..self.ready_lens[core]]
+    }
+
+    /// Wakeups still queued for `core`
+    pub fn pending(&self, core: usize) -> usize {
+        self.remote.pending(core)
+    }
+
+    /// Take a thread off the ready queue of its core, as if it ran and blocked again. Its
+    /// queued wakeups are dropped, like `handle_remote_wakeups` skips threads not runnable.
+    pub fn block(&mut self, core: usize, thread: usize) {
+        self.remote.remove(thread);
+        let len = self.ready_lens[core];
+        if let Some(index) = self.ready[core][..len].iter().position(|t| *t == thread) {
+            self.ready[core].copy_within(index + 1..len, index);
+            self.ready_lens[core] = len - 1;
+        }
+        if self.switched_to[core] == Some(thread) {
+            self.switched_to[core] = None;
+        }
+    }
+}
+
+impl<const CORES: usize, const DEPTH: usize> Default for SimScheduler<CORES, DEPTH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,326 @@
+//! Per-object IPC statistics, enabled by the `stats` feature.
+//!
+//! Counters are attached to each endpoint and notification the first time it is used, and
+//! the time every thread spends queued on an object is recorded in a histogram of clock
+//! cycles. The debug queries return a snapshot and optionally reset it.
+use core::ptr::addr_of_mut;
+
+use crate::clock;
+use crate::handle::{EndpointRef, Handle, NotificationRef};
+use crate::object_table::ObjectTable;
+
+/// Number of endpoints and of notifications that get counters
+pub const STATS_MAX_OBJECTS: usize = 256;
+/// Number of threads whose enqueue time can be tracked at once
+const STATS_MAX_QUEUED: usize = 512;
+/// Number of histogram buckets, bucket `i` counts waits of `[2^i, 2^(i+1))` cycles
+pub const QUEUED_HISTOGRAM_BUCKETS: usize = 32;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// A histogram of the cycles threads spent queued, in power-of-two buckets
+pub struct QueuedHistogram {
+    pub buckets: [u64; QUEUED_HISTOGRAM_BUCKETS],
+    pub max: u64,
+}
+
+impl QueuedHistogram {
+    const EMPTY: QueuedHistogram = QueuedHistogram {
+        buckets: [0; QUEUED_HISTOGRAM_BUCKETS],
+        max: 0,
+    };
+
+    fn record(&mut self, cycles: u64) {
+        let bucket = (u64::BITS - cycles.leading_zeros()).saturating_sub(1) as usize;
+        self.buckets[core::cmp::min(bucket, QUEUED_HISTOGRAM_BUCKETS - 1)] += 1;
+        self.max = core::cmp::max(self.max, cycles);
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The counters of an endpoint
+pub struct EndpointStats {
+    /// Messages transferred through the endpoint
+    pub delivered: u64,
+    /// Delivered messages that were calls
+    pub calls: u64,
+    pub blocked_sends: u64,
+    pub blocked_receives: u64,
+    /// Caps inserted into the receive slot of a receiver
+    pub caps_transferred: u64,
+    /// Caps to this endpoint that were unwrapped into their badge
+    pub caps_unwrapped: u64,
+    /// Threads cancelled or restarted while queued
+    pub cancellations: u64,
+    pub queue_depth: u64,
+    pub max_queue_depth: u64,
+    pub queued: QueuedHistogram,
+}
+
+impl EndpointStats {
+    const EMPTY: EndpointStats = EndpointStats {
+        delivered: 0,
+        calls: 0,
+        blocked_sends: 0,
+        blocked_receives: 0,
+        caps_transferred: 0,
+        caps_unwrapped: 0,
+        cancellations: 0,
+        queue_depth: 0,
+        max_queue_depth: 0,
+        queued: QueuedHistogram::EMPTY,
+    };
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// The counters of a notification
+pub struct NotificationStats {
+    pub signals: u64,
+    /// Signals OR-ed into an already active notification
+    pub coalesced: u64,
+    /// Signals delivered straight to the bound TCB blocked on an endpoint
+    pub bound_fast_deliveries: u64,
+    /// Signals delivered to a thread waiting on the notification
+    pub waiter_deliveries: u64,
+    pub blocked_waits: u64,
+    pub cancellations: u64,
+    pub queued: QueuedHistogram,
+}
+
+impl NotificationStats {
+    const EMPTY: NotificationStats = NotificationStats {
+        signals: 0,
+        coalesced: 0,
+        bound_fast_deliveries: 0,
+        waiter_deliveries: 0,
+        blocked_waits: 0,
+        cancellations: 0,
+        queued: QueuedHistogram::EMPTY,
+    };
+}
+
+struct IpcStats {
+    endpoints: ObjectTable<EndpointStats, STATS_MAX_OBJECTS>,
+    notifications: ObjectTable<NotificationStats, STATS_MAX_OBJECTS>,
+    /// Clock value at which a thread was queued
+    enqueued_at: ObjectTable<u64, STATS_MAX_QUEUED>,
+    /// Events lost because a table was full
+    untracked: u64,
+}
+
+static mut IPC_STATS: IpcStats = IpcStats {
+    endpoints: ObjectTable::new(EndpointStats::EMPTY),
+    notifications: ObjectTable::new(NotificationStats::EMPTY),
+    enqueued_at: ObjectTable::new(0),
+    untracked: 0,
+};
+
+#[inline]
+fn stats() -> &'static mut IpcStats {
+    unsafe { &mut *addr_of_mut!(IPC_STATS) }
+}
+
+/// Events are recorded by cores holding unrelated object locks, so with per-object locking the
+/// counters have a lock of their own
+#[cfg(feature = "fine_grained_locking")]
+static STATS_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+fn with_endpoint(ep: usize, f: impl FnOnce(&mut EndpointStats)) {
+    let stats = stats();
+    match stats.endpoints.get_or_insert(ep, EndpointStats::EMPTY) {
+        Some(ep_stats) => f(ep_stats),
+        None => stats.untracked += 1,
+    }
+}
+
+fn with_notification(ntfn: usize, f: impl FnOnce(&mut NotificationStats)) {
+    let stats = stats();
+    match stats
+        .notifications
+        .get_or_insert(ntfn, NotificationStats::EMPTY)
+    {
+        Some(ntfn_stats) => f(ntfn_stats),
+        None => stats.untracked += 1,
+    }
+}
+
+fn mark_enqueued(thread: usize) {
+    let stats = stats();
+    match stats.enqueued_at.get_or_insert(thread, 0) {
+        Some(at) => *at = clock::now(),
+        None => stats.untracked += 1,
+    }
+}
+
+/// Get the cycles `thread` spent queued, `None` if it was not tracked
+fn take_queued_cycles(thread: usize) -> Option<u64> {
+    stats()
+        .enqueued_at
+        .remove(thread)
+        .map(|at| clock::now().saturating_sub(at))
+}
+
+/// A thread was appended to the queue of an endpoint
+pub(crate) fn endpoint_enqueued(ep: usize, thread: usize, is_send: bool) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    mark_enqueued(thread);
+    with_endpoint(ep, |stats| {
+        if is_send {
+            stats.blocked_sends += 1;
+        } else {
+            stats.blocked_receives += 1;
+        }
+        stats.queue_depth += 1;
+        stats.max_queue_depth = core::cmp::max(stats.max_queue_depth, stats.queue_depth);
+    });
+}
+
+/// A queued thread took part in a message transfer on an endpoint
+pub(crate) fn endpoint_dequeued(ep: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    let cycles = take_queued_cycles(thread);
+    with_endpoint(ep, |stats| {
+        stats.queue_depth = stats.queue_depth.saturating_sub(1);
+        if let Some(cycles) = cycles {
+            stats.queued.record(cycles);
+        }
+    });
+}
+
+/// A message was transferred through an endpoint
+pub(crate) fn endpoint_delivered(ep: usize, is_call: bool) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    with_endpoint(ep, |stats| {
+        stats.delivered += 1;
+        if is_call {
+            stats.calls += 1;
+        }
+    });
+}
+
+/// A cap was transferred with a message through an endpoint
+pub(crate) fn endpoint_cap_transferred(ep: usize, unwrapped: bool) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    if ep == 0 {
+        return;
+    }
+    with_endpoint(ep, |stats| {
+        if unwrapped {
+            stats.caps_unwrapped += 1;
+        } else {
+            stats.caps_transferred += 1;
+        }
+    });
+}
+
+/// A queued thread was removed from an endpoint without a transfer
+pub(crate) fn endpoint_cancelled(ep: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    stats().enqueued_at.remove(thread);
+    with_endpoint(ep, |stats| {
+        stats.cancellations += 1;
+        stats.queue_depth = stats.queue_depth.saturating_sub(1);
+    });
+}
+
+/// A signal was sent to a notification
+pub(crate) fn notification_signalled(ntfn: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    with_notification(ntfn, |stats| stats.signals += 1);
+}
+
+/// A signal was OR-ed into an active notification
+pub(crate) fn notification_coalesced(ntfn: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    with_notification(ntfn, |stats| stats.coalesced += 1);
+}
+
+/// A signal went straight to the bound TCB
+pub(crate) fn notification_bound_delivered(ntfn: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    with_notification(ntfn, |stats| stats.bound_fast_deliveries += 1);
+}
+
+/// A thread started waiting on a notification
+pub(crate) fn notification_enqueued(ntfn: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    mark_enqueued(thread);
+    with_notification(ntfn, |stats| stats.blocked_waits += 1);
+}
+
+/// A waiting thread received a signal
+pub(crate) fn notification_dequeued(ntfn: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    let cycles = take_queued_cycles(thread);
+    with_notification(ntfn, |stats| {
+        stats.waiter_deliveries += 1;
+        if let Some(cycles) = cycles {
+            stats.queued.record(cycles);
+        }
+    });
+}
+
+/// A waiting thread was removed from a notification without a signal
+pub(crate) fn notification_cancelled(ntfn: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    stats().enqueued_at.remove(thread);
+    with_notification(ntfn, |stats| stats.cancellations += 1);
+}
+
+/// Read the counters of an endpoint, resetting them if `reset` is set.
+/// The queue depth is kept, it describes the endpoint rather than past events.
+pub fn endpoint_stats(ep: EndpointRef, reset: bool) -> Option<EndpointStats> {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    let ep_stats = stats().endpoints.get_mut(ep.raw())?;
+    let snapshot = *ep_stats;
+    if reset {
+        *ep_stats = EndpointStats {
+            queue_depth: snapshot.queue_depth,
+            max_queue_depth: snapshot.queue_depth,
+            ..EndpointStats::EMPTY
+        };
+    }
+    Some(snapshot)
+}
+
+/// Read the counters of a notification, resetting them if `reset` is set
+pub fn notification_stats(ntfn: NotificationRef, reset: bool) -> Option<NotificationStats> {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    let ntfn_stats = stats().notifications.get_mut(ntfn.raw())?;
+    let snapshot = *ntfn_stats;
+    if reset {
+        *ntfn_stats = NotificationStats::EMPTY;
+    }
+    Some(snapshot)
+}
+
+/// Visit the counters of every endpoint seen so far, to find the hot ones. `f` runs with the
+/// counters locked and must not call into IPC.
+pub fn for_each_endpoint_stats(mut f: impl FnMut(EndpointRef, &EndpointStats)) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    stats()
+        .endpoints
+        .for_each(|ep, ep_stats| f(EndpointRef::from_raw(ep), ep_stats));
+}
+
+/// Visit the counters of every notification seen so far, `f` must not call into IPC
+pub fn for_each_notification_stats(mut f: impl FnMut(NotificationRef, &NotificationStats)) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    stats()
+        .notifications
+        .for_each(|ntfn, ntfn_stats| f(NotificationRef::from_raw(ntfn), ntfn_stats));
+}
+
+/// Forget the counters of a deleted endpoint or notification
+pub fn forget_object_stats(object: impl Handle) {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    let object = object.raw();
+    let stats = stats();
+    stats.endpoints.remove(object);
+    stats.notifications.remove(object);
+}
+
+/// Number of events lost because the counters of an object or thread did not fit
+pub fn untracked_stats_events() -> u64 {
+    ipc_lock!(lock_leaf(&STATS_LOCK));
+    stats().untracked
+}
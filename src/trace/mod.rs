@@ -0,0 +1,123 @@
+//! IPC event tracing, enabled by the `trace` feature.
+//!
+//! Every send, receive, block, wakeup, reply, signal, cancel and cap transfer is written into a
+//! fixed-size ring buffer, the oldest records are overwritten once it is full. The buffer is
+//! read out with `trace_dump` and decoded on the host by `tools/ipc_trace_decode`. Records are
+//! timestamped with the clock set by `set_ipc_clock`.
+mod record;
+
+pub use record::*;
+
+use core::ptr::addr_of_mut;
+
+use sel4_common::arch::ArchReg;
+use sel4_common::message_info::seL4_MessageInfo_func;
+use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
+use sel4_task::tcb_t;
+
+use crate::clock;
+
+/// Number of records kept in the ring buffer
+pub const TRACE_BUFFER_ENTRIES: usize = 1024;
+
+struct TraceBuffer {
+    records: [TraceRecord; TRACE_BUFFER_ENTRIES],
+    /// Number of records written since boot, the next record goes to `recorded % ENTRIES`
+    recorded: u64,
+    enabled: bool,
+}
+
+static mut TRACE_BUFFER: TraceBuffer = TraceBuffer {
+    records: [TraceRecord::EMPTY; TRACE_BUFFER_ENTRIES],
+    recorded: 0,
+    enabled: true,
+};
+
+#[inline]
+fn buffer() -> &'static mut TraceBuffer {
+    unsafe { &mut *addr_of_mut!(TRACE_BUFFER) }
+}
+
+/// Records are written by cores holding unrelated object locks, so with per-object locking the
+/// buffer has a lock of its own
+#[cfg(feature = "fine_grained_locking")]
+static TRACE_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Enable or disable recording, the records already in the buffer are kept
+pub fn set_trace_enabled(enabled: bool) {
+    ipc_lock!(lock_leaf(&TRACE_LOCK));
+    buffer().enabled = enabled;
+}
+
+/// Append a record to the ring buffer, filling in its sequence number and timestamp
+pub fn trace_record(mut record: TraceRecord) {
+    ipc_lock!(lock_leaf(&TRACE_LOCK));
+    let buffer = buffer();
+    if !buffer.enabled {
+        return;
+    }
+    record.seq = buffer.recorded;
+    record.timestamp = clock::now();
+    buffer.records[(buffer.recorded % TRACE_BUFFER_ENTRIES as u64) as usize] = record;
+    buffer.recorded += 1;
+}
+
+/// Visit the records in the buffer from the oldest to the newest. `f` runs with the buffer
+/// locked and must not call into IPC.
+pub fn trace_for_each(mut f: impl FnMut(&TraceRecord)) {
+    ipc_lock!(lock_leaf(&TRACE_LOCK));
+    let buffer = buffer();
+    let count = core::cmp::min(buffer.recorded, TRACE_BUFFER_ENTRIES as u64);
+    for seq in buffer.recorded - count..buffer.recorded {
+        f(&buffer.records[(seq % TRACE_BUFFER_ENTRIES as u64) as usize]);
+    }
+}
+
+/// Drop all records in the buffer
+pub fn trace_clear() {
+    ipc_lock!(lock_leaf(&TRACE_LOCK));
+    buffer().recorded = 0;
+}
+
+/// Dump the buffer into `out` as little-endian words, see `TRACE_DUMP_MAGIC`.
+/// Returns the number of bytes written, records that do not fit are left out.
+pub fn trace_dump(out: &mut [u8]) -> usize {
+    ipc_lock!(lock_leaf(&TRACE_LOCK));
+    let buffer = buffer();
+    let mut written = 0;
+    let header: [u64; TRACE_DUMP_HEADER_WORDS] = [
+        TRACE_DUMP_MAGIC,
+        TRACE_DUMP_VERSION,
+        TRACE_BUFFER_ENTRIES as u64,
+        buffer.recorded,
+    ];
+    if out.len() < TRACE_DUMP_HEADER_WORDS * 8 {
+        return 0;
+    }
+    for word in header {
+        put_word(out, &mut written, word);
+    }
+    let count = core::cmp::min(buffer.recorded, TRACE_BUFFER_ENTRIES as u64);
+    for seq in buffer.recorded - count..buffer.recorded {
+        if out.len() - written < TRACE_RECORD_WORDS * 8 {
+            break;
+        }
+        let record = &buffer.records[(seq % TRACE_BUFFER_ENTRIES as u64) as usize];
+        for word in record.to_words() {
+            put_word(out, &mut written, word);
+        }
+    }
+    written
+}
+
+#[inline]
+fn put_word(out: &mut [u8], written: &mut usize, word: u64) {
+    out[*written..*written + 8].copy_from_slice(&word.to_le_bytes());
+    *written += 8;
+}
+
+/// Get the message info a thread has received, for the label and length of a record
+#[inline]
+pub(crate) fn received_info(thread: &tcb_t) -> seL4_MessageInfo {
+    seL4_MessageInfo::from_word(thread.tcbArch.get_register(ArchReg::MsgInfo))
+}
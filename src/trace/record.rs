@@ -0,0 +1,189 @@
+//! The record format of the IPC trace buffer.
+//!
+//! This file has no dependencies, it is shared with the host-side decoder in
+//! `tools/ipc_trace_decode`, so both sides always agree on the dump layout.
+
+/// Magic number at the start of a dumped trace buffer
+pub const TRACE_DUMP_MAGIC: u64 = u64::from_le_bytes(*b"RL4IPCTR");
+/// Version of the dump layout, bumped on every change of `TraceRecord`
+pub const TRACE_DUMP_VERSION: u64 = 1;
+/// Number of 64-bit words in the dump header: magic, version, entries and recorded
+pub const TRACE_DUMP_HEADER_WORDS: usize = 4;
+/// Number of 64-bit words of one record in a dump
+pub const TRACE_RECORD_WORDS: usize = 9;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u8)]
+/// What happened on an IPC object
+pub enum TraceEvent {
+    Send = 0,
+    Receive = 1,
+    Block = 2,
+    Wakeup = 3,
+    Reply = 4,
+    Signal = 5,
+    Cancel = 6,
+    CapTransfer = 7,
+}
+
+impl TraceEvent {
+    pub const ALL: [TraceEvent; 8] = [
+        TraceEvent::Send,
+        TraceEvent::Receive,
+        TraceEvent::Block,
+        TraceEvent::Wakeup,
+        TraceEvent::Reply,
+        TraceEvent::Signal,
+        TraceEvent::Cancel,
+        TraceEvent::CapTransfer,
+    ];
+
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        Self::ALL.get(raw as usize).copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceEvent::Send => "send",
+            TraceEvent::Receive => "receive",
+            TraceEvent::Block => "block",
+            TraceEvent::Wakeup => "wakeup",
+            TraceEvent::Reply => "reply",
+            TraceEvent::Signal => "signal",
+            TraceEvent::Cancel => "cancel",
+            TraceEvent::CapTransfer => "cap_transfer",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u8)]
+/// The outcome of a traced event
+pub enum TraceOutcome {
+    /// A message, badge or reply reached the peer thread
+    Delivered = 0,
+    /// The thread was queued on the object
+    Blocked = 1,
+    /// A non-blocking operation found no peer
+    Dropped = 2,
+    /// A signal made an idle notification active
+    Activated = 3,
+    /// A signal was OR-ed into an active notification
+    Coalesced = 4,
+    /// An endpoint cap was unwrapped into its badge
+    Unwrapped = 5,
+    /// A cap was inserted into the receive slot
+    Transferred = 6,
+    /// The thread was set to restart
+    Restarted = 7,
+    /// The thread was set to inactive
+    Cancelled = 8,
+}
+
+impl TraceOutcome {
+    pub const ALL: [TraceOutcome; 9] = [
+        TraceOutcome::Delivered,
+        TraceOutcome::Blocked,
+        TraceOutcome::Dropped,
+        TraceOutcome::Activated,
+        TraceOutcome::Coalesced,
+        TraceOutcome::Unwrapped,
+        TraceOutcome::Transferred,
+        TraceOutcome::Restarted,
+        TraceOutcome::Cancelled,
+    ];
+
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        Self::ALL.get(raw as usize).copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceOutcome::Delivered => "delivered",
+            TraceOutcome::Blocked => "blocked",
+            TraceOutcome::Dropped => "dropped",
+            TraceOutcome::Activated => "activated",
+            TraceOutcome::Coalesced => "coalesced",
+            TraceOutcome::Unwrapped => "unwrapped",
+            TraceOutcome::Transferred => "transferred",
+            TraceOutcome::Restarted => "restarted",
+            TraceOutcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(C)]
+/// One entry of the trace buffer
+pub struct TraceRecord {
+    /// Sequence number of the record since boot
+    pub seq: u64,
+    /// Value of the IPC clock, 0 if no clock is set
+    pub timestamp: u64,
+    /// The endpoint or notification, 0 for replies without an object
+    pub object: u64,
+    /// The thread doing the operation
+    pub thread: u64,
+    /// The thread on the other side, 0 if there is none
+    pub peer: u64,
+    pub badge: u64,
+    pub label: u64,
+    pub length: u64,
+    /// `TraceEvent` in the low byte, `TraceOutcome` in the next one
+    pub kind: u64,
+}
+
+impl TraceRecord {
+    pub const EMPTY: TraceRecord = TraceRecord {
+        seq: 0,
+        timestamp: 0,
+        object: 0,
+        thread: 0,
+        peer: 0,
+        badge: 0,
+        label: 0,
+        length: 0,
+        kind: 0,
+    };
+
+    #[inline]
+    pub fn kind_of(event: TraceEvent, outcome: TraceOutcome) -> u64 {
+        event as u64 | (outcome as u64) << 8
+    }
+
+    pub fn event(&self) -> Option<TraceEvent> {
+        TraceEvent::from_raw(self.kind as u8)
+    }
+
+    pub fn outcome(&self) -> Option<TraceOutcome> {
+        TraceOutcome::from_raw((self.kind >> 8) as u8)
+    }
+
+    pub fn to_words(self) -> [u64; TRACE_RECORD_WORDS] {
+        [
+            self.seq,
+            self.timestamp,
+            self.object,
+            self.thread,
+            self.peer,
+            self.badge,
+            self.label,
+            self.length,
+            self.kind,
+        ]
+    }
+
+    pub fn from_words(words: &[u64; TRACE_RECORD_WORDS]) -> Self {
+        TraceRecord {
+            seq: words[0],
+            timestamp: words[1],
+            object: words[2],
+            thread: words[3],
+            peer: words[4],
+            badge: words[5],
+            label: words[6],
+            length: words[7],
+            kind: words[8],
+        }
+    }
+}
@@ -1,31 +1,46 @@
 use core::intrinsics::likely;
 use core::intrinsics::unlikely;
 
+use super::abi::{MessageInfo, ReceivedCaps};
+#[cfg(feature = "hardware_debug_api")]
+use super::debug_exception::{debug_exception_of, do_debug_reply};
+#[cfg(feature = "double_fault_policy")]
+use super::double_fault::{fault_delivered, fault_replied};
 use super::endpoint::*;
+use super::error::{IpcError, IpcResult};
+use super::fault_codec::*;
+#[cfg(feature = "fault_profiles")]
+use super::fault_profile::{copy_profile_fault_mrs, copy_profile_reply};
+use super::handle::*;
 use super::notification::*;
+#[cfg(feature = "register_replies")]
+use super::register_reply::do_register_reply;
+use super::sched::possible_switch_to;
 
+#[cfg(any(
+    feature = "register_replies",
+    feature = "fault_profiles",
+    feature = "kernel_services"
+))]
+use sel4_common::arch::n_msgRegisters;
 use sel4_common::arch::ArchReg;
-#[cfg(feature = "kernel_mcs")]
-use sel4_common::arch::N_TIMEOUT_MESSAGE;
-use sel4_common::arch::{N_EXCEPTON_MESSAGE, N_SYSCALL_MESSAGE};
-use sel4_common::fault::*;
 use sel4_common::message_info::seL4_MessageInfo_func;
 use sel4_common::sel4_config::*;
 use sel4_common::shared_types_bf_gen::seL4_MessageInfo;
 use sel4_common::structures::*;
 use sel4_common::structures_gen::{
-    cap, cap_tag, endpoint, notification, seL4_Fault, seL4_Fault_NullFault, seL4_Fault_tag,
+    cap, cap_tag, endpoint, seL4_Fault, seL4_Fault_NullFault, seL4_Fault_tag,
 };
 use sel4_common::utils::*;
 use sel4_cspace::interface::*;
-use sel4_task::{possible_switch_to, set_thread_state, tcb_t, ThreadState};
 #[cfg(feature = "kernel_mcs")]
-use sel4_task::{reply::reply_t, reply_remove_tcb, sched_context::sched_context_t};
+use sel4_task::{reply::reply_t, reply_remove_tcb};
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
 use sel4_vspace::pptr_t;
 
 /// The trait for IPC transfer, please see doc.md for more details
 pub trait Transfer {
-    fn cancel_ipc(&mut self);
+    fn cancel_ipc(&mut self) -> IpcResult<()>;
 
     fn set_transfer_caps(
         &mut self,
@@ -42,7 +57,7 @@ pub trait Transfer {
         ipc_buffer: Option<&mut seL4_IPCBuffer>,
     );
 
-    fn do_fault_transfer(&self, receiver: &mut tcb_t, badge: usize);
+    fn do_fault_transfer(&self, receiver: &mut tcb_t, badge: usize) -> IpcResult<()>;
 
     fn do_normal_transfer(
         &mut self,
@@ -62,33 +77,42 @@ pub trait Transfer {
         endpoint: Option<&endpoint>,
         badge: usize,
         grant: bool,
-    );
+    ) -> IpcResult<()>;
     #[cfg(feature = "kernel_mcs")]
-    fn do_reply(&mut self, reply: &mut reply_t, grant: bool);
+    fn do_reply(&mut self, reply: &mut reply_t, grant: bool) -> IpcResult<()>;
     #[cfg(not(feature = "kernel_mcs"))]
-    fn do_reply(&mut self, receiver: &mut tcb_t, slot: &mut cte_t, grant: bool);
+    fn do_reply(&mut self, receiver: &mut tcb_t, slot: &mut cte_t, grant: bool) -> IpcResult<()>;
 }
 
 impl Transfer for tcb_t {
-    fn cancel_ipc(&mut self) {
-        let state = &self.tcbState;
+    fn cancel_ipc(&mut self) -> IpcResult<()> {
+        ipc_lock!(lock_blocking_object(self));
+        ipc_invariants!(Thread, self.get_ptr(), "Transfer::cancel_ipc");
+        // Cancelling the IPC of a thread cancels its fault too.
         #[cfg(feature = "kernel_mcs")]
         {
-            seL4_Fault_NullFault::new();
+            self.tcbFault = seL4_Fault_NullFault::new().unsplay();
         }
         match self.get_state() {
             ThreadState::ThreadStateBlockedOnSend | ThreadState::ThreadStateBlockedOnReceive => {
-                let ep = convert_to_mut_type_ref::<endpoint>(state.get_blockingObject() as usize);
-                assert_ne!(ep.get_ep_state(), EPState::Idle);
-                ep.cancel_ipc(self);
+                ipc_wait_graph!(cancel_receive(self));
+                if let Some(ep) = self.blocking_endpoint().get() {
+                    if ep.get_ep_state()? == EPState::Idle {
+                        return Err(IpcError::CorruptedObjectState {
+                            object: ep.get_ptr(),
+                        });
+                    }
+                    ep.cancel_ipc(self);
+                }
             }
             ThreadState::ThreadStateBlockedOnNotification => {
-                let ntfn =
-                    convert_to_mut_type_ref::<notification>(state.get_blockingObject() as usize);
-                ntfn.cancel_signal(self);
+                if let Some(ntfn) = self.blocking_notification().get() {
+                    ntfn.cancel_signal(self);
+                }
             }
 
             ThreadState::ThreadStateBlockedOnReply => {
+                ipc_wait_graph!(clear_call(self.get_ptr()));
                 #[cfg(feature = "kernel_mcs")]
                 {
                     reply_remove_tcb(self);
@@ -105,6 +129,7 @@ impl Transfer for tcb_t {
             }
             _ => {}
         }
+        Ok(())
     }
 
     fn set_transfer_caps(
@@ -113,26 +138,45 @@ impl Transfer for tcb_t {
         info: &mut seL4_MessageInfo,
         current_extra_caps: &[pptr_t; SEL4_MSG_MAX_EXTRA_CAPS],
     ) {
-        info.set_extraCaps(0);
-        info.set_capsUnwrapped(0);
+        ipc_invariants!(Thread, self.get_ptr(), "Transfer::set_transfer_caps");
+        *info = seL4_MessageInfo::from_word(
+            MessageInfo::from_word(info.to_word())
+                .with_received_caps(ReceivedCaps::NONE)
+                .word(),
+        );
         let ipc_buffer = self.lookup_mut_ipc_buffer(true);
         if current_extra_caps[0] as usize == 0 || ipc_buffer.is_none() {
             return;
         }
         let buffer = ipc_buffer.unwrap();
         let mut dest_slot = self.get_receive_slot();
-        let mut i = 0;
-        while i < SEL4_MSG_MAX_EXTRA_CAPS && current_extra_caps[i] as usize != 0 {
-            let slot = convert_to_mut_type_ref::<cte_t>(current_extra_caps[i]);
+        let mut received = ReceivedCaps::NONE;
+        for &extra_cap in current_extra_caps
+            .iter()
+            .take_while(|extra_cap| **extra_cap as usize != 0)
+        {
+            let slot = convert_to_mut_type_ref::<cte_t>(extra_cap);
             let capability_cpy = &slot.capability.clone();
             if capability_cpy.get_tag() == cap_tag::cap_endpoint_cap
                 && ep.is_some()
                 && cap::cap_endpoint_cap(capability_cpy).get_capEPPtr() as usize
                     == ep.unwrap().get_ptr()
             {
-                buffer.caps_or_badges[i] =
-                    cap::cap_endpoint_cap(capability_cpy).get_capEPBadge() as usize;
-                info.set_capsUnwrapped(info.get_capsUnwrapped() | (1 << i));
+                let badge = cap::cap_endpoint_cap(capability_cpy).get_capEPBadge() as usize;
+                if !received.unwrap(&mut buffer.caps_or_badges, badge) {
+                    break;
+                }
+                ipc_trace!(
+                    CapTransfer,
+                    Unwrapped,
+                    ep.map_or(0, |ep| ep.get_ptr()),
+                    self.get_ptr(),
+                    badge: badge,
+                );
+                ipc_stats!(endpoint_cap_transferred(
+                    ep.map_or(0, |ep| ep.get_ptr()),
+                    true
+                ));
             } else {
                 if dest_slot.is_none() {
                     break;
@@ -145,12 +189,26 @@ impl Transfer for tcb_t {
                         break;
                     }
                     cte_insert(&dc_ret.capability, slot, dest.unwrap());
+                    received.transfer();
+                    ipc_trace!(
+                        CapTransfer,
+                        Transferred,
+                        ep.map_or(0, |ep| ep.get_ptr()),
+                        self.get_ptr()
+                    );
+                    ipc_stats!(endpoint_cap_transferred(
+                        ep.map_or(0, |ep| ep.get_ptr()),
+                        false
+                    ));
                     dest_slot = None;
                 }
             }
-            i += 1;
         }
-        info.set_extraCaps(i as u64);
+        *info = seL4_MessageInfo::from_word(
+            MessageInfo::from_word(info.to_word())
+                .with_received_caps(received)
+                .word(),
+        );
     }
 
     fn set_transfer_caps_with_buf(
@@ -160,26 +218,49 @@ impl Transfer for tcb_t {
         current_extra_caps: &[pptr_t; SEL4_MSG_MAX_EXTRA_CAPS],
         ipc_buffer: Option<&mut seL4_IPCBuffer>,
     ) {
-        info.set_extraCaps(0);
-        info.set_capsUnwrapped(0);
+        ipc_invariants!(
+            Thread,
+            self.get_ptr(),
+            "Transfer::set_transfer_caps_with_buf"
+        );
+        *info = seL4_MessageInfo::from_word(
+            MessageInfo::from_word(info.to_word())
+                .with_received_caps(ReceivedCaps::NONE)
+                .word(),
+        );
         // let ipc_buffer = self.lookup_mut_ipc_buffer(true);
         if likely(current_extra_caps[0] as usize == 0 || ipc_buffer.is_none()) {
             return;
         }
         let buffer = ipc_buffer.unwrap();
         let mut dest_slot = self.get_receive_slot();
-        let mut i = 0;
-        while i < SEL4_MSG_MAX_EXTRA_CAPS && current_extra_caps[i] as usize != 0 {
-            let slot = convert_to_mut_type_ref::<cte_t>(current_extra_caps[i]);
+        let mut received = ReceivedCaps::NONE;
+        for &extra_cap in current_extra_caps
+            .iter()
+            .take_while(|extra_cap| **extra_cap as usize != 0)
+        {
+            let slot = convert_to_mut_type_ref::<cte_t>(extra_cap);
             let capability_cpy = &slot.capability.clone();
             if capability_cpy.get_tag() == cap_tag::cap_endpoint_cap
                 && ep.is_some()
                 && cap::cap_endpoint_cap(capability_cpy).get_capEPPtr() as usize
                     == ep.unwrap().get_ptr()
             {
-                buffer.caps_or_badges[i] =
-                    cap::cap_endpoint_cap(capability_cpy).get_capEPBadge() as usize;
-                info.set_capsUnwrapped(info.get_capsUnwrapped() | (1 << i));
+                let badge = cap::cap_endpoint_cap(capability_cpy).get_capEPBadge() as usize;
+                if !received.unwrap(&mut buffer.caps_or_badges, badge) {
+                    break;
+                }
+                ipc_trace!(
+                    CapTransfer,
+                    Unwrapped,
+                    ep.map_or(0, |ep| ep.get_ptr()),
+                    self.get_ptr(),
+                    badge: badge,
+                );
+                ipc_stats!(endpoint_cap_transferred(
+                    ep.map_or(0, |ep| ep.get_ptr()),
+                    true
+                ));
             } else {
                 if dest_slot.is_none() {
                     break;
@@ -192,85 +273,85 @@ impl Transfer for tcb_t {
                         break;
                     }
                     cte_insert(&dc_ret.capability, slot, dest.unwrap());
+                    received.transfer();
+                    ipc_trace!(
+                        CapTransfer,
+                        Transferred,
+                        ep.map_or(0, |ep| ep.get_ptr()),
+                        self.get_ptr()
+                    );
+                    ipc_stats!(endpoint_cap_transferred(
+                        ep.map_or(0, |ep| ep.get_ptr()),
+                        false
+                    ));
                 }
             }
-            i += 1;
         }
-        info.set_extraCaps(i as u64);
+        *info = seL4_MessageInfo::from_word(
+            MessageInfo::from_word(info.to_word())
+                .with_received_caps(received)
+                .word(),
+        );
     }
 
-    fn do_fault_transfer(&self, receiver: &mut tcb_t, badge: usize) {
+    fn do_fault_transfer(&self, receiver: &mut tcb_t, badge: usize) -> IpcResult<()> {
+        ipc_invariants!(TransferPeer, self.get_ptr(), "Transfer::do_fault_transfer");
+        ipc_invariants!(
+            TransferPeer,
+            receiver.get_ptr(),
+            "Transfer::do_fault_transfer"
+        );
+        check_transferable_fault(self)?;
+        let mut mrs = [0; MAX_FAULT_MESSAGE];
         let sent = match self.tcbFault.get_tag() {
             seL4_Fault_tag::seL4_Fault_CapFault => {
-                receiver.set_mr(CAP_FAULT_IP, self.tcbArch.get_register(ArchReg::FAULT_IP));
-                receiver.set_mr(
-                    CAP_FAULT_ADDR,
-                    seL4_Fault::seL4_Fault_CapFault(&self.tcbFault).get_address() as usize,
-                );
-                receiver.set_mr(
-                    CAP_FAULT_IN_RECV_PHASE,
-                    seL4_Fault::seL4_Fault_CapFault(&self.tcbFault).get_inReceivePhase() as usize,
-                );
-                receiver.set_lookup_fault_mrs(CAP_FAULT_LOOKUP_FAILURE_TYPE, &self.tcbLookupFailure)
+                let fault = seL4_Fault::seL4_Fault_CapFault(&self.tcbFault);
+                receiver.set_mr(CapFault::IP, self.tcbArch.get_register(ArchReg::FAULT_IP));
+                receiver.set_mr(CapFault::ADDR, fault.get_address() as usize);
+                receiver.set_mr(CapFault::IN_RECV_PHASE, fault.get_inReceivePhase() as usize);
+                receiver.set_lookup_fault_mrs(CapFault::LOOKUP_FAILURE_TYPE, &self.tcbLookupFailure)
             }
             seL4_Fault_tag::seL4_Fault_UnknownSyscall => {
-                self.copy_syscall_fault_mrs(receiver);
-                receiver.set_mr(
-                    N_SYSCALL_MESSAGE,
-                    seL4_Fault::seL4_Fault_UnknownSyscall(&self.tcbFault).get_syscallNumber()
-                        as usize,
-                )
+                let fault = seL4_Fault::seL4_Fault_UnknownSyscall(&self.tcbFault);
+                let syscall = copy_syscall_registers(self, receiver);
+                receiver.set_mr(syscall, fault.get_syscallNumber() as usize)
             }
             seL4_Fault_tag::seL4_Fault_UserException => {
-                self.copy_exeception_fault_mrs(receiver);
-                receiver.set_mr(
-                    N_EXCEPTON_MESSAGE,
-                    seL4_Fault::seL4_Fault_UserException(&self.tcbFault).get_number() as usize,
-                );
-                receiver.set_mr(
-                    N_EXCEPTON_MESSAGE + 1,
-                    seL4_Fault::seL4_Fault_UserException(&self.tcbFault).get_code() as usize,
-                )
-            }
-            seL4_Fault_tag::seL4_Fault_VMFault => {
-                receiver.set_mr(VM_FAULT_IP, self.tcbArch.get_register(ArchReg::FAULT_IP));
-                receiver.set_mr(
-                    VM_FAULT_ADDR,
-                    seL4_Fault::seL4_Fault_VMFault(&self.tcbFault).get_address() as usize,
-                );
-                receiver.set_mr(
-                    VM_FAULT_PREFETCH_FAULT,
-                    seL4_Fault::seL4_Fault_VMFault(&self.tcbFault).get_instructionFault() as usize,
-                );
-                receiver.set_mr(
-                    VM_FAULT_FSR,
-                    seL4_Fault::seL4_Fault_VMFault(&self.tcbFault).get_FSR() as usize,
-                )
+                let fault = seL4_Fault::seL4_Fault_UserException(&self.tcbFault);
+                let number = copy_exception_registers(self, receiver);
+                receiver.set_mr(number, fault.get_number() as usize);
+                receiver.set_mr(number + 1, fault.get_code() as usize)
             }
             #[cfg(feature = "kernel_mcs")]
             seL4_Fault_tag::seL4_Fault_Timeout => {
-                let len = receiver.set_mr(
-                    TIMEOUT_DATA,
-                    seL4_Fault::seL4_Fault_Timeout(&self.tcbFault).get_badge() as usize,
-                );
-                if let Some(sc) =
-                    convert_to_option_mut_type_ref::<sched_context_t>(self.tcbSchedContext)
-                {
-                    let consumed = sc.sched_context_update_consumed();
-                    receiver.set_mr(len, consumed)
-                } else {
-                    len
+                let length = Timeout {
+                    data: seL4_Fault::seL4_Fault_Timeout(&self.tcbFault).get_badge() as usize,
+                    consumed: self
+                        .sched_context()
+                        .get()
+                        .map(|sc| sc.sched_context_update_consumed()),
                 }
+                .encode(&mut mrs);
+                set_mrs(receiver, &mrs[..length])
             }
-            _ => {
-                panic!("invalid fault")
-            }
+            tag => match fault_message_of(self) {
+                Some(message) => {
+                    let length = message.encode(&mut mrs);
+                    set_mrs(receiver, &mrs[..length])
+                }
+                None => {
+                    return Err(IpcError::InvalidFaultTag {
+                        thread: self.handle(),
+                        tag: tag as u64,
+                    })
+                }
+            },
         };
-        let msg_info = seL4_MessageInfo::new(self.tcbFault.get_tag() as u64, 0, 0, sent as u64);
-        receiver
-            .tcbArch
-            .set_register(ArchReg::MsgInfo, msg_info.to_word());
-        receiver.tcbArch.set_register(ArchReg::Badge, badge);
+        #[cfg(feature = "double_fault_policy")]
+        fault_delivered(self, receiver);
+        let info = MessageInfo::new(self.tcbFault.get_tag() as usize, sent);
+        set_message_info(receiver, info, badge);
+        Ok(())
     }
 
     fn do_normal_transfer(
@@ -280,8 +361,13 @@ impl Transfer for tcb_t {
         badge: usize,
         can_grant: bool,
     ) {
-        let mut tag =
-            seL4_MessageInfo::from_word_security(self.tcbArch.get_register(ArchReg::MsgInfo));
+        ipc_invariants!(TransferPeer, self.get_ptr(), "Transfer::do_normal_transfer");
+        ipc_invariants!(
+            TransferPeer,
+            receiver.get_ptr(),
+            "Transfer::do_normal_transfer"
+        );
+        let info = MessageInfo::from_word_security(self.tcbArch.get_register(ArchReg::MsgInfo));
         let mut current_extra_caps = [0; SEL4_MSG_MAX_EXTRA_CAPS];
         if can_grant {
             let status = self.lookup_extra_caps(&mut current_extra_caps);
@@ -291,69 +377,66 @@ impl Transfer for tcb_t {
         } else {
             current_extra_caps[0] = 0;
         }
-        let msg_transferred = self.copy_mrs(receiver, tag.get_length() as usize);
+        let msg_transferred = self.copy_mrs(receiver, info.length());
+        let mut tag = seL4_MessageInfo::from_word(info.word());
         receiver.set_transfer_caps(ep, &mut tag, &current_extra_caps);
-        tag.set_length(msg_transferred as u64);
-        receiver
-            .tcbArch
-            .set_register(ArchReg::MsgInfo, tag.to_word());
-        receiver.tcbArch.set_register(ArchReg::Badge, badge);
+        let info = MessageInfo::from_word(tag.to_word()).with_length(msg_transferred);
+        set_message_info(receiver, info, badge);
     }
 
     fn do_fault_reply_transfer(&mut self, receiver: &mut tcb_t) -> bool {
-        let tag = seL4_MessageInfo::from_word_security(self.tcbArch.get_register(ArchReg::MsgInfo));
-        let label = tag.get_label() as usize;
-        let length = tag.get_length() as usize;
-        match receiver.tcbFault.get_tag() {
-            seL4_Fault_tag::seL4_Fault_UnknownSyscall => {
-                self.copy_fault_mrs_for_reply(
-                    receiver,
-                    MESSAGE_ID_SYSCALL,
-                    core::cmp::min(length, N_SYSCALL_MESSAGE),
-                );
-                return label as usize == 0;
-            }
-            seL4_Fault_tag::seL4_Fault_UserException => {
-                self.copy_fault_mrs_for_reply(
-                    receiver,
-                    MESSAGE_ID_EXCEPTION,
-                    core::cmp::min(length, N_EXCEPTON_MESSAGE),
-                );
-                return label as usize == 0;
-            }
-            #[cfg(feature = "kernel_mcs")]
-            seL4_Fault_tag::seL4_Fault_Timeout => {
-                self.copy_fault_mrs_for_reply(
-                    receiver,
-                    MESSAGE_ID_TIMEOUT_REPLY,
-                    core::cmp::min(length, N_TIMEOUT_MESSAGE),
-                );
-                return label as usize == 0;
-            }
-            _ => true,
+        ipc_invariants!(
+            TransferPeer,
+            self.get_ptr(),
+            "Transfer::do_fault_reply_transfer"
+        );
+        ipc_invariants!(
+            TransferPeer,
+            receiver.get_ptr(),
+            "Transfer::do_fault_reply_transfer"
+        );
+        let info = MessageInfo::from_word_security(self.tcbArch.get_register(ArchReg::MsgInfo));
+        let fault_tag = receiver.tcbFault.get_tag() as u64;
+        #[cfg(feature = "double_fault_policy")]
+        fault_replied(self);
+        #[cfg(feature = "fault_profiles")]
+        let profiled = (fault_tag == UnknownSyscall::LABEL || fault_tag == UserException::LABEL)
+            && copy_profile_reply(self, receiver, info.length());
+        #[cfg(not(feature = "fault_profiles"))]
+        let profiled = false;
+        if let Some((message_id, length)) = fault_reply_copy(fault_tag, info).filter(|_| !profiled)
+        {
+            self.copy_fault_mrs_for_reply(receiver, message_id, length);
         }
+        #[cfg(feature = "register_replies")]
+        if info.label() == RegisterReply::LABEL && RegisterReply::accepts(fault_tag) {
+            return do_register_reply(self, receiver, info.length());
+        }
+        #[cfg(feature = "hardware_debug_api")]
+        if fault_tag == DebugException::LABEL {
+            do_debug_reply(self, receiver, info.label());
+        }
+        fault_reply_restart(fault_tag, info.label())
     }
 
     fn complete_signal(&mut self) -> bool {
-        if let Some(ntfn) =
-            convert_to_option_mut_type_ref::<notification>(self.tcbBoundNotification)
-        {
-            if likely(ntfn.get_ntfn_state() == NtfnState::Active) {
+        ipc_lock!(lock_objects(self.tcbBoundNotification, 0));
+        ipc_invariants!(Thread, self.get_ptr(), "Transfer::complete_signal");
+        if let Some(ntfn) = self.bound_notification().get() {
+            ipc_ntfn_claim!(ntfn);
+            if likely(matches!(ntfn.get_ntfn_state(), Ok(NtfnState::Active))) {
                 self.tcbArch
                     .set_register(ArchReg::Badge, ntfn.get_ntfnMsgIdentifier() as usize);
-                ntfn.set_state(NtfnState::Idle as u64);
+                ntfn.set_ntfn_state(NtfnState::Idle);
                 #[cfg(feature = "kernel_mcs")]
                 {
                     maybe_donate_sched_context(self, ntfn);
-                    if let Some(tcbsc) =
-                        convert_to_option_mut_type_ref::<sched_context_t>(self.tcbSchedContext)
-                    {
-                        if tcbsc.sc_sporadic() {
-                            if self.tcbSchedContext == ntfn.get_ntfnSchedContext() as usize
-                                && !tcbsc.is_current()
-                            {
-                                tcbsc.refill_unblock_check();
-                            }
+                    if let Some(tcbsc) = self.sched_context().get() {
+                        if tcbsc.sc_sporadic()
+                            && self.sched_context() == ntfn.sched_context()
+                            && !tcbsc.is_current()
+                        {
+                            tcbsc.refill_unblock_check();
                         }
                     }
                 }
@@ -369,56 +452,93 @@ impl Transfer for tcb_t {
         ep: Option<&endpoint>,
         badge: usize,
         grant: bool,
-    ) {
+    ) -> IpcResult<()> {
+        ipc_invariants!(TransferPeer, self.get_ptr(), "Transfer::do_ipc_transfer");
+        ipc_invariants!(
+            TransferPeer,
+            receiver.get_ptr(),
+            "Transfer::do_ipc_transfer"
+        );
         if likely(self.tcbFault.get_tag() == seL4_Fault_tag::seL4_Fault_NullFault) {
-            self.do_normal_transfer(receiver, ep, badge, grant)
+            self.do_normal_transfer(receiver, ep, badge, grant);
+            Ok(())
         } else {
             self.do_fault_transfer(receiver, badge)
         }
     }
+    /// Reply to the caller waiting on `reply`. Removing the caller from the reply object
+    /// gives back the scheduling context it donated, a caller left without budget raises a
+    /// timeout fault or is postponed. A reply the cross-domain policy forbids is not delivered,
+    /// the caller is made inactive and `CrossDomainIpc` returned.
     #[cfg(feature = "kernel_mcs")]
-    fn do_reply(&mut self, reply: &mut reply_t, grant: bool) {
+    fn do_reply(&mut self, reply: &mut reply_t, grant: bool) -> IpcResult<()> {
+        ipc_invariants!(Thread, self.get_ptr(), "Transfer::do_reply");
         use sel4_common::{ffi::current_fault, structures_gen::seL4_Fault_Timeout};
         use sel4_task::handleTimeout;
 
-        if reply.replyTCB == 0
-            || convert_to_mut_type_ref::<tcb_t>(reply.replyTCB)
-                .tcbState
-                .get_tsType()
-                != ThreadState::ThreadStateBlockedOnReply as u64
-        {
+        let receiver = match TcbRef::from_raw(reply.replyTCB).get() {
+            Some(receiver)
+                if receiver.tcbState.get_tsType()
+                    == ThreadState::ThreadStateBlockedOnReply as u64 =>
+            {
+                receiver
+            }
             /* nothing to do */
-            return;
+            _ => return Ok(()),
+        };
+        ipc_invariants!(Thread, receiver.get_ptr(), "Transfer::do_reply");
+        // Removing the caller clears both links, check them before anything changes.
+        if receiver.tcbState.get_replyObject() as usize != reply.get_ptr() {
+            return Err(IpcError::CorruptedObjectState {
+                object: reply.get_ptr(),
+            });
         }
-
-        let receiver = convert_to_mut_type_ref::<tcb_t>(reply.replyTCB);
+        #[cfg(feature = "domain_isolation")]
+        let cross_domain = crate::domain::check_cross_domain(self, receiver);
         reply.remove(receiver);
-        assert!(receiver.tcbState.get_replyObject() == 0);
-        assert!(reply.replyTCB == 0);
 
-        if let Some(sc) =
-            convert_to_option_mut_type_ref::<sched_context_t>(receiver.tcbSchedContext)
-        {
+        if let Some(sc) = receiver.sched_context().get() {
             if sc.sc_sporadic() && !sc.is_current() {
                 sc.refill_unblock_check();
             }
         }
 
+        ipc_wait_graph!(clear_call(receiver.get_ptr()));
+        #[cfg(feature = "domain_isolation")]
+        if let Err(error) = cross_domain {
+            crate::domain::cancel_forbidden_ipc(receiver);
+            ipc_trace!(Reply, Cancelled, reply.get_ptr(), self.get_ptr(), peer: receiver.get_ptr());
+            return Err(error);
+        }
         let fault_type = receiver.tcbFault.get_tag();
         if likely(fault_type == seL4_Fault_tag::seL4_Fault_NullFault) {
-            self.do_ipc_transfer(receiver, None, 0, grant);
+            self.do_ipc_transfer(receiver, None, 0, grant)?;
             set_thread_state(receiver, ThreadState::ThreadStateRunning);
+            ipc_trace!(
+                Reply,
+                Delivered,
+                reply.get_ptr(),
+                self.get_ptr(),
+                peer: receiver.get_ptr(),
+                label: crate::trace::received_info(receiver).get_label(),
+                length: crate::trace::received_info(receiver).get_length(),
+            );
         } else {
             let restart = self.do_fault_reply_transfer(receiver);
             receiver.tcbFault = seL4_Fault_NullFault::new().unsplay();
             if restart {
                 set_thread_state(receiver, ThreadState::ThreadStateRestart);
+                ipc_trace!(Reply, Restarted, reply.get_ptr(), self.get_ptr(), peer: receiver.get_ptr());
             } else {
                 set_thread_state(receiver, ThreadState::ThreadStateInactive);
+                ipc_trace!(Reply, Cancelled, reply.get_ptr(), self.get_ptr(), peer: receiver.get_ptr());
             }
         }
-        if receiver.tcbSchedContext != 0 && receiver.is_runnable() {
-            let sc = convert_to_mut_type_ref::<sched_context_t>(receiver.tcbSchedContext);
+        if let Some(sc) = receiver
+            .sched_context()
+            .get()
+            .filter(|_| receiver.is_runnable())
+        {
             if sc.refill_ready() && sc.refill_sufficient(0) {
                 possible_switch_to(receiver);
             } else {
@@ -434,16 +554,44 @@ impl Transfer for tcb_t {
                 }
             }
         }
+        Ok(())
     }
+    /// Reply to `receiver` through its reply cap in `slot`, which is deleted. A reply the
+    /// cross-domain policy forbids is not delivered, the caller is made inactive and
+    /// `CrossDomainIpc` returned.
     #[cfg(not(feature = "kernel_mcs"))]
-    fn do_reply(&mut self, receiver: &mut tcb_t, slot: &mut cte_t, grant: bool) {
-        assert_eq!(receiver.get_state(), ThreadState::ThreadStateBlockedOnReply);
+    fn do_reply(&mut self, receiver: &mut tcb_t, slot: &mut cte_t, grant: bool) -> IpcResult<()> {
+        ipc_invariants!(Thread, self.get_ptr(), "Transfer::do_reply");
+        ipc_invariants!(Thread, receiver.get_ptr(), "Transfer::do_reply");
+        if receiver.get_state() != ThreadState::ThreadStateBlockedOnReply {
+            return Err(IpcError::ImpossibleThreadState {
+                thread: receiver.handle(),
+            });
+        }
+        #[cfg(feature = "domain_isolation")]
+        if let Err(error) = crate::domain::check_cross_domain(self, receiver) {
+            slot.delete_one();
+            ipc_wait_graph!(clear_call(receiver.get_ptr()));
+            crate::domain::cancel_forbidden_ipc(receiver);
+            ipc_trace!(Reply, Cancelled, 0, self.get_ptr(), peer: receiver.get_ptr());
+            return Err(error);
+        }
+        ipc_wait_graph!(clear_call(receiver.get_ptr()));
         let fault_type = receiver.tcbFault.get_tag();
         if likely(fault_type == seL4_Fault_tag::seL4_Fault_NullFault) {
-            self.do_ipc_transfer(receiver, None, 0, grant);
+            self.do_ipc_transfer(receiver, None, 0, grant)?;
             slot.delete_one();
             set_thread_state(receiver, ThreadState::ThreadStateRunning);
             possible_switch_to(receiver);
+            ipc_trace!(
+                Reply,
+                Delivered,
+                0,
+                self.get_ptr(),
+                peer: receiver.get_ptr(),
+                label: crate::trace::received_info(receiver).get_label(),
+                length: crate::trace::received_info(receiver).get_length(),
+            );
         } else {
             slot.delete_one();
             let restart = self.do_fault_reply_transfer(receiver);
@@ -451,9 +599,143 @@ impl Transfer for tcb_t {
             if restart {
                 set_thread_state(receiver, ThreadState::ThreadStateRestart);
                 possible_switch_to(receiver);
+                ipc_trace!(Reply, Restarted, 0, self.get_ptr(), peer: receiver.get_ptr());
             } else {
                 set_thread_state(receiver, ThreadState::ThreadStateInactive);
+                ipc_trace!(Reply, Cancelled, 0, self.get_ptr(), peer: receiver.get_ptr());
             }
         }
+        Ok(())
+    }
+}
+
+/// Build the message of the fault of `thread` from the fault alone, `None` for faults whose
+/// message needs the registers, the lookup failure or the scheduling context of `thread`.
+pub(crate) fn fault_message_of(thread: &tcb_t) -> Option<FaultMessage> {
+    match thread.tcbFault.get_tag() {
+        seL4_Fault_tag::seL4_Fault_VMFault => {
+            let fault = seL4_Fault::seL4_Fault_VMFault(&thread.tcbFault);
+            Some(FaultMessage::VmFault(VmFault {
+                ip: thread.tcbArch.get_register(ArchReg::FAULT_IP),
+                addr: fault.get_address() as usize,
+                prefetch_fault: fault.get_instructionFault() != 0,
+                fsr: fault.get_FSR() as usize,
+            }))
+        }
+        #[cfg(feature = "hardware_debug_api")]
+        seL4_Fault_tag::seL4_Fault_DebugException => {
+            debug_exception_of(thread).map(FaultMessage::DebugException)
+        }
+        #[cfg(feature = "arm_hypervisor_support")]
+        seL4_Fault_tag::seL4_Fault_VGICMaintenance => {
+            let fault = seL4_Fault::seL4_Fault_VGICMaintenance(&thread.tcbFault);
+            Some(FaultMessage::VgicMaintenance(VgicMaintenance {
+                idx: (fault.get_idxValid() != 0).then_some(fault.get_idx() as usize),
+            }))
+        }
+        #[cfg(feature = "arm_hypervisor_support")]
+        seL4_Fault_tag::seL4_Fault_VCPUFault => Some(FaultMessage::VcpuFault(VcpuFault {
+            hsr: seL4_Fault::seL4_Fault_VCPUFault(&thread.tcbFault).get_hsr() as usize,
+        })),
+        #[cfg(feature = "arm_hypervisor_support")]
+        seL4_Fault_tag::seL4_Fault_VPPIEvent => Some(FaultMessage::VppiEvent(VppiEvent {
+            irq: seL4_Fault::seL4_Fault_VPPIEvent(&thread.tcbFault).get_irq_w() as usize,
+        })),
+        _ => None,
+    }
+}
+
+/// Copy the registers of an `UnknownSyscall` of `sender` to `receiver`, returning the message
+/// register of the syscall number
+fn copy_syscall_registers(sender: &tcb_t, receiver: &mut tcb_t) -> usize {
+    #[cfg(feature = "fault_profiles")]
+    if let Some(profile) = copy_profile_fault_mrs(sender, receiver) {
+        return profile.syscall_index();
+    }
+    sender.copy_syscall_fault_mrs(receiver);
+    UnknownSyscall::SYSCALL
+}
+
+/// Copy the registers of a `UserException` of `sender` to `receiver`, returning the message
+/// register of the exception number
+fn copy_exception_registers(sender: &tcb_t, receiver: &mut tcb_t) -> usize {
+    #[cfg(feature = "fault_profiles")]
+    if let Some(profile) = copy_profile_fault_mrs(sender, receiver) {
+        return profile.exception_number_index();
+    }
+    sender.copy_exeception_fault_mrs(receiver);
+    UserException::NUMBER
+}
+
+/// Read message register `index` of `thread`, `None` if it is in an IPC buffer it lacks
+#[cfg(any(
+    feature = "register_replies",
+    feature = "fault_profiles",
+    feature = "kernel_services"
+))]
+pub(crate) fn get_mr(thread: &tcb_t, index: usize) -> Option<usize> {
+    if index < n_msgRegisters {
+        Some(thread.tcbArch.get_register(ArchReg::Msg(index)))
+    } else {
+        thread
+            .lookup_ipc_buffer(false)
+            .map(|buffer| buffer.msg[index])
+    }
+}
+
+/// Write the encoded fault message `mrs` to the receiver with `set_mr`, which writes message
+/// register `index` and returns the length of the message so far, like `tcb_t::set_mr`.
+/// Returns the length of the message the receiver got.
+pub fn write_fault_mrs(mrs: &[usize], mut set_mr: impl FnMut(usize, usize) -> usize) -> usize {
+    let mut sent = 0;
+    for (index, mr) in mrs.iter().enumerate() {
+        sent = set_mr(index, *mr);
+    }
+    sent
+}
+
+/// Write the words of a message built by the kernel, such as an encoded fault, to the message
+/// registers of `receiver`, returning the number of registers written.
+fn set_mrs(receiver: &mut tcb_t, mrs: &[usize]) -> usize {
+    write_fault_mrs(mrs, |index, mr| receiver.set_mr(index, mr))
+}
+
+/// Tell `receiver` what it received, the message info with the length actually written and
+/// the badge of the cap it was sent with
+fn set_message_info(receiver: &mut tcb_t, info: MessageInfo, badge: usize) {
+    receiver.tcbArch.set_register(ArchReg::MsgInfo, info.word());
+    receiver.tcbArch.set_register(ArchReg::Badge, badge);
+}
+
+/// Reply to `receiver` with a message built by the kernel, delivered like the reply of a
+/// thread through `do_reply`: the words as far as its message registers go, no caps and
+/// badge 0. The state of `receiver` is left to the caller.
+#[cfg(feature = "kernel_services")]
+pub(crate) fn do_kernel_reply(receiver: &mut tcb_t, label: usize, words: &[usize]) {
+    let sent = set_mrs(receiver, words);
+    set_message_info(receiver, MessageInfo::new(label, sent), 0);
+}
+
+/// Check that the fault of `thread` can be transferred, before it is dequeued from anywhere.
+/// Faults whose message is built from the fault alone must also have a valid message, e.g. a
+/// debug exception must have a known reason.
+pub(crate) fn check_transferable_fault(thread: &tcb_t) -> IpcResult<()> {
+    let fault_tag = thread.tcbFault.get_tag() as u64;
+    let transferable = match thread.tcbFault.get_tag() {
+        seL4_Fault_tag::seL4_Fault_NullFault
+        | seL4_Fault_tag::seL4_Fault_CapFault
+        | seL4_Fault_tag::seL4_Fault_UnknownSyscall
+        | seL4_Fault_tag::seL4_Fault_UserException => true,
+        #[cfg(feature = "kernel_mcs")]
+        seL4_Fault_tag::seL4_Fault_Timeout => true,
+        _ => is_transferable_fault(fault_tag) && fault_message_of(thread).is_some(),
+    };
+    if transferable {
+        Ok(())
+    } else {
+        Err(IpcError::InvalidFaultTag {
+            thread: thread.handle(),
+            tag: fault_tag,
+        })
     }
 }
@@ -0,0 +1,324 @@
+//! The wait-for graph of IPC, enabled by the `deadlock_detection` and `priority_inheritance`
+//! features.
+//!
+//! A thread blocked on send waits for the thread serving the endpoint, and a thread blocked on
+//! reply waits for the thread holding its reply cap. Neither relation is kept by the kernel
+//! objects, so this module records them: the caller of every call is attached to the server that
+//! received it, and every endpoint to the last thread that received on it. A thread stops serving
+//! an endpoint when it receives on another one or its receive is cancelled. With several threads
+//! serving one endpoint the graph follows the last one, and a server may stop serving without
+//! ever receiving again, so a reported cycle may be a false positive.
+//!
+//! A blocking call that would close a cycle is handled according to the `DeadlockPolicy`. The
+//! default only logs it, failing the call or raising a fault is opt-in because of the false
+//! positives. `dump_wait_graph` writes the current graph in DOT format. With `priority_inheritance` every
+//! change of a call edge updates the priority of the server, see `priority_inheritance.rs`.
+use core::fmt;
+use core::ptr::addr_of_mut;
+
+#[cfg(feature = "deadlock_detection")]
+use sel4_common::structures_gen::seL4_Fault_tag;
+use sel4_task::{tcb_t, ThreadState};
+
+use crate::endpoint::endpoint_func;
+#[cfg(feature = "deadlock_detection")]
+use crate::error::{IpcError, IpcResult};
+use crate::handle::{tcb_handle_func, EndpointRef, Handle, TcbRef};
+use crate::object_table::ObjectTable;
+
+/// Number of callers whose server can be tracked at once
+const WAIT_GRAPH_MAX_CALLS: usize = 512;
+/// Number of endpoints whose server can be tracked at once
+const WAIT_GRAPH_MAX_ENDPOINTS: usize = 256;
+/// Longest chain or endpoint queue followed, in case the graph is corrupted
+const MAX_WALK: usize = 64;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+/// What to do with a blocking call that would close a cycle. The cycle may be a false positive,
+/// e.g. when another thread serving the endpoint would have taken the call, so only `Ignore`
+/// and `Log` are safe for every system. The other policies are for systems whose endpoints
+/// each have a single server that stays in its receive loop.
+pub enum DeadlockPolicy {
+    /// Do not look for cycles
+    Ignore,
+    /// Log the cycle and block the caller as usual
+    Log,
+    /// Log the cycle and return `IpcError::DeadlockDetected` without blocking the caller
+    FailCall,
+    /// Log the cycle and hand the caller to the handler set by `set_deadlock_fault_handler`
+    /// without blocking it. Falls back to `FailCall` if no handler is set or the call is
+    /// itself a fault message.
+    RaiseFault,
+}
+
+struct WaitGraph {
+    /// Caller blocked on reply -> thread that received the call
+    calls: ObjectTable<usize, WAIT_GRAPH_MAX_CALLS>,
+    /// Endpoint -> last thread that received on it
+    servers: ObjectTable<usize, WAIT_GRAPH_MAX_ENDPOINTS>,
+    policy: DeadlockPolicy,
+    fault_handler: Option<fn(&mut tcb_t, EndpointRef)>,
+    /// Edges lost because a table was full
+    untracked: u64,
+}
+
+static mut WAIT_GRAPH: WaitGraph = WaitGraph {
+    calls: ObjectTable::new(0),
+    servers: ObjectTable::new(0),
+    policy: DeadlockPolicy::Log,
+    fault_handler: None,
+    untracked: 0,
+};
+
+#[inline]
+fn graph() -> &'static mut WaitGraph {
+    unsafe { &mut *addr_of_mut!(WAIT_GRAPH) }
+}
+
+/// Edges are recorded by cores holding unrelated object locks, so with per-object locking the
+/// graph has a lock of its own. It also covers the boosts of `priority_inheritance.rs`, which
+/// follow the graph.
+#[cfg(feature = "fine_grained_locking")]
+pub(crate) static WAIT_GRAPH_LOCK: crate::lock::ObjectLock = crate::lock::ObjectLock::new();
+
+/// Set what happens to a blocking call that would close a cycle, `Log` by default
+pub fn set_deadlock_policy(policy: DeadlockPolicy) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    graph().policy = policy;
+}
+
+/// Set the handler raising a fault on a caller that would deadlock, with the endpoint it called.
+/// The handler runs without locks held and must leave the caller in a consistent state, e.g. by
+/// calling `handleFault`.
+pub fn set_deadlock_fault_handler(handler: fn(&mut tcb_t, EndpointRef)) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    graph().fault_handler = Some(handler);
+}
+
+/// Forget the edges of a deleted thread or endpoint
+pub fn forget_wait_graph_object(object: impl Handle) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let object = object.raw();
+    let graph = graph();
+    let _server = graph.calls.remove(object);
+    graph.servers.remove(object);
+    #[cfg(feature = "priority_inheritance")]
+    if let Some(server) = _server {
+        crate::priority_inheritance::update_inherited_priority(server);
+    }
+}
+
+/// Number of edges lost because the tables were full
+pub fn untracked_wait_graph_edges() -> u64 {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    graph().untracked
+}
+
+impl WaitGraph {
+    /// Count an edge that did not fit in its table, logging the first one
+    fn lose_edge(&mut self, table: &str) {
+        if self.untracked == 0 {
+            log::warn!(
+                "IPC wait-for graph: the {} table is full, cycles through the lost edges go undetected",
+                table
+            );
+        }
+        self.untracked += 1;
+    }
+}
+
+/// `caller` is blocked on reply to `server`
+pub(crate) fn record_call(caller: usize, server: usize) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let graph = graph();
+    match graph.calls.get_or_insert(caller, 0) {
+        Some(edge) => *edge = server,
+        None => graph.lose_edge("call"),
+    }
+    #[cfg(feature = "priority_inheritance")]
+    crate::priority_inheritance::update_inherited_priority(server);
+}
+
+/// `caller` no longer waits for a reply
+pub(crate) fn clear_call(caller: usize) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let _server = graph().calls.remove(caller);
+    #[cfg(feature = "priority_inheritance")]
+    if let Some(server) = _server {
+        crate::priority_inheritance::update_inherited_priority(server);
+    }
+}
+
+/// Get the thread `caller` waits on for a reply
+#[cfg(feature = "priority_inheritance")]
+pub(crate) fn server_of(caller: &tcb_t) -> Option<usize> {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    if caller.get_state() != ThreadState::ThreadStateBlockedOnReply {
+        return None;
+    }
+    graph().calls.get(caller.get_ptr()).copied()
+}
+
+/// Visit the callers waiting on `server` for a reply
+#[cfg(feature = "priority_inheritance")]
+pub(crate) fn for_each_caller(server: usize, mut f: impl FnMut(&tcb_t)) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    graph().calls.for_each(|caller, edge| {
+        if *edge == server {
+            match TcbRef::from_raw(caller).get() {
+                Some(caller) if caller.get_state() == ThreadState::ThreadStateBlockedOnReply => {
+                    f(caller)
+                }
+                _ => {}
+            }
+        }
+    });
+}
+
+/// `thread` received, or is waiting to receive, on `ep`. It no longer serves the endpoints it
+/// received on before.
+pub(crate) fn record_server(ep: usize, thread: usize) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let graph = graph();
+    graph
+        .servers
+        .retain(|other, server| other == ep || *server != thread);
+    match graph.servers.get_or_insert(ep, 0) {
+        Some(server) => *server = thread,
+        None => graph.lose_edge("server"),
+    }
+}
+
+/// `thread` leaves its receive without a message, it no longer serves the endpoint
+pub(crate) fn cancel_receive(thread: &tcb_t) {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    if thread.get_state() == ThreadState::ThreadStateBlockedOnReceive {
+        let thread = thread.get_ptr();
+        graph().servers.retain(|_, server| *server != thread);
+    }
+}
+
+/// Get the thread `thread` is waiting for, `None` if it is not waiting for a known thread
+#[cfg(feature = "deadlock_detection")]
+fn waits_for(thread: &tcb_t) -> Option<usize> {
+    let graph = graph();
+    match thread.get_state() {
+        ThreadState::ThreadStateBlockedOnReply => graph.calls.get_mut(thread.get_ptr()).copied(),
+        ThreadState::ThreadStateBlockedOnSend => graph
+            .servers
+            .get_mut(thread.tcbState.get_blockingObject() as usize)
+            .copied(),
+        _ => None,
+    }
+}
+
+/// Check whether `caller` blocking on a call to `ep` closes a cycle, and apply the policy.
+/// Returns `Ok(true)` if the caller should block, `Ok(false)` if it was handed to the fault
+/// handler instead. Runs before the endpoint is locked so that the handler may send the fault.
+/// A server waiting on the endpoint waits for nobody, so a call it would take closes no cycle.
+#[cfg(feature = "deadlock_detection")]
+pub(crate) fn check_call(caller: &mut tcb_t, ep: usize) -> IpcResult<bool> {
+    let (policy, fault_handler) = {
+        ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+        let graph = graph();
+        if graph.policy == DeadlockPolicy::Ignore || !closes_cycle(caller, ep) {
+            return Ok(true);
+        }
+        (graph.policy, graph.fault_handler)
+    };
+    let error = IpcError::DeadlockDetected {
+        thread: caller.handle(),
+        endpoint: EndpointRef::from_raw(ep),
+    };
+    match policy {
+        DeadlockPolicy::Ignore | DeadlockPolicy::Log => Ok(true),
+        DeadlockPolicy::FailCall => Err(error),
+        DeadlockPolicy::RaiseFault => match fault_handler {
+            Some(handler) if caller.tcbFault.get_tag() == seL4_Fault_tag::seL4_Fault_NullFault => {
+                handler(caller, EndpointRef::from_raw(ep));
+                Ok(false)
+            }
+            _ => Err(error),
+        },
+    }
+}
+
+/// Follow the graph from the server of `ep` and log the cycle if it leads back to `caller`
+#[cfg(feature = "deadlock_detection")]
+fn closes_cycle(caller: &tcb_t, ep: usize) -> bool {
+    let mut next = graph().servers.get_mut(ep).copied();
+    let mut length = 1;
+    while let Some(thread) = next.and_then(|thread| TcbRef::from_raw(thread).get()) {
+        if thread.get_ptr() == caller.get_ptr() {
+            log::warn!(
+                "IPC deadlock: call of thread {:#x} on endpoint {:#x} may close a cycle of {} threads",
+                caller.get_ptr(),
+                ep,
+                length
+            );
+            return true;
+        }
+        if length == MAX_WALK {
+            break;
+        }
+        next = waits_for(thread);
+        length += 1;
+    }
+    false
+}
+
+/// Write the wait-for graph in DOT format. Threads are ellipses and endpoints boxes, a thread
+/// blocked on send points to its endpoint, an endpoint to its server and a caller to the thread
+/// it waits on for a reply.
+pub fn dump_wait_graph(out: &mut impl fmt::Write) -> fmt::Result {
+    ipc_lock!(lock_leaf(&WAIT_GRAPH_LOCK));
+    let graph = graph();
+    let mut result = writeln!(out, "digraph ipc_wait_for {{");
+    graph.servers.for_each(|ep, server| {
+        result = result
+            .and_then(|_| writeln!(out, "  \"{:#x}\" [shape=box];", ep))
+            .and_then(|_| {
+                writeln!(
+                    out,
+                    "  \"{:#x}\" -> \"{:#x}\" [label=\"served by\"];",
+                    ep, server
+                )
+            });
+        let mut walked = 0;
+        let mut sender = EndpointRef::from_raw(ep)
+            .get()
+            .and_then(|ep| TcbRef::from_raw(ep.get_queue().head).get());
+        while let Some(thread) = sender {
+            if walked == MAX_WALK {
+                break;
+            }
+            if thread.get_state() == ThreadState::ThreadStateBlockedOnSend {
+                result = result.and_then(|_| {
+                    writeln!(
+                        out,
+                        "  \"{:#x}\" -> \"{:#x}\" [label=\"send\"];",
+                        thread.get_ptr(),
+                        ep
+                    )
+                });
+            }
+            sender = thread.ep_next().get();
+            walked += 1;
+        }
+    });
+    graph.calls.for_each(|caller, server| {
+        let blocked = TcbRef::from_raw(caller)
+            .get()
+            .is_some_and(|caller| caller.get_state() == ThreadState::ThreadStateBlockedOnReply);
+        if blocked {
+            result = result.and_then(|_| {
+                writeln!(
+                    out,
+                    "  \"{:#x}\" -> \"{:#x}\" [label=\"reply\"];",
+                    caller, server
+                )
+            });
+        }
+    });
+    result.and_then(|_| writeln!(out, "}}"))
+}
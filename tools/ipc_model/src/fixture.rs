@@ -0,0 +1,74 @@
+//! The kernel objects the tests run on, allocated in host memory with the size and alignment
+//! the kernel gives them, so the pointer arithmetic of the kernel finds what it expects.
+use std::alloc::{alloc_zeroed, Layout};
+use std::sync::{Mutex, MutexGuard};
+
+use sel4_common::arch::ArchReg;
+use sel4_common::sel4_config::{seL4_TCBBits, TCB_OFFSET};
+use sel4_common::structures_gen::endpoint;
+use sel4_ipc::{endpoint_func, IpcOutcome};
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
+
+/// The scheduler state is global, so the tests run one at a time
+static KERNEL: Mutex<()> = Mutex::new(());
+
+/// A zeroed object of `2^bits` bytes aligned to its size, like an untyped retype. Objects are
+/// never freed, a test may leave a thread queued on them.
+fn object(bits: usize) -> usize {
+    let layout = Layout::from_size_align(1 << bits, 1 << bits).unwrap();
+    let ptr = unsafe { alloc_zeroed(layout) };
+    assert!(!ptr.is_null());
+    ptr as usize
+}
+
+fn new_thread() -> &'static mut tcb_t {
+    // The TCB follows the CNode slots of the thread in the object.
+    let thread = unsafe { &mut *((object(seL4_TCBBits) + TCB_OFFSET) as *mut tcb_t) };
+    thread.tcbPriority = 100;
+    thread
+}
+
+pub struct IpcFixture {
+    /// A thread not blocked on anything
+    pub sender: &'static mut tcb_t,
+    /// A second thread not blocked on anything
+    pub receiver: &'static mut tcb_t,
+    /// An idle endpoint
+    pub endpoint: &'static mut endpoint,
+    _kernel: MutexGuard<'static, ()>,
+}
+
+impl IpcFixture {
+    pub fn new() -> Self {
+        let kernel = KERNEL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        IpcFixture {
+            sender: new_thread(),
+            receiver: new_thread(),
+            endpoint: unsafe { &mut *(object(4) as *mut endpoint) },
+            _kernel: kernel,
+        }
+    }
+
+    /// Make a thread runnable with an empty message, as if it was about to enter the kernel
+    pub fn ready(thread: &mut tcb_t) {
+        thread.tcbArch.set_register(ArchReg::MsgInfo, 0);
+        set_thread_state(thread, ThreadState::ThreadStateRunning);
+    }
+
+    /// Send on the endpoint from `sender` with `badge`, the way `seL4_Send` or, with
+    /// `do_call`, `seL4_Call` does
+    pub fn send(&mut self, badge: usize, do_call: bool) -> IpcOutcome {
+        Self::ready(self.sender);
+        self.endpoint
+            .send_ipc(self.sender, true, do_call, false, badge, true)
+            .unwrap()
+    }
+
+    /// Receive on the endpoint as `receiver`
+    pub fn receive(&mut self) -> IpcOutcome {
+        Self::ready(self.receiver);
+        self.endpoint.receive_ipc(self.receiver, true, false).unwrap()
+    }
+}
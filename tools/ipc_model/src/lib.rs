@@ -0,0 +1,15 @@
+//! Host test of the endpoint rendezvous of `sel4_ipc` with `ipc_invariants`, run with
+//!
+//! ```text
+//! cargo test
+//! ```
+//!
+//! Every endpoint operation checks the endpoint and the threads it works on when it starts
+//! and when it returns, and panics on a broken invariant. Each test builds its threads and
+//! endpoint in host memory, meets a sender and a receiver on the endpoint in either order, and
+//! checks the outcome and the thread states, so a check that does not hold in the middle of a
+//! rendezvous fails the test.
+#[cfg(test)]
+mod fixture;
+#[cfg(test)]
+mod tests;
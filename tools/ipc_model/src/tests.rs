@@ -0,0 +1,78 @@
+//! The rendezvous scenarios. Every test starts from fresh objects, so none depends on what
+//! another left behind.
+use sel4_ipc::{endpoint_func, EPState, IpcOutcome, IpcTransfer};
+use sel4_task::ThreadState;
+
+use crate::fixture::IpcFixture;
+
+/// A send to a receiver waiting on the endpoint delivers the message and wakes the receiver.
+#[test]
+fn send_to_waiting_receiver() {
+    let mut f = IpcFixture::new();
+    assert_eq!(
+        f.receive(),
+        IpcOutcome::BlockedOnEndpoint(f.endpoint.handle())
+    );
+    assert_eq!(
+        f.receiver.get_state(),
+        ThreadState::ThreadStateBlockedOnReceive
+    );
+    let outcome = f.send(0x10, false);
+    assert!(matches!(
+        outcome,
+        IpcOutcome::Transferred(IpcTransfer {
+            badge: 0x10,
+            caller_cap: false,
+            fault: false,
+            ..
+        })
+    ));
+    assert_eq!(f.receiver.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.sender.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.endpoint.get_ep_state(), Ok(EPState::Idle));
+}
+
+/// A receive from a sender queued on the endpoint takes its message and wakes the sender.
+#[test]
+fn receive_from_waiting_sender() {
+    let mut f = IpcFixture::new();
+    assert_eq!(
+        f.send(0x20, false),
+        IpcOutcome::BlockedOnEndpoint(f.endpoint.handle())
+    );
+    assert_eq!(f.sender.get_state(), ThreadState::ThreadStateBlockedOnSend);
+    let outcome = f.receive();
+    assert!(matches!(
+        outcome,
+        IpcOutcome::Transferred(IpcTransfer {
+            badge: 0x20,
+            caller_cap: false,
+            ..
+        })
+    ));
+    assert_eq!(f.sender.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.receiver.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.endpoint.get_ep_state(), Ok(EPState::Idle));
+}
+
+/// A call queued before the receiver waits leaves the caller waiting for the reply once the
+/// receiver takes it.
+#[test]
+fn receive_from_waiting_caller() {
+    let mut f = IpcFixture::new();
+    assert_eq!(
+        f.send(0x30, true),
+        IpcOutcome::BlockedOnEndpoint(f.endpoint.handle())
+    );
+    let outcome = f.receive();
+    assert!(matches!(
+        outcome,
+        IpcOutcome::Transferred(IpcTransfer {
+            badge: 0x30,
+            caller_cap: true,
+            ..
+        })
+    ));
+    assert_eq!(f.sender.get_state(), ThreadState::ThreadStateBlockedOnReply);
+    assert_eq!(f.receiver.get_state(), ThreadState::ThreadStateRunning);
+}
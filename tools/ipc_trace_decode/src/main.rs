@@ -0,0 +1,165 @@
+//! Decode an IPC trace buffer dumped by `sel4_ipc::trace::trace_dump` into a timeline and
+//! per-object summaries.
+//!
+//! Usage: ipc_trace_decode <dump file> [--summary-only]
+#[path = "../../../src/trace/record.rs"]
+#[allow(dead_code)]
+mod record;
+
+use std::collections::BTreeMap;
+use std::process::ExitCode;
+
+use record::*;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Default)]
+struct ObjectSummary {
+    events: [u64; TraceEvent::ALL.len()],
+    outcomes: [u64; TraceOutcome::ALL.len()],
+    threads: BTreeMap<u64, u64>,
+    first: u64,
+    last: u64,
+}
+
+fn read_words(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+fn decode(words: &[u64]) -> Result<(u64, Vec<TraceRecord>), String> {
+    if words.len() < TRACE_DUMP_HEADER_WORDS {
+        return Err("dump is shorter than its header".into());
+    }
+    if words[0] != TRACE_DUMP_MAGIC {
+        return Err(format!("bad magic {:#x}", words[0]));
+    }
+    if words[1] != TRACE_DUMP_VERSION {
+        return Err(format!(
+            "dump version {} is not supported, expected {}",
+            words[1], TRACE_DUMP_VERSION
+        ));
+    }
+    let recorded = words[3];
+    let records = words[TRACE_DUMP_HEADER_WORDS..]
+        .chunks_exact(TRACE_RECORD_WORDS)
+        .map(|chunk| TraceRecord::from_words(chunk.try_into().unwrap()))
+        .collect();
+    Ok((recorded, records))
+}
+
+fn describe(record: &TraceRecord) -> String {
+    let event = record.event().map_or("?", |event| event.name());
+    let outcome = record.outcome().map_or("?", |outcome| outcome.name());
+    let mut line = format!(
+        "#{:<8} t={:<14} {:<12} {:<11} obj={:#x} thread={:#x}",
+        record.seq, record.timestamp, event, outcome, record.object, record.thread
+    );
+    if record.peer != 0 {
+        line += &format!(" peer={:#x}", record.peer);
+    }
+    if record.badge != 0 {
+        line += &format!(" badge={:#x}", record.badge);
+    }
+    if record.label != 0 || record.length != 0 {
+        line += &format!(" label={:#x} len={}", record.label, record.length);
+    }
+    line
+}
+
+fn summarize(records: &[TraceRecord]) -> BTreeMap<u64, ObjectSummary> {
+    let mut summaries: BTreeMap<u64, ObjectSummary> = BTreeMap::new();
+    for record in records {
+        let summary = summaries.entry(record.object).or_insert_with(|| ObjectSummary {
+            first: record.timestamp,
+            ..Default::default()
+        });
+        if let Some(event) = record.event() {
+            summary.events[event as usize] += 1;
+        }
+        if let Some(outcome) = record.outcome() {
+            summary.outcomes[outcome as usize] += 1;
+        }
+        if record.thread != 0 {
+            *summary.threads.entry(record.thread).or_default() += 1;
+        }
+        summary.last = record.timestamp;
+    }
+    summaries
+}
+
+fn format_summary(object: u64, summary: &ObjectSummary) -> String {
+    let counts = |names: Vec<(&str, u64)>| {
+        names
+            .into_iter()
+            .filter(|(_, count)| *count != 0)
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let events = counts(
+        TraceEvent::ALL
+            .iter()
+            .map(|event| (event.name(), summary.events[*event as usize]))
+            .collect(),
+    );
+    let outcomes = counts(
+        TraceOutcome::ALL
+            .iter()
+            .map(|outcome| (outcome.name(), summary.outcomes[*outcome as usize]))
+            .collect(),
+    );
+    let threads = summary
+        .threads
+        .iter()
+        .map(|(thread, count)| format!("{:#x}({})", thread, count))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "object {:#x}  (t={}..{})\n  events:   {}\n  outcomes: {}\n  threads:  {}",
+        object, summary.first, summary.last, events, outcomes, threads
+    )
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1) else {
+        eprintln!("usage: {} <dump file> [--summary-only]", args[0]);
+        return ExitCode::FAILURE;
+    };
+    let summary_only = args.iter().any(|arg| arg == "--summary-only");
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("cannot read {}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (recorded, records) = match decode(&read_words(&bytes)) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "{} records in dump, {} recorded since boot, {} overwritten",
+        records.len(),
+        recorded,
+        recorded.saturating_sub(records.len() as u64)
+    );
+    if !summary_only {
+        println!();
+        for record in &records {
+            println!("{}", describe(record));
+        }
+    }
+    println!();
+    for (object, summary) in &summarize(&records) {
+        println!("{}", format_summary(*object, summary));
+    }
+    ExitCode::SUCCESS
+}
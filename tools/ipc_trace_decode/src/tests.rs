@@ -0,0 +1,164 @@
+//! Decoding of a known dump, written the way `trace_dump` writes it.
+use super::*;
+
+fn record(
+    seq: u64,
+    timestamp: u64,
+    event: TraceEvent,
+    outcome: TraceOutcome,
+    object: u64,
+    thread: u64,
+) -> TraceRecord {
+    TraceRecord {
+        seq,
+        timestamp,
+        object,
+        thread,
+        kind: TraceRecord::kind_of(event, outcome),
+        ..TraceRecord::EMPTY
+    }
+}
+
+/// A call on endpoint 0x1000 and a signal on notification 0x2000. The buffer has 4 entries and
+/// the first record was overwritten.
+fn known_records() -> Vec<TraceRecord> {
+    vec![
+        TraceRecord {
+            badge: 0x5,
+            ..record(
+                1,
+                100,
+                TraceEvent::Send,
+                TraceOutcome::Blocked,
+                0x1000,
+                0xa000,
+            )
+        },
+        TraceRecord {
+            peer: 0xa000,
+            badge: 0x5,
+            label: 0x2,
+            length: 3,
+            ..record(
+                2,
+                200,
+                TraceEvent::Receive,
+                TraceOutcome::Delivered,
+                0x1000,
+                0xb000,
+            )
+        },
+        TraceRecord {
+            badge: 0x1,
+            ..record(
+                3,
+                250,
+                TraceEvent::Signal,
+                TraceOutcome::Activated,
+                0x2000,
+                0xa000,
+            )
+        },
+        TraceRecord {
+            peer: 0xa000,
+            ..record(
+                4,
+                300,
+                TraceEvent::Reply,
+                TraceOutcome::Delivered,
+                0x1000,
+                0xb000,
+            )
+        },
+    ]
+}
+
+fn dump(header: [u64; TRACE_DUMP_HEADER_WORDS], records: &[TraceRecord]) -> Vec<u8> {
+    header
+        .into_iter()
+        .chain(records.iter().flat_map(|record| record.to_words()))
+        .flat_map(u64::to_le_bytes)
+        .collect()
+}
+
+fn known_dump() -> Vec<u8> {
+    dump(
+        [TRACE_DUMP_MAGIC, TRACE_DUMP_VERSION, 4, 5],
+        &known_records(),
+    )
+}
+
+#[test]
+fn decodes_records() {
+    let (recorded, records) = decode(&read_words(&known_dump())).unwrap();
+    assert_eq!(recorded, 5);
+    assert_eq!(records, known_records());
+}
+
+#[test]
+fn timeline() {
+    let (_, records) = decode(&read_words(&known_dump())).unwrap();
+    let lines: Vec<String> = records.iter().map(describe).collect();
+    assert_eq!(
+        lines,
+        [
+            "#1        t=100            send         blocked     obj=0x1000 thread=0xa000 badge=0x5",
+            "#2        t=200            receive      delivered   obj=0x1000 thread=0xb000 peer=0xa000 badge=0x5 label=0x2 len=3",
+            "#3        t=250            signal       activated   obj=0x2000 thread=0xa000 badge=0x1",
+            "#4        t=300            reply        delivered   obj=0x1000 thread=0xb000 peer=0xa000",
+        ]
+    );
+}
+
+#[test]
+fn summaries() {
+    let (_, records) = decode(&read_words(&known_dump())).unwrap();
+    let summaries: Vec<String> = summarize(&records)
+        .iter()
+        .map(|(object, summary)| format_summary(*object, summary))
+        .collect();
+    assert_eq!(
+        summaries,
+        [
+            "object 0x1000  (t=100..300)\n  events:   send=1 receive=1 reply=1\n  outcomes: delivered=2 blocked=1\n  threads:  0xa000(1) 0xb000(2)",
+            "object 0x2000  (t=250..250)\n  events:   signal=1\n  outcomes: activated=1\n  threads:  0xa000(1)",
+        ]
+    );
+}
+
+/// Unknown events and outcomes of a newer kernel are shown as `?` and not counted.
+#[test]
+fn unknown_kind() {
+    let unknown = TraceRecord {
+        seq: 1,
+        object: 0x1000,
+        thread: 0xa000,
+        kind: 0xffff,
+        ..TraceRecord::EMPTY
+    };
+    assert_eq!(
+        describe(&unknown),
+        "#1        t=0              ?            ?           obj=0x1000 thread=0xa000"
+    );
+    let summary = &summarize(&[unknown])[&0x1000];
+    assert_eq!(summary.events, [0; TraceEvent::ALL.len()]);
+    assert_eq!(summary.outcomes, [0; TraceOutcome::ALL.len()]);
+}
+
+#[test]
+fn rejects_bad_dumps() {
+    assert!(decode(&[TRACE_DUMP_MAGIC, TRACE_DUMP_VERSION]).is_err());
+    let bad_magic = dump([0, TRACE_DUMP_VERSION, 4, 0], &[]);
+    assert!(decode(&read_words(&bad_magic)).is_err());
+    let bad_version = dump([TRACE_DUMP_MAGIC, TRACE_DUMP_VERSION + 1, 4, 0], &[]);
+    assert!(decode(&read_words(&bad_version)).is_err());
+}
+
+/// A dump cut short in the middle of a record keeps the records before it.
+#[test]
+fn ignores_partial_record() {
+    let mut bytes = known_dump();
+    bytes.truncate(bytes.len() - 8);
+    let (_, records) = decode(&read_words(&bytes)).unwrap();
+    assert_eq!(records, known_records()[..3]);
+}
@@ -0,0 +1,18 @@
+//! Model test of the per-object locks of `sel4_ipc::lock` and of the lock-free signal fast
+//! path, run with
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release
+//! ```
+//!
+//! loom explores every interleaving of the simulated cores. Without `cfg(loom)` the same tests
+//! run as a stress test on real threads.
+#[path = "../../../src/lock/signal_word.rs"]
+pub mod signal_word;
+#[path = "../../../src/lock/spin.rs"]
+pub mod spin;
+
+#[cfg(test)]
+mod signal_tests;
+#[cfg(test)]
+mod tests;
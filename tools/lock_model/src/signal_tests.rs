@@ -0,0 +1,295 @@
+//! The lock-free signal fast path against the locked notification paths. The badge and the
+//! waiter queue are plain cells that the paths only touch with the state word claimed, so a
+//! fast signal overlapping a locked path shows up as a data race, and a signal left pending
+//! while a receiver stays queued shows up as a lost wakeup.
+use crate::signal_word::{StateField, STATE_ACTIVE, STATE_IDLE, STATE_WAITING};
+use crate::spin::LockTable;
+use std::sync::atomic::Ordering;
+
+#[cfg(loom)]
+use loom::{
+    cell::UnsafeCell,
+    sync::{atomic::AtomicU64, Arc},
+    thread,
+};
+#[cfg(not(loom))]
+use std::{
+    sync::{atomic::AtomicU64, Arc},
+    thread,
+};
+
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+const NTFN: usize = 0x2000;
+const STRIPES: usize = 4;
+/// The state sits in the low bits of its word like in the notification bitfield, the rest of
+/// the word is the queue tail
+const FIELD: StateField = StateField { shift: 0 };
+
+/// A notification with the parts the signal paths touch
+struct Ntfn {
+    locks: LockTable<STRIPES>,
+    word: AtomicU64,
+    badge: UnsafeCell<u64>,
+    /// Threads blocked on the notification
+    waiters: UnsafeCell<Vec<usize>>,
+    /// Badges delivered to woken waiters
+    delivered: UnsafeCell<Vec<(usize, u64)>>,
+}
+
+unsafe impl Send for Ntfn {}
+unsafe impl Sync for Ntfn {}
+
+fn ntfn() -> Arc<Ntfn> {
+    Arc::new(Ntfn {
+        locks: LockTable::new(),
+        word: AtomicU64::new(FIELD.with(0x1230, STATE_IDLE)),
+        badge: UnsafeCell::new(0),
+        waiters: UnsafeCell::new(Vec::new()),
+        delivered: UnsafeCell::new(Vec::new()),
+    })
+}
+
+fn run(f: impl Fn() + Sync + Send + 'static) {
+    run_bounded(None, f);
+}
+
+/// `run` exploring only the schedules with at most `preemptions` preemptions. Three cores
+/// spinning on each other take too long to explore otherwise.
+fn run_bounded(preemptions: Option<usize>, f: impl Fn() + Sync + Send + 'static) {
+    #[cfg(loom)]
+    {
+        let mut builder = loom::model::Builder::new();
+        if preemptions.is_some() {
+            builder.preemption_bound = preemptions;
+        }
+        builder.check(f);
+    }
+    #[cfg(not(loom))]
+    {
+        let _ = preemptions;
+        for _ in 0..1000 {
+            f();
+        }
+    }
+}
+
+impl Ntfn {
+    /// `send_signal`: the fast path, falling back to the locked path to wake a waiter
+    fn signal(&self, badge: u64, core: usize) {
+        match FIELD.try_claim_fast(&self.word) {
+            Some(STATE_IDLE) => {
+                self.badge.with_mut(|b| unsafe { *b = badge });
+                FIELD.release(&self.word, STATE_ACTIVE);
+            }
+            Some(_) => {
+                self.badge.with_mut(|b| unsafe { *b |= badge });
+                FIELD.release(&self.word, STATE_ACTIVE);
+            }
+            None => self.signal_locked(badge, core),
+        }
+    }
+
+    fn signal_locked(&self, badge: u64, core: usize) {
+        let taken = self.locks.lock_pair(NTFN, 0, core);
+        let state = match FIELD.claim(&self.word) {
+            STATE_WAITING => {
+                let (thread, empty) = self.waiters.with_mut(|w| unsafe {
+                    let thread = (*w).remove(0);
+                    (thread, (*w).is_empty())
+                });
+                self.delivered
+                    .with_mut(|d| unsafe { (*d).push((thread, badge)) });
+                if empty {
+                    STATE_IDLE
+                } else {
+                    STATE_WAITING
+                }
+            }
+            STATE_ACTIVE => {
+                self.badge.with_mut(|b| unsafe { *b |= badge });
+                STATE_ACTIVE
+            }
+            _ => {
+                self.badge.with_mut(|b| unsafe { *b = badge });
+                STATE_ACTIVE
+            }
+        };
+        FIELD.release(&self.word, state);
+        self.locks.unlock_pair(taken, core);
+    }
+
+    /// `receive_signal`: take the pending badge or block
+    fn receive(&self, thread: usize, core: usize) -> Option<u64> {
+        let taken = self.locks.lock_pair(NTFN, 0, core);
+        let (state, badge) = match FIELD.claim(&self.word) {
+            STATE_ACTIVE => (STATE_IDLE, Some(self.badge.with_mut(|b| unsafe { *b }))),
+            _ => {
+                self.waiters.with_mut(|w| unsafe { (*w).push(thread) });
+                (STATE_WAITING, None)
+            }
+        };
+        FIELD.release(&self.word, state);
+        self.locks.unlock_pair(taken, core);
+        badge
+    }
+
+    /// `receive_signal` blocking `thread`: the queue tail in the rest of the state word is
+    /// written with a plain store while the word is claimed, like `set_ntfnQueue_tail`
+    fn receive_and_queue(&self, thread: u64, core: usize) -> Option<u64> {
+        let taken = self.locks.lock_pair(NTFN, 0, core);
+        let (state, badge) = match FIELD.claim(&self.word) {
+            STATE_ACTIVE => (STATE_IDLE, Some(self.badge.with_mut(|b| unsafe { *b }))),
+            _ => {
+                self.waiters
+                    .with_mut(|w| unsafe { (*w).push(thread as usize) });
+                let word = self.word.load(Ordering::Relaxed);
+                self.word
+                    .store(FIELD.with(thread << 4, FIELD.get(word)), Ordering::Relaxed);
+                (STATE_WAITING, None)
+            }
+        };
+        FIELD.release(&self.word, state);
+        self.locks.unlock_pair(taken, core);
+        badge
+    }
+
+    /// The queue tail kept in the rest of the state word
+    fn tail(&self) -> u64 {
+        let word = self.word.load(Ordering::Acquire);
+        assert_ne!(FIELD.get(word), 3, "the word was left busy");
+        word & !3
+    }
+
+    /// Badge a blocked thread was woken with
+    fn woken_with(&self, thread: usize) -> Option<u64> {
+        self.delivered.with_mut(|d| unsafe {
+            (*d).iter()
+                .find(|(woken, _)| *woken == thread)
+                .map(|(_, badge)| *badge)
+        })
+    }
+
+    /// Badge left pending in an active notification
+    fn pending(&self) -> Option<u64> {
+        let word = self.word.load(std::sync::atomic::Ordering::Acquire);
+        // The rest of the word survives every claim.
+        assert_eq!(word & !3, 0x1230);
+        match FIELD.get(word) {
+            STATE_ACTIVE => Some(self.badge.with_mut(|b| unsafe { *b })),
+            state => {
+                assert_ne!(state, 3, "the word was left busy");
+                None
+            }
+        }
+    }
+
+    fn waiting(&self) -> usize {
+        self.waiters.with_mut(|w| unsafe { (*w).len() })
+    }
+}
+
+/// A fast signal racing a receiver: the receiver either takes the badge or blocks and is woken
+/// with it, the signal is never left pending next to a blocked receiver.
+#[test]
+fn signal_and_receive() {
+    run(|| {
+        let n = ntfn();
+        let signaller = {
+            let n = n.clone();
+            thread::spawn(move || n.signal(1, 0))
+        };
+        let received = n.receive(7, 1);
+        signaller.join().unwrap();
+        match received {
+            Some(badge) => assert_eq!(badge, 1),
+            None => assert_eq!(n.woken_with(7), Some(1)),
+        }
+        assert_eq!(n.waiting(), 0);
+        assert_eq!(n.pending(), None);
+    });
+}
+
+/// Two fast signals and a receiver: the badges are coalesced, every bit reaches the receiver or
+/// stays pending, and the receiver is never left blocked.
+#[test]
+fn signals_coalesce_around_receive() {
+    run_bounded(Some(1), || {
+        let n = ntfn();
+        let signallers: Vec<_> = [(1, 0), (2, 1)]
+            .into_iter()
+            .map(|(badge, core)| {
+                let n = n.clone();
+                thread::spawn(move || n.signal(badge, core))
+            })
+            .collect();
+        let received = n.receive(7, 2);
+        for signaller in signallers {
+            signaller.join().unwrap();
+        }
+        let got = received.or_else(|| n.woken_with(7));
+        assert!(got.is_some(), "the receiver was left blocked");
+        assert_eq!(got.unwrap() | n.pending().unwrap_or(0), 3);
+        assert_eq!(got.unwrap() & n.pending().unwrap_or(0), 0);
+        assert_eq!(n.waiting(), 0);
+    });
+}
+
+/// A fast signal in flight while the locked path writes the queue tail into the state word:
+/// the tail survives the compare-and-swap and the release of the fast path, and the signal is
+/// taken or wakes the receiver.
+#[test]
+fn queue_tail_written_during_fast_signal() {
+    run(|| {
+        let n = ntfn();
+        let signaller = {
+            let n = n.clone();
+            thread::spawn(move || n.signal(1, 0))
+        };
+        let received = n.receive_and_queue(7, 1);
+        signaller.join().unwrap();
+        match received {
+            Some(badge) => {
+                assert_eq!(badge, 1);
+                assert_eq!(n.tail(), 0x1230);
+            }
+            None => {
+                assert_eq!(n.woken_with(7), Some(1));
+                assert_eq!(n.tail(), 7 << 4);
+            }
+        }
+        assert_eq!(n.waiting(), 0);
+    });
+}
+
+/// A blocked receiver is woken by the locked path, the fast path backs off from a waiting
+/// notification.
+#[test]
+fn signal_wakes_blocked_receiver() {
+    run(|| {
+        let n = ntfn();
+        assert_eq!(n.receive(7, 1), None);
+        let signaller = {
+            let n = n.clone();
+            thread::spawn(move || n.signal(4, 0))
+        };
+        let received = n.receive(8, 1);
+        signaller.join().unwrap();
+        assert_eq!(n.woken_with(7), Some(4));
+        assert_eq!(received, None);
+        assert_eq!(n.waiting(), 1);
+        assert_eq!(n.pending(), None);
+    });
+}
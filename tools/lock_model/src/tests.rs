@@ -0,0 +1,189 @@
+//! Each test mirrors the locking of an IPC path: the queue of an object is a plain cell that
+//! is only touched with the lock of the object held, so a missing or misordered lock shows up
+//! as a data race, a deadlock or a broken queue.
+use crate::spin::LockTable;
+
+#[cfg(loom)]
+use loom::{cell::UnsafeCell, sync::Arc, thread};
+#[cfg(not(loom))]
+use std::{sync::Arc, thread};
+
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        UnsafeCell(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// Two objects whose addresses hash to different locks of a four-lock table
+const EP: usize = 0x1000;
+const NTFN: usize = 0x2000;
+const STRIPES: usize = 4;
+
+/// An endpoint or notification queue, the threads are plain ids
+struct Queue(UnsafeCell<Vec<usize>>);
+
+unsafe impl Send for Queue {}
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    fn new() -> Self {
+        Queue(UnsafeCell::new(Vec::new()))
+    }
+
+    fn push(&self, thread: usize) {
+        self.0.with_mut(|queue| unsafe { (*queue).push(thread) });
+    }
+
+    fn pop(&self) -> Option<usize> {
+        self.0.with_mut(|queue| unsafe {
+            if (*queue).is_empty() {
+                None
+            } else {
+                Some((*queue).remove(0))
+            }
+        })
+    }
+
+    fn remove(&self, thread: usize) -> bool {
+        self.0.with_mut(|queue| unsafe {
+            let len = (*queue).len();
+            (*queue).retain(|t| *t != thread);
+            (*queue).len() != len
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.0.with_mut(|queue| unsafe { (*queue).len() })
+    }
+}
+
+struct Model {
+    locks: LockTable<STRIPES>,
+    ep: Queue,
+    ntfn: Queue,
+}
+
+fn model() -> Arc<Model> {
+    assert_ne!(
+        LockTable::<STRIPES>::index(EP),
+        LockTable::<STRIPES>::index(NTFN)
+    );
+    Arc::new(Model {
+        locks: LockTable::new(),
+        ep: Queue::new(),
+        ntfn: Queue::new(),
+    })
+}
+
+fn run(f: impl Fn() + Sync + Send + 'static) {
+    #[cfg(loom)]
+    loom::model(f);
+    #[cfg(not(loom))]
+    for _ in 0..1000 {
+        f();
+    }
+}
+
+/// `send_ipc` and `receive_ipc` on one endpoint from two cores: the message is delivered
+/// exactly once, whichever core comes first.
+#[test]
+fn send_and_receive() {
+    run(|| {
+        let m = model();
+        let sender = {
+            let m = m.clone();
+            thread::spawn(move || {
+                let taken = m.locks.lock_pair(EP, 0, 0);
+                // Deliver to a waiting receiver, or queue the sender.
+                let delivered = m.ep.pop().is_some();
+                if !delivered {
+                    m.ep.push(1);
+                }
+                m.locks.unlock_pair(taken, 0);
+                delivered
+            })
+        };
+        let taken = m.locks.lock_pair(EP, 0, 1);
+        let received = m.ep.pop().is_some();
+        if !received {
+            m.ep.push(2);
+        }
+        m.locks.unlock_pair(taken, 1);
+        let delivered = sender.join().unwrap();
+        // The second core to take the lock finds the first one queued.
+        assert!(delivered != received);
+        assert_eq!(m.ep.len(), 0);
+    });
+}
+
+/// `receive_ipc` with a bound notification and `send_signal` to a bound TCB blocked on the
+/// endpoint take the same two locks from opposite sides. Both lock in table order, and the
+/// nested `cancel_ipc` takes the endpoint lock again, so neither deadlocks.
+#[test]
+fn receive_with_bound_notification_and_signal() {
+    run(|| {
+        let m = model();
+        let signaller = {
+            let m = m.clone();
+            thread::spawn(move || {
+                let taken = m.locks.lock_pair(NTFN, EP, 0);
+                // cancel_ipc of the bound TCB locks its endpoint again.
+                let nested = m.locks.lock_pair(EP, 0, 0);
+                let woken = m.ep.remove(7);
+                if !woken {
+                    m.ntfn.push(7);
+                }
+                m.locks.unlock_pair(nested, 0);
+                m.locks.unlock_pair(taken, 0);
+            })
+        };
+        let taken = m.locks.lock_pair(EP, NTFN, 1);
+        // complete_signal locks the notification again.
+        let nested = m.locks.lock_pair(NTFN, 0, 1);
+        let signalled = m.ntfn.pop().is_some();
+        if !signalled {
+            m.ep.push(7);
+        }
+        m.locks.unlock_pair(nested, 1);
+        m.locks.unlock_pair(taken, 1);
+        signaller.join().unwrap();
+        // The signal either completed the receive or woke the blocked receiver.
+        assert_eq!(m.ep.len() + m.ntfn.len(), 0);
+    });
+}
+
+/// `cancel_all_ipc` racing a sender: every queued sender is either cancelled or still queued,
+/// never both.
+#[test]
+fn cancel_all_and_send() {
+    run(|| {
+        let m = model();
+        let canceller = {
+            let m = m.clone();
+            thread::spawn(move || {
+                let taken = m.locks.lock_pair(EP, 0, 0);
+                let mut cancelled = 0;
+                while m.ep.pop().is_some() {
+                    cancelled += 1;
+                }
+                m.locks.unlock_pair(taken, 0);
+                cancelled
+            })
+        };
+        for thread in 1..=2 {
+            let taken = m.locks.lock_pair(EP, 0, 1);
+            m.ep.push(thread);
+            m.locks.unlock_pair(taken, 1);
+        }
+        let cancelled = canceller.join().unwrap();
+        assert_eq!(cancelled + m.ep.len(), 2);
+    });
+}
@@ -0,0 +1,139 @@
+//! The kernel objects the tests run on, allocated in host memory with the size and alignment
+//! the kernel gives them, so the pointer arithmetic of the kernel finds what it expects.
+use std::alloc::{alloc_zeroed, Layout};
+use std::sync::{Mutex, MutexGuard};
+
+use sel4_common::arch::ArchReg;
+use sel4_common::sel4_config::{
+    seL4_MinSchedContextBits, seL4_ReplyBits, seL4_TCBBits, TCB_OFFSET,
+};
+use sel4_common::structures_gen::{cap_reply_cap, endpoint};
+use sel4_ipc::{endpoint_func, IpcOutcome, Transfer};
+use sel4_task::reply::reply_t;
+use sel4_task::sched_context::sched_context_t;
+use sel4_task::{set_thread_state, tcb_t, ThreadState};
+
+/// Refills of the scheduling contexts, as `seL4_SchedContext_Configure` with the minimum
+const REFILLS: usize = 2;
+const BUDGET: u64 = 1_000_000;
+const PERIOD: u64 = 1_000_000;
+
+/// The scheduler state is global, so the tests run one at a time
+static KERNEL: Mutex<()> = Mutex::new(());
+
+/// A zeroed object of `2^bits` bytes aligned to its size, like an untyped retype. Objects are
+/// never freed, a test may leave a thread queued on them.
+fn object(bits: usize) -> usize {
+    let layout = Layout::from_size_align(1 << bits, 1 << bits).unwrap();
+    let ptr = unsafe { alloc_zeroed(layout) };
+    assert!(!ptr.is_null());
+    ptr as usize
+}
+
+fn new_thread() -> &'static mut tcb_t {
+    // The TCB follows the CNode slots of the thread in the object.
+    let thread = unsafe { &mut *((object(seL4_TCBBits) + TCB_OFFSET) as *mut tcb_t) };
+    thread.tcbPriority = 100;
+    thread
+}
+
+fn new_sched_context(thread: &mut tcb_t) -> &'static mut sched_context_t {
+    let sc = unsafe { &mut *(object(seL4_MinSchedContextBits) as *mut sched_context_t) };
+    sc.refill_new(REFILLS, BUDGET, PERIOD);
+    sc.sched_context_bind_tcb(thread);
+    sc
+}
+
+pub struct McsFixture {
+    /// A thread with a configured and active scheduling context, not blocked on anything
+    pub client: &'static mut tcb_t,
+    /// A second thread like `client`, with a scheduling context of its own
+    pub other_client: &'static mut tcb_t,
+    /// A thread without a scheduling context, not blocked on anything
+    pub server: &'static mut tcb_t,
+    /// An idle endpoint
+    pub endpoint: &'static mut endpoint,
+    /// A cap to an unused reply object
+    pub reply_cap: cap_reply_cap,
+    /// The scheduling context of `client`
+    pub client_sc: usize,
+    /// The scheduling context of `other_client`
+    pub other_client_sc: usize,
+    _kernel: MutexGuard<'static, ()>,
+}
+
+impl McsFixture {
+    pub fn new() -> Self {
+        let kernel = KERNEL
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let client = new_thread();
+        let other_client = new_thread();
+        let client_sc = new_sched_context(client).get_ptr();
+        let other_client_sc = new_sched_context(other_client).get_ptr();
+        let reply = object(seL4_ReplyBits);
+        let mut reply_cap: cap_reply_cap = unsafe { core::mem::zeroed() };
+        reply_cap.set_capReplyPtr(reply as u64);
+        reply_cap.set_capReplyCanGrant(1);
+        McsFixture {
+            client,
+            other_client,
+            server: new_thread(),
+            endpoint: unsafe { &mut *(object(4) as *mut endpoint) },
+            reply_cap,
+            client_sc,
+            other_client_sc,
+            _kernel: kernel,
+        }
+    }
+
+    /// The reply object of the reply cap
+    pub fn reply(&self) -> &'static mut reply_t {
+        unsafe { &mut *(self.reply_cap.get_capReplyPtr() as usize as *mut reply_t) }
+    }
+
+    /// Make a thread runnable with an empty message, as if it was about to enter the kernel
+    pub fn ready(thread: &mut tcb_t) {
+        thread.tcbArch.set_register(ArchReg::MsgInfo, 0);
+        set_thread_state(thread, ThreadState::ThreadStateRunning);
+    }
+
+    /// Block the server on the endpoint with the reply object
+    pub fn server_waits(&mut self) {
+        Self::ready(self.server);
+        let outcome = self
+            .endpoint
+            .receive_ipc(self.server, true, Some(&mut self.reply_cap))
+            .unwrap();
+        assert_eq!(
+            outcome,
+            IpcOutcome::BlockedOnEndpoint(self.endpoint.handle())
+        );
+        assert_eq!(
+            self.server.get_state(),
+            ThreadState::ThreadStateBlockedOnReceive
+        );
+        assert_eq!(self.reply().replyTCB, self.server.get_ptr());
+    }
+
+    /// The server receives from the endpoint with the reply object
+    pub fn server_receives(&mut self) -> IpcOutcome {
+        Self::ready(self.server);
+        self.endpoint
+            .receive_ipc(self.server, true, Some(&mut self.reply_cap))
+            .unwrap()
+    }
+
+    /// Call the endpoint from `client`, the way `seL4_Call` does
+    pub fn call(endpoint: &mut endpoint, client: &mut tcb_t) -> IpcOutcome {
+        Self::ready(client);
+        endpoint
+            .send_ipc(client, true, true, false, 0x10, true, true)
+            .unwrap()
+    }
+
+    /// The server replies through the reply object
+    pub fn reply_from_server(&mut self) {
+        self.server.do_reply(self.reply(), false).unwrap();
+    }
+}
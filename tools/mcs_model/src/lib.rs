@@ -0,0 +1,15 @@
+//! Host test of the passive server paths of `sel4_ipc` under MCS, run with
+//!
+//! ```text
+//! cargo test
+//! ```
+//!
+//! A passive server has no scheduling context of its own and runs on the one its caller
+//! donates with the call. Each test builds its threads, endpoint, reply object and scheduling
+//! contexts in host memory, drives the IPC paths on them, and checks the thread states, the
+//! owner of every scheduling context and the reply object against what upstream seL4 does in
+//! the same situation.
+#[cfg(test)]
+mod fixture;
+#[cfg(test)]
+mod tests;
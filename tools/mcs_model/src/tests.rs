@@ -0,0 +1,168 @@
+//! The passive server scenarios. Every test starts from fresh objects, so none depends on
+//! what another left behind.
+use sel4_common::structures_gen::{seL4_Fault_Timeout, seL4_Fault_tag};
+use sel4_ipc::{endpoint_func, EPState, IpcOutcome, IpcTransfer, Transfer};
+use sel4_task::ThreadState;
+
+use crate::fixture::McsFixture;
+
+/// A call to a server waiting on the endpoint donates the scheduling context of the caller,
+/// the reply returns it.
+#[test]
+fn call_to_waiting_server() {
+    let mut f = McsFixture::new();
+    f.server_waits();
+    let outcome = McsFixture::call(f.endpoint, f.client);
+    assert!(matches!(
+        outcome,
+        IpcOutcome::Transferred(IpcTransfer {
+            badge: 0x10,
+            caller_cap: true,
+            donated: true,
+            ..
+        })
+    ));
+    assert_eq!(f.client.get_state(), ThreadState::ThreadStateBlockedOnReply);
+    assert_eq!(f.client.tcbSchedContext, 0);
+    assert_eq!(f.server.tcbSchedContext, f.client_sc);
+    assert_eq!(f.server.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.reply().replyTCB, f.client.get_ptr());
+    f.reply_from_server();
+    assert_eq!(f.client.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.client.tcbSchedContext, f.client_sc);
+    assert_eq!(f.server.tcbSchedContext, 0);
+    assert_eq!(f.reply().replyTCB, 0);
+}
+
+/// A caller queued before the server waits keeps its scheduling context until the server
+/// receives the call.
+#[test]
+fn call_before_server_waits() {
+    let mut f = McsFixture::new();
+    let outcome = McsFixture::call(f.endpoint, f.client);
+    assert_eq!(outcome, IpcOutcome::BlockedOnEndpoint(f.endpoint.handle()));
+    assert_eq!(f.client.get_state(), ThreadState::ThreadStateBlockedOnSend);
+    assert_eq!(f.client.tcbSchedContext, f.client_sc);
+    assert!(matches!(f.endpoint.get_ep_state(), Ok(EPState::Send)));
+    f.server_receives();
+    assert_eq!(f.client.get_state(), ThreadState::ThreadStateBlockedOnReply);
+    assert_eq!(f.server.tcbSchedContext, f.client_sc);
+    assert!(matches!(f.endpoint.get_ep_state(), Ok(EPState::Idle)));
+    f.reply_from_server();
+    assert_eq!(f.client.tcbSchedContext, f.client_sc);
+    assert_eq!(f.server.tcbSchedContext, 0);
+    assert_eq!(f.reply().replyTCB, 0);
+}
+
+/// Cancelling a caller blocked on the reply, e.g. by suspending it, takes it off the reply
+/// object. The server keeps running on the donated scheduling context, and its reply finds
+/// nobody to reply to.
+#[test]
+fn caller_cancelled_mid_call() {
+    let mut f = McsFixture::new();
+    f.server_waits();
+    McsFixture::call(f.endpoint, f.client);
+    f.client.cancel_ipc().unwrap();
+    assert_eq!(f.client.tcbState.get_replyObject(), 0);
+    assert_eq!(f.reply().replyTCB, 0);
+    assert_eq!(f.server.tcbSchedContext, f.client_sc);
+    f.reply_from_server();
+    assert_eq!(f.server.tcbSchedContext, f.client_sc);
+    assert_eq!(f.client.tcbSchedContext, 0);
+}
+
+/// Receiving again with a reply object that still holds the reply of an earlier call cancels
+/// that caller, and the reply object then serves the next call.
+#[test]
+fn reply_object_reused() {
+    let mut f = McsFixture::new();
+    f.server_waits();
+    McsFixture::call(f.endpoint, f.client);
+    assert_eq!(f.reply().replyTCB, f.client.get_ptr());
+    f.server_receives();
+    assert_eq!(f.client.tcbState.get_replyObject(), 0);
+    assert_eq!(f.reply().replyTCB, f.server.get_ptr());
+    assert_eq!(
+        f.server.get_state(),
+        ThreadState::ThreadStateBlockedOnReceive
+    );
+    // The server still runs on the scheduling context of the cancelled call, so the next
+    // caller has nothing to donate to.
+    McsFixture::call(f.endpoint, f.other_client);
+    assert_eq!(f.reply().replyTCB, f.other_client.get_ptr());
+    assert_eq!(
+        f.other_client.get_state(),
+        ThreadState::ThreadStateBlockedOnReply
+    );
+    assert_eq!(f.other_client.tcbSchedContext, f.other_client_sc);
+    assert_eq!(f.server.tcbSchedContext, f.client_sc);
+    f.reply_from_server();
+    assert_eq!(f.other_client.get_state(), ThreadState::ThreadStateRunning);
+    assert_eq!(f.other_client.tcbSchedContext, f.other_client_sc);
+    assert_eq!(f.reply().replyTCB, 0);
+}
+
+/// A timeout fault is sent without donating, whether the server waits already or receives it
+/// from the queue. The reply to the fault clears it and gives the thread its budget back.
+#[test]
+fn timeout_fault_not_donated() {
+    for server_first in [true, false] {
+        let mut f = McsFixture::new();
+        if server_first {
+            f.server_waits();
+        }
+        McsFixture::ready(f.client);
+        f.client.tcbFault = seL4_Fault_Timeout::new(0x20).unsplay();
+        // As `handleTimeout` sends it: not a call and without donation.
+        let outcome = f
+            .endpoint
+            .send_ipc(f.client, true, false, true, 0x10, true, false)
+            .unwrap();
+        if server_first {
+            assert!(matches!(
+                outcome,
+                IpcOutcome::Transferred(IpcTransfer {
+                    fault: true,
+                    caller_cap: true,
+                    donated: false,
+                    ..
+                })
+            ));
+        } else {
+            assert_eq!(f.client.get_state(), ThreadState::ThreadStateBlockedOnSend);
+            f.server_receives();
+        }
+        assert_eq!(f.client.get_state(), ThreadState::ThreadStateBlockedOnReply);
+        assert_eq!(f.client.tcbSchedContext, f.client_sc);
+        assert_eq!(f.server.tcbSchedContext, 0);
+        assert_eq!(f.reply().replyTCB, f.client.get_ptr());
+        f.reply_from_server();
+        assert_eq!(
+            f.client.tcbFault.get_tag(),
+            seL4_Fault_tag::seL4_Fault_NullFault
+        );
+        assert_ne!(f.client.get_state(), ThreadState::ThreadStateBlockedOnReply);
+        assert_eq!(f.client.tcbSchedContext, f.client_sc);
+        assert_eq!(f.reply().replyTCB, 0);
+    }
+}
+
+/// Cancelling the IPC of a thread queued with a fault drops the fault, and a call cancelled
+/// before it was received never donated anything.
+#[test]
+fn cancel_clears_fault() {
+    let f = McsFixture::new();
+    McsFixture::ready(f.client);
+    f.client.tcbFault = seL4_Fault_Timeout::new(0x20).unsplay();
+    f.endpoint
+        .send_ipc(f.client, true, false, true, 0x10, true, false)
+        .unwrap();
+    f.client.cancel_ipc().unwrap();
+    assert_eq!(
+        f.client.tcbFault.get_tag(),
+        seL4_Fault_tag::seL4_Fault_NullFault
+    );
+    assert_eq!(f.client.get_state(), ThreadState::ThreadStateInactive);
+    assert!(matches!(f.endpoint.get_ep_state(), Ok(EPState::Idle)));
+    assert_eq!(f.client.tcbSchedContext, f.client_sc);
+}
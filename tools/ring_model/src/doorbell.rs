@@ -0,0 +1,75 @@
+//! A ring in host memory and a doorbell standing in for its notification.
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use sel4_ipc_abi::ring::{Ring, RingEntry, RingSlot};
+
+/// How long a consumer sleeps on the doorbell before the wakeup is taken as lost
+const LOST_WAKEUP: Duration = Duration::from_secs(10);
+
+/// Set up a ring of `entries` slots that is never freed, so that threads can share it, and
+/// return its base
+pub fn new_ring(entries: usize) -> usize {
+    let region = Box::leak(vec![RingSlot::default(); entries + 1].into_boxed_slice());
+    Ring::init(region).unwrap();
+    region.as_ptr() as usize
+}
+
+/// A view of the ring at `base` for the calling thread
+pub fn view(base: usize, entries: usize) -> Ring<'static> {
+    unsafe { Ring::from_raw(base, entries) }
+}
+
+#[derive(Default)]
+/// A notification with a single signal bit, as a doorbell has
+pub struct Doorbell {
+    signalled: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl Doorbell {
+    /// `send_signal` by the producer
+    pub fn signal(&self) {
+        *self.signalled.lock().unwrap() = true;
+        self.wakeup.notify_one();
+    }
+
+    /// `receive_signal` by the consumer. Panics if a blocking wait is not signalled in time.
+    fn receive(&self, is_blocking: bool) {
+        let mut signalled = self.signalled.lock().unwrap();
+        if is_blocking {
+            let (woken, timeout) = self
+                .wakeup
+                .wait_timeout_while(signalled, LOST_WAKEUP, |signalled| !*signalled)
+                .unwrap();
+            assert!(!timeout.timed_out(), "the consumer slept through a message");
+            signalled = woken;
+        }
+        *signalled = false;
+    }
+
+    /// Wait for `ring` the way `wait_ipc_ring` does, returning whether the consumer blocked
+    pub fn wait(&self, ring: &Ring<'_>) -> bool {
+        if !ring.is_empty() {
+            self.receive(false);
+            return false;
+        }
+        if !ring.prepare_wait() {
+            ring.cancel_wait();
+            self.receive(false);
+            return false;
+        }
+        self.receive(true);
+        true
+    }
+}
+
+/// Take the next message of `ring`, waiting on `doorbell` while it is empty
+pub fn take(ring: &Ring<'_>, doorbell: &Doorbell) -> RingEntry {
+    loop {
+        if let Some(entry) = ring.dequeue() {
+            return entry;
+        }
+        doorbell.wait(ring);
+    }
+}
@@ -0,0 +1,14 @@
+//! Host test of the shared-memory IPC rings of `sel4_ipc_abi`, run with
+//!
+//! ```text
+//! cargo test
+//! ```
+//!
+//! The producer and the consumer of a ring run as host threads on a ring in host memory. The
+//! doorbell is a simulated notification, and the consumer waits on it the way `wait_ipc_ring`
+//! does in the kernel, so the tests check that the `RING_NEED_DOORBELL` handshake of
+//! `Ring::enqueue` and `Ring::prepare_wait` never loses a wakeup.
+#[cfg(test)]
+mod doorbell;
+#[cfg(test)]
+mod tests;
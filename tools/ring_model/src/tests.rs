@@ -0,0 +1,134 @@
+//! The ring scenarios. Every test sets up a ring of its own.
+use std::sync::Arc;
+use std::thread;
+
+use sel4_ipc_abi::ring::{RingError, RING_NEED_DOORBELL, RING_SLOT_WORDS};
+use sel4_ipc_abi::MessageInfo;
+
+use crate::doorbell::{new_ring, take, view, Doorbell};
+
+const ENTRIES: usize = 4;
+/// Messages the threaded tests send, many times the slots so that the ring wraps
+const MESSAGES: usize = 100_000;
+
+fn flags(base: usize) -> usize {
+    // The flags follow the head and tail in the header.
+    unsafe { *((base as *const usize).add(2)) }
+}
+
+/// Messages come out in the order they went in, until the ring is full.
+#[test]
+fn enqueue_dequeue_in_order() {
+    let ring = view(new_ring(ENTRIES), ENTRIES);
+    for label in 0..ENTRIES {
+        let words = [label; RING_SLOT_WORDS];
+        assert_eq!(
+            ring.enqueue(MessageInfo::new(label, label), &words),
+            Ok(false)
+        );
+    }
+    assert_eq!(
+        ring.enqueue(MessageInfo::new(0, 0), &[]),
+        Err(RingError::Full)
+    );
+    assert_eq!(ring.len(), ENTRIES);
+    for label in 0..ENTRIES {
+        let entry = ring.dequeue().unwrap();
+        assert_eq!(entry.info.label(), label);
+        assert_eq!(entry.words(), &[label; RING_SLOT_WORDS][..label]);
+    }
+    assert!(ring.dequeue().is_none());
+    assert!(ring.is_empty());
+}
+
+/// Messages that do not fit a slot are refused and leave the ring as it was.
+#[test]
+fn refuses_what_a_slot_cannot_hold() {
+    let ring = view(new_ring(ENTRIES), ENTRIES);
+    let words = [0; RING_SLOT_WORDS + 1];
+    let with_cap = MessageInfo::from_word(MessageInfo::new(1, 0).word() | 1 << 7);
+    assert_eq!(with_cap.extra_caps(), 1);
+    assert_eq!(ring.enqueue(with_cap, &words), Err(RingError::HasCaps));
+    assert_eq!(
+        ring.enqueue(MessageInfo::new(1, RING_SLOT_WORDS + 1), &words),
+        Err(RingError::TooLong)
+    );
+    assert_eq!(
+        ring.enqueue(MessageInfo::new(1, 2), &words[..1]),
+        Err(RingError::TooLong)
+    );
+    assert!(ring.is_empty());
+}
+
+/// The producer is told to ring only while the consumer is about to sleep, and taking a
+/// message or giving up the wait stops it.
+#[test]
+fn doorbell_only_while_the_consumer_sleeps() {
+    let base = new_ring(ENTRIES);
+    let ring = view(base, ENTRIES);
+    let message = MessageInfo::new(1, 0);
+    assert_eq!(ring.enqueue(message, &[]), Ok(false));
+    // A consumer that finds a message does not sleep.
+    assert!(!ring.prepare_wait());
+    ring.cancel_wait();
+    assert_eq!(flags(base) & RING_NEED_DOORBELL, 0);
+    ring.dequeue().unwrap();
+
+    assert!(ring.prepare_wait());
+    assert_eq!(flags(base) & RING_NEED_DOORBELL, RING_NEED_DOORBELL);
+    assert_eq!(ring.enqueue(message, &[]), Ok(true));
+    ring.dequeue().unwrap();
+    assert_eq!(flags(base) & RING_NEED_DOORBELL, 0);
+    assert_eq!(ring.enqueue(message, &[]), Ok(false));
+}
+
+/// A producer and a consumer on their own threads: the consumer gets every message in order,
+/// and never sleeps through one, whether it blocks or the producer is ahead.
+#[test]
+fn no_lost_wakeup() {
+    let base = new_ring(ENTRIES);
+    let doorbell = Arc::new(Doorbell::default());
+    let producer = {
+        let doorbell = doorbell.clone();
+        thread::spawn(move || {
+            let ring = view(base, ENTRIES);
+            let mut sent = 0;
+            while sent < MESSAGES {
+                match ring.enqueue(MessageInfo::new(sent, 1), &[sent]) {
+                    Ok(ring_doorbell) => {
+                        if ring_doorbell {
+                            doorbell.signal();
+                        }
+                        sent += 1;
+                    }
+                    Err(RingError::Full) => thread::yield_now(),
+                    Err(error) => panic!("enqueue failed with {:?}", error),
+                }
+            }
+        })
+    };
+    let ring = view(base, ENTRIES);
+    for expected in 0..MESSAGES {
+        let entry = take(&ring, &doorbell);
+        assert_eq!(entry.info.label(), expected);
+        assert_eq!(entry.words(), &[expected]);
+    }
+    producer.join().unwrap();
+    assert!(ring.is_empty());
+}
+
+/// A consumer woken by a stale signal checks the ring again instead of taking a message that
+/// is not there.
+#[test]
+fn stale_signal_is_harmless() {
+    let base = new_ring(ENTRIES);
+    let ring = view(base, ENTRIES);
+    let doorbell = Doorbell::default();
+    doorbell.signal();
+    // The pending signal ends the wait, the ring is still empty.
+    assert!(doorbell.wait(&ring));
+    assert!(ring.dequeue().is_none());
+    assert_eq!(ring.enqueue(MessageInfo::new(1, 0), &[]), Ok(true));
+    doorbell.signal();
+    assert_eq!(take(&ring, &doorbell).info.label(), 1);
+}
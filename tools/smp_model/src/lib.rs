@@ -0,0 +1,17 @@
+//! Host test of the cross-core wakeups of `sel4_ipc::smp`, run with
+//!
+//! ```text
+//! cargo test
+//! ```
+//!
+//! The simulated scheduler of `smp/sim.rs` queues wakeups through the same `RemoteWakeups` as
+//! the kernel path and delivers the reschedule IPIs when a test says so, so the tests can check
+//! where a wakeup lands, how many IPIs are sent and what happens between sending an IPI and its
+//! arrival.
+#[path = "../../../src/smp/remote.rs"]
+pub mod remote;
+#[path = "../../../src/smp/sim.rs"]
+pub mod sim;
+
+#[cfg(test)]
+mod tests;
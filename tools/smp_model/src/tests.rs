@@ -0,0 +1,131 @@
+//! The wakeup scenarios. The simulated kernel runs on core 0 unless a test moves it.
+use crate::remote::WakeupKind;
+use crate::sim::SimScheduler;
+
+const CORES: usize = 4;
+const DEPTH: usize = 4;
+
+type Sim = SimScheduler<CORES, DEPTH>;
+
+/// Cores other than `core` have nothing ready and nothing queued.
+fn only_on(sched: &Sim, core: usize) {
+    for other in (0..CORES).filter(|other| *other != core) {
+        assert!(
+            sched.ready(other).is_empty(),
+            "core {other} has ready threads"
+        );
+        assert_eq!(sched.pending(other), 0, "core {other} has queued wakeups");
+    }
+}
+
+/// A thread of the current core is ready at once, without an IPI.
+#[test]
+fn local_wakeup_needs_no_ipi() {
+    let mut sched = Sim::new();
+    assert!(sched.wake(0x10, 0, WakeupKind::SwitchTo));
+    assert_eq!(sched.ready(0), &[0x10]);
+    assert_eq!(sched.switched_to[0], Some(0x10));
+    sched.exit_kernel();
+    assert_eq!(sched.ipis_sent, 0);
+    only_on(&sched, 0);
+}
+
+/// A thread of another core waits in the queue of that core until its IPI arrives, and then
+/// runs there and nowhere else.
+#[test]
+fn remote_wakeup_reaches_its_core() {
+    let mut sched = Sim::new();
+    assert!(sched.wake(0x10, 2, WakeupKind::Enqueue));
+    assert!(sched.ready(2).is_empty());
+    assert_eq!(sched.pending(2), 1);
+    sched.exit_kernel();
+    assert!(sched.ready(2).is_empty());
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(2), &[0x10]);
+    assert_eq!(sched.switched_to[2], None);
+    assert_eq!(sched.pending(2), 0);
+    only_on(&sched, 2);
+}
+
+/// However many threads of a core are woken, the core gets one IPI per kernel exit.
+#[test]
+fn one_ipi_per_core() {
+    let mut sched = Sim::new();
+    for thread in [0x10, 0x11, 0x12] {
+        assert!(sched.wake(thread, 1, WakeupKind::Enqueue));
+    }
+    for thread in [0x20, 0x21] {
+        assert!(sched.wake(thread, 3, WakeupKind::SwitchTo));
+    }
+    sched.exit_kernel();
+    assert_eq!(sched.ipis_sent, 2);
+    // Nothing new was woken, so leaving the kernel again sends nothing.
+    sched.exit_kernel();
+    assert_eq!(sched.ipis_sent, 2);
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(1), &[0x10, 0x11, 0x12]);
+    assert_eq!(sched.ready(3), &[0x20, 0x21]);
+    assert_eq!(sched.switched_to[3], Some(0x21));
+    assert!(sched.ready(2).is_empty());
+}
+
+/// Waking a queued thread again queues it once, a `SwitchTo` upgrades an `Enqueue`.
+#[test]
+fn repeated_wakeup_is_queued_once() {
+    let mut sched = Sim::new();
+    assert!(sched.wake(0x10, 1, WakeupKind::Enqueue));
+    assert!(sched.wake(0x10, 1, WakeupKind::SwitchTo));
+    assert!(sched.wake(0x10, 1, WakeupKind::Enqueue));
+    assert_eq!(sched.pending(1), 1);
+    sched.exit_kernel();
+    assert_eq!(sched.ipis_sent, 1);
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(1), &[0x10]);
+    assert_eq!(sched.switched_to[1], Some(0x10));
+}
+
+/// A thread that blocks again before the IPI of its wakeup arrives stays blocked, the IPI
+/// finds nothing to do.
+#[test]
+fn blocked_before_ipi_stays_blocked() {
+    let mut sched = Sim::new();
+    assert!(sched.wake(0x10, 1, WakeupKind::SwitchTo));
+    assert!(sched.wake(0x11, 1, WakeupKind::Enqueue));
+    sched.exit_kernel();
+    sched.block(1, 0x10);
+    assert_eq!(sched.pending(1), 1);
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(1), &[0x11]);
+    assert_eq!(sched.switched_to[1], None);
+    assert_eq!(sched.ipis_sent, 1);
+}
+
+/// Wakeups sent from several cores to one core all arrive, each sender owes its own IPI.
+#[test]
+fn wakeups_from_several_cores() {
+    let mut sched = Sim::new();
+    for (core, thread) in [(0, 0x10), (1, 0x11), (2, 0x12)] {
+        sched.current_core = core;
+        assert!(sched.wake(thread, 3, WakeupKind::Enqueue));
+        sched.exit_kernel();
+    }
+    assert_eq!(sched.ipis_sent, 3);
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(3), &[0x10, 0x11, 0x12]);
+    only_on(&sched, 3);
+}
+
+/// A full queue refuses the wakeup instead of overwriting another one.
+#[test]
+fn full_queue_refuses_wakeup() {
+    let mut sched = Sim::new();
+    for thread in 0..DEPTH {
+        assert!(sched.wake(0x10 + thread, 1, WakeupKind::Enqueue));
+    }
+    assert!(!sched.wake(0x10 + DEPTH, 1, WakeupKind::Enqueue));
+    assert_eq!(sched.pending(1), DEPTH);
+    sched.exit_kernel();
+    sched.deliver_ipis();
+    assert_eq!(sched.ready(1).len(), DEPTH);
+    assert!(!sched.ready(1).contains(&(0x10 + DEPTH)));
+}